@@ -0,0 +1,74 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use asm::compiler::{Output, OutputFormat, OutputKind, SymbolMap};
+use asm::link::{link, Object, Script};
+
+const USAGE: &str =
+    "Usage: cr8-ld <object>... -o <output> [-T <script>] [-s] [--logisim | --ihex | --srec]";
+
+/// Link objects made with `asm -c` into one binary. Without `-T`, ROM is at the start of
+/// the address space and `#[dyn]`s go in the general purpose RAM up to the stack.
+fn main() -> Result<()> {
+    let mut inputs: Vec<PathBuf> = vec![];
+    let mut output = None;
+    let mut script = None;
+    let mut symbols = false;
+    let mut format = OutputFormat::Default;
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-o" | "--output" => {
+                i += 1;
+                output = args.get(i).cloned();
+            }
+            "-T" | "--script" => {
+                i += 1;
+                script = args.get(i).map(PathBuf::from);
+            }
+            "-s" | "--symbols" => symbols = true,
+            "--logisim" => format = OutputFormat::Logisim,
+            "--ihex" => format = OutputFormat::IntelHex,
+            "--srec" => format = OutputFormat::SRecord,
+            path => inputs.push(PathBuf::from(path)),
+        }
+        i += 1;
+    }
+
+    let (Some(output), false) = (output, inputs.is_empty()) else {
+        bail!(USAGE);
+    };
+
+    let script = match script {
+        Some(path) => {
+            let content =
+                fs::read_to_string(&path).with_context(|| format!("Failed to read {path:?}"))?;
+            Script::parse(&content).with_context(|| format!("Invalid linker script {path:?}"))?
+        }
+        None => Script::default(),
+    };
+    let objects = inputs
+        .iter()
+        .map(|path| Ok((path.to_string_lossy().to_string(), Object::read(path)?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let linked = link(&objects, &script)?;
+
+    let output = Output::new(OutputKind::File(output), format);
+    output.write(&linked.bin)?;
+    if symbols {
+        output.write_symbols(&SymbolMap {
+            files: inputs
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            labels: linked.symbols,
+            ..Default::default()
+        })?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::{env, fs};
+
+use anyhow::{bail, Result};
+use asm::compiler::{read_logisim_hex_file, Compiler, Input, SymbolMap};
+use asm::disasm::{disassemble, Folder};
+
+const USAGE: &str = "Usage: disasm <file> [-s <symbols>] [-m <macros.asm>]... [--native]";
+
+/// `<file>` is a raw binary or a Logisim image. Symbols are read from `<file>.sym.json`
+/// next to it if `-s` isn't given.
+///
+/// Native instructions are folded back into calls of the `core` macros, and of any macros
+/// defined in the files passed with `-m`, unless `--native` is given.
+fn main() -> Result<()> {
+    let mut input: Option<PathBuf> = None;
+    let mut symbols: Option<PathBuf> = None;
+    let mut macros = vec![];
+    let mut native = false;
+
+    let args = std::env::args().collect::<Vec<_>>();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-s" | "--symbols" => {
+                i += 1;
+                symbols = args.get(i).map(PathBuf::from);
+            }
+            "-m" | "--macros" => {
+                i += 1;
+                macros.extend(args.get(i).cloned());
+            }
+            "--native" => native = true,
+            path => input = Some(PathBuf::from(path)),
+        }
+        i += 1;
+    }
+
+    let Some(input) = input else {
+        bail!(USAGE);
+    };
+
+    let content = fs::read(&input)?;
+    let bin = if content.starts_with(b"v3.0 hex") || content.starts_with(b"v2.0 raw") {
+        read_logisim_hex_file(&String::from_utf8(content)?)?
+    } else {
+        content
+    };
+
+    let symbols = match symbols {
+        Some(path) => Some(SymbolMap::read(path)?),
+        None => {
+            let path = input.with_extension("sym.json");
+            path.exists().then(|| SymbolMap::read(path)).transpose()?
+        }
+    };
+
+    let folder = if native {
+        None
+    } else {
+        let mut compiler = Compiler::new();
+        for file in macros {
+            compiler.push(Input::File(file), Arc::new(env::current_dir()?))?;
+        }
+        Some(Folder::new(&compiler))
+    };
+
+    print!("{}", disassemble(&bin, symbols.as_ref(), folder.as_ref()));
+
+    Ok(())
+}
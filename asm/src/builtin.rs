@@ -12,6 +12,7 @@ modules! {
                 clear,
                 jmp,
                 logic,
+                mem,
                 send,
                 math::{add, sub}
             }
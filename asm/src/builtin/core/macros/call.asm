@@ -1,6 +1,6 @@
 #[use(core::macros::jmp)]
 
-#[macro] call: {
+#[pub macro] call: {
     ($addr: expr) => {
         push ($ + 7) >> 8     ; 2 bytes
         push ($ + 5) & 0x00FF ; 2 bytes
@@ -13,7 +13,7 @@
     }
 }
 
-#[macro] ret: {
+#[pub macro] ret: {
     () => {
         pop %x
         pop %y
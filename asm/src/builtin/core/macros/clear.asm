@@ -1,16 +1,16 @@
-#[macro] clrf: {
+#[pub macro] clrf: {
     () => {
         mov %f, 0
     }
 }
 
-#[macro] clrfb: {
+#[pub macro] clrfb: {
     () => {
         and %f, 0b0111
     }
 }
 
-#[macro] clrfc: {
+#[pub macro] clrfc: {
     () => {
         and %f, 0b1011
     }
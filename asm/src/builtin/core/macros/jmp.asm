@@ -1,6 +1,6 @@
 #[use(core::macros::logic)]
 
-#[macro] ldxy: {
+#[pub macro] ldxy: {
     ($addr: expr) => {
         mov %y, $addr.h
         mov %x, $addr.l
@@ -11,7 +11,7 @@
     }
 }
 
-#[macro] jnz: {
+#[pub macro] jnz: {
     ($addr: expr, $ifl: reg, $ifh: reg) => {
         mov %f, $ifl
         or %f, $ifh
@@ -19,7 +19,7 @@
     }
 }
 
-#[macro] jeq: {
+#[pub macro] jeq: {
     ($addr: expr) => {
         and %f, 0b0010
         jnz $addr, %f
@@ -34,7 +34,7 @@
     }
 }
 
-#[macro] jneq: {
+#[pub macro] jneq: {
     ($addr: expr) => {
         not %f
         and %f, 0b0010
@@ -47,21 +47,21 @@
     }
 }
 
-#[macro] jlt: {
+#[pub macro] jlt: {
     ($addr: expr) => {
         and %f, 0b0001
         jnz $addr, %f
     }
 }
 
-#[macro] jle: {
+#[pub macro] jle: {
     ($addr: expr) => {
         and %f, 0b0011
         jnz $addr, %f
     }
 }
 
-#[macro] jgt: {
+#[pub macro] jgt: {
     ($addr: expr) => {
         not %f
         and %f, 0b0001
@@ -69,7 +69,7 @@
     }
 }
 
-#[macro] jge: {
+#[pub macro] jge: {
     ($addr: expr) => {
         nand %f, 0b0001
         and %f, 0b0011
@@ -77,7 +77,7 @@
     }
 }
 
-#[macro] jz: {
+#[pub macro] jz: {
     ($addr: expr, $if: reg) => {
         cmp $if, 0b0010
         jeq $addr
@@ -1,10 +1,10 @@
-#[macro] not: {
+#[pub macro] not: {
     ($lhs: reg) => {
         nor $lhs, $lhs
     }
 }
 
-#[macro] nand: {
+#[pub macro] nand: {
     ($lhs: reg, $rhs: any) => {
         and $lhs, $rhs
         not $lhs
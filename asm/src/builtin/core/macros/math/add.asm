@@ -1,6 +1,6 @@
 #[use(core::macros::clear)]
 
-#[macro] add: {
+#[pub macro] add: {
     ($into: reg, $rhs: any) => {
         clrfc
         adc $into, $rhs
@@ -15,13 +15,13 @@
     }
 }
 
-#[macro] adc: {
+#[pub macro] adc: {
     ($into: reg) => {
         adc $into, 0
     }
 }
 
-#[macro] inc: {
+#[pub macro] inc: {
     ($into: reg) => {
         add $into, 1
     }
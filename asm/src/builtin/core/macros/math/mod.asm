@@ -1,2 +1,2 @@
-#[use(core::macros::math::add)]
-#[use(core::macros::math::sub)]
+#[pub use(core::macros::math::add)]
+#[pub use(core::macros::math::sub)]
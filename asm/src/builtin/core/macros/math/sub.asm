@@ -1,6 +1,6 @@
 #[use(core::macros::clear)]
 
-#[macro] sub: {
+#[pub macro] sub: {
     ($into: reg, $rhs: any) => {
         clrfb
         sbb $into, $rhs
@@ -15,13 +15,13 @@
     }
 }
 
-#[macro] sbb: {
+#[pub macro] sbb: {
     ($into: reg) => {
         sbb $into, 0
     }
 }
 
-#[macro] dec: {
+#[pub macro] dec: {
     ($into: reg) => {
         sub $into, 1
     }
@@ -0,0 +1,70 @@
+#[use(core::macros::math)]
+#[use(core::macros::util)]
+
+; Copy `$len` bytes (at least 1) from `$src` to `$dst`.
+; Overwrites %a, %b, %c, %d, %x, %y, %z and %f
+#[pub macro] memcpy: {
+    ($dst: expr, $src: expr, $len: expr) => {
+        mov %a, %b, $src
+        mov %c, %d, $dst
+    copy:
+        mov %x, %y, %a, %b
+        lw %z
+        mov %x, %y, %c, %d
+        sw %z
+        inc %a, %b
+        inc %c, %d
+        mov %x, %y, %c, %d
+        sub %x, %y, $dst + $len
+        jnz copy, %x, %y
+    }
+}
+
+; Set `$len` bytes (at least 1) from `$dst` to `$val`.
+; Overwrites %c, %d, %x, %y, %z and %f
+#[pub macro] memset: {
+    ($dst: expr, $val: any, $len: expr) => {
+        mov %z, $val
+        mov %c, %d, $dst
+    fill:
+        mov %x, %y, %c, %d
+        sw %z
+        inc %c, %d
+        mov %x, %y, %c, %d
+        sub %x, %y, $dst + $len
+        jnz fill, %x, %y
+    }
+}
+
+; Select bank `$b`, and keep it in KREG so that far calls select it again once they return.
+; Use it rather than `bank` for banks that are still needed after a `call`.
+; Overwrites %f if `$b` isn't a register
+#[pub macro] setbank: {
+    ($b: reg) => {
+        sw KREG, $b
+        bank $b
+    }
+    ($b: expr) => {
+        mov %f, $b
+        setbank %f
+    }
+}
+
+; Select the bank `$addr` is in, then load the byte at `$addr` into `$r`.
+; Leaves the bank selected
+#[pub macro] lwb: {
+    ($r: reg, $addr: expr) => {
+        mov $r, bank($addr)
+        setbank $r
+        lw $r, $addr
+    }
+}
+
+; Select the bank `$addr` is in, then store `$r` at `$addr`.
+; Leaves the bank selected. Overwrites %f, so `$r` can't be it
+#[pub macro] swb: {
+    ($addr: expr, $r: reg) => {
+        setbank bank($addr)
+        sw $addr, $r
+    }
+}
@@ -1,7 +1,8 @@
-#[use(core::macros::math)]
-#[use(core::macros::call)]
-#[use(core::macros::clear)]
-#[use(core::macros::jmp)]
-#[use(core::macros::logic)]
-#[use(core::macros::send)]
-#[use(core::macros::util)]
+#[pub use(core::macros::math)]
+#[pub use(core::macros::call)]
+#[pub use(core::macros::clear)]
+#[pub use(core::macros::jmp)]
+#[pub use(core::macros::logic)]
+#[pub use(core::macros::mem)]
+#[pub use(core::macros::send)]
+#[pub use(core::macros::util)]
@@ -1,31 +1,31 @@
 #[use(core::sys)]
 
-#[macro] send: {
+#[pub macro] send: {
     ($port: expr, $b: expr) => {
         mov %f, $b.l
         out $port.l, %f
     }
 }
 
-#[macro] halt: {
+#[pub macro] halt: {
     () => {
         send CTRL, SIGHALT
     }
 }
 
-#[macro] ping: {
+#[pub macro] ping: {
     () => {
         send CTRL, SIGPING
     }
 }
 
-#[macro] brkpt: {
+#[pub macro] brkpt: {
     () => {
         send CTRL, SIGBRKPT
     }
 }
 
-#[macro] dbg: {
+#[pub macro] dbg: {
     () => {
         push %f
         send CTRL, SIGDBG
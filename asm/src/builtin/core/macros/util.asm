@@ -1,4 +1,4 @@
-#[macro] mov: {
+#[pub macro] mov: {
     ($inlo: reg, $inhi: reg, $frlo: any, $frhi: any) => {
         mov $inlo, $frlo
         mov $inhi, $frhi
@@ -9,7 +9,7 @@
     }
 }
 
-#[macro] sw: {
+#[pub macro] sw: {
     ($to: expr, $b: lit) => {
         mov %f, $b
         sw $to, %f
@@ -25,7 +25,7 @@
     }
 }
 
-#[macro] lw: {
+#[pub macro] lw: {
     ($a: reg, $b: reg) => {
         lw $a
         inc %x, %y
@@ -34,27 +34,27 @@
 }
 
 ; do nothing for 1 tick
-#[macro] nop: {
+#[pub macro] nop: {
     () => {
       mov %a, %a ; 1 byte
     }
 }
 
-#[macro] push: {
+#[pub macro] push: {
     ($l: any, $h: any) => {
         push $l
         push $h
     }
 }
 
-#[macro] pushx: {
+#[pub macro] pushx: {
     ($a: expr) => {
         push $a.l
         push $a.h
     }
 }
 
-#[macro] pop: {
+#[pub macro] pop: {
     ($l: reg, $h:  reg) => {
         pop $h
         pop $l
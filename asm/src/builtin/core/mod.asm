@@ -1,2 +1,2 @@
-#[use(core::sys)]
-#[use(core::macros)]
+#[pub use(core::sys)]
+#[pub use(core::macros)]
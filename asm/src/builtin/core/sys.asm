@@ -1,31 +1,35 @@
-#[static(ROM: 0x0000)]
-#[static(BRAM: 0x8000)]
+#[pub static(ROM: 0x0000)]
+#[pub static(BRAM: 0x8000)]
 #[dyn(&0xC000)]
-#[static(GPRAM: 0xC000)]
-#[static(STACK: 0xFC00)]
-#[static(STACK_END: 0xFEFF)]
+#[pub static(GPRAM: 0xC000)]
+#[pub static(STACK: 0xFC00)]
+#[pub static(STACK_END: 0xFEFF)]
 
 
 ; Psuedo Register addresses
 ; Used for temporary data
-#[static(PSR0: 0xFF00)]
-#[static(PSR1: 0xFF01)]
-#[static(PSR2: 0xFF02)]
-#[static(PSR3: 0xFF03)]
-#[static(PSR4: 0xFF04)]
-#[static(PSR5: 0xFF05)]
-#[static(PSR6: 0xFF06)]
-#[static(PSR7: 0xFF07)]
-#[static(PSR8: 0xFF08)]
-#[static(PSR9: 0xFF09)]
+#[pub static(PSR0: 0xFF00)]
+#[pub static(PSR1: 0xFF01)]
+#[pub static(PSR2: 0xFF02)]
+#[pub static(PSR3: 0xFF03)]
+#[pub static(PSR4: 0xFF04)]
+#[pub static(PSR5: 0xFF05)]
+#[pub static(PSR6: 0xFF06)]
+#[pub static(PSR7: 0xFF07)]
+#[pub static(PSR8: 0xFF08)]
+#[pub static(PSR9: 0xFF09)]
 
-#[static(CTRL: 0x00)]
-#[static(SIGPING: 0x00)]
-#[static(SIGHALT: 0x01)]
-#[static(SIGDBG: 0x02)]
-#[static(SIGBRKPT: 0x03)]
+; Shadow of the bank register %k, which can't be read. `setbank`, `lwb` and `swb` keep it
+; up to date, and far calls to code in a #[bank] select the bank it holds again once they return
+#[pub static(KREG: 0xFFFF)]
 
-#[static(KB: 0x01)]
+#[pub static(CTRL: 0x00)]
+#[pub static(SIGPING: 0x00)]
+#[pub static(SIGHALT: 0x01)]
+#[pub static(SIGDBG: 0x02)]
+#[pub static(SIGBRKPT: 0x03)]
 
-#[static(RNG: 0x02)]
+#[pub static(KB: 0x01)]
+
+#[pub static(RNG: 0x02)]
 
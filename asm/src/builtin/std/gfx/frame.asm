@@ -1,6 +1,6 @@
 ; CALLER MUST SET mb 1
 ; Draws bytes from ROM to VRAM
-#[macro] clrvram: {
+#[pub macro] clrvram: {
     ($from: expr, $to: expr) => {
         mov %a, $from.l
         mov %b, $from.h
@@ -29,6 +29,7 @@ _clrvram:
 ; ab: Frame address
 ; cd: Write location
 ; [PSR0][PSR1]: Frame length
+#[pub]
 frmwof:
     .loop:
         mov %x, %a
@@ -1,7 +1,8 @@
 #[use(std::gfx::grid::cfg)]
+#[use(std::gfx::grid::point)]
 
 ; Draws a box at the address: [%ab]
-#[macro] raw_block: {
+#[pub macro] raw_block: {
     ($color: any) => {
         ldxy %a, %b
         sw $color
@@ -41,7 +42,7 @@
     }
 }
 
-#[macro] block: {
+#[pub macro] block: {
     ($color: any) => {
         call point_addr
         raw_block $color
@@ -1,3 +1,3 @@
-#[static(BLOCK_HEIGHT: 8)]
-#[static(SCREEN_WIDTH: 32)] ; bytes -- 256 bits (px)
+#[pub static(BLOCK_HEIGHT: 8)]
+#[pub static(SCREEN_WIDTH: 32)] ; bytes -- 256 bits (px)
 
@@ -1,2 +1,2 @@
-#[use(std::gfx::grid::block)]
-#[use(std::gfx::grid::point)]
+#[pub use(std::gfx::grid::block)]
+#[pub use(std::gfx::grid::point)]
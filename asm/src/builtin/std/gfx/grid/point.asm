@@ -7,9 +7,11 @@
 ; Args:
 ;   - %a:  x-value (0-31)
 ;   - %b:  y-value (0-31)
+#[pub]
 point_addr:
-    add %a, %a
-    add %a, %a
+    #[repeat(2, $i)] {
+        add %a, %a
+    }
 
     add %b, %b
 
@@ -1,2 +1,2 @@
-#[use(std::gfx::grid)]
-#[use(std::gfx::frame)]
+#[pub use(std::gfx::grid)]
+#[pub use(std::gfx::frame)]
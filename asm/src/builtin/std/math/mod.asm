@@ -1,2 +1,2 @@
-#[use(std::math::mul)]
-#[use(std::math::shift)]
+#[pub use(std::math::mul)]
+#[pub use(std::math::shift)]
@@ -1,2 +1,2 @@
-#[use(std::math::mul::mul)]
-#[use(std::math::mul::mul16)]
+#[pub use(std::math::mul::mul)]
+#[pub use(std::math::mul::mul16)]
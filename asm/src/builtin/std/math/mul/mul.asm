@@ -1,4 +1,5 @@
 ; Multiply %a * %b -> %zd
+#[pub]
 mul:
     mov %z, 0
     jnz .loop, %a
@@ -13,6 +14,7 @@ mul:
 
 ; Multiply %a * %b -> %ab
 ; In-place
+#[pub]
 mulip:
     mov %c, %b
     mov %z, %a
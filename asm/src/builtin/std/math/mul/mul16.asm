@@ -14,6 +14,7 @@
 ;            [ad] [ad]
 ;            [bc] [bc]
 ;                 [bd] [bd]
+#[pub]
 mul16:
     dbg
     push %d
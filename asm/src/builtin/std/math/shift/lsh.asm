@@ -1,5 +1,6 @@
 ; Logical Left Shift
 ; Side effects: %z, %b
+#[pub]
 lsh:
     mov %z, %a
     jnz .loop, %b
@@ -13,6 +14,7 @@ lsh:
 
 ; Algorithmic Left Shift
 ; Side effects: %z, %b, %d
+#[pub]
 lsa:
     mov %d, %a
     and %d, 0b10000000
@@ -22,6 +24,7 @@ lsa:
 
 ; Rotate left
 ; Side effects: %z, %b
+#[pub]
 lrt:
     mov %z, %a
     jnz .loop, %b
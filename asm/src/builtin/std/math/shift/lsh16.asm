@@ -1,6 +1,7 @@
 ; Logical Left Shift
 ; ab << c  -> ab
 ; Side effects: %a, %b, %c
+#[pub]
 lsh16:
     jnz .loop, %c
     ret
@@ -14,6 +15,7 @@ lsh16:
 
 ; Algorithmic Left Shift
 ; Side effects: %a, %b, %c, %d
+#[pub]
 lsa16:
     mov %d, %b
     call lsh16
@@ -1,3 +1,3 @@
-#[use(std::math::shift::lsh)]
-#[use(std::math::shift::lsh16)]
-#[use(std::math::shift::rsh)]
+#[pub use(std::math::shift::lsh)]
+#[pub use(std::math::shift::lsh16)]
+#[pub use(std::math::shift::rsh)]
@@ -2,6 +2,7 @@
 
 ; Rotate Right
 ; Side effects: %z, %b, %c
+#[pub]
 rrt:
     ; Left rotate %a (8 - %b) times
     mov %c, 8
@@ -14,6 +15,7 @@ rrt:
 
 ; Logical Right Shift
 ; Side effects: %z, %b, %c, %d
+#[pub]
 rsh:
     mov %d, 0
     mov %c, %b
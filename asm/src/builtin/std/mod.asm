@@ -1,3 +1,3 @@
-#[use(std::math)]
-#[use(std::gfx)]
-#[use(std::sleep)]
+#[pub use(std::math)]
+#[pub use(std::gfx)]
+#[pub use(std::sleep)]
@@ -25,6 +25,7 @@
 ;     nearest millisecond. The web simulator is fairly weird, and the speed
 ;     of the sleep tends to be 2x what it would be calculated to be -- what 
 ;     should take 1s takes 2s.
+#[pub]
 sleep:
     .loop: ; 52 bytes / iteration
         dec %a
@@ -0,0 +1,130 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Result;
+use indexmap::IndexMap;
+
+use super::{Compiler, Diagnostic, Input, Level, SearchPath, SourceProvider, SymbolMap};
+use crate::link::Object;
+
+/// Sets up a [Compiler] to embed it: where it reads files from, how it looks for them and
+/// what to compile. Nothing is logged or written.
+///
+/// ```
+/// use asm::compiler::{Compiler, Input, MemoryFiles};
+///
+/// let files = MemoryFiles::new()
+///     .with("main.asm", "#[use(\"lib\")]\ncall lib")
+///     .with("lib.asm", "#[pub]\nlib:\n    ret");
+/// let build = Compiler::builder()
+///     .sources(files)
+///     .input(Input::File("main.asm".to_string()))
+///     .compile()
+///     .unwrap();
+/// assert_eq!(build.symbols.labels["lib::lib"], 0);
+/// ```
+#[derive(Debug, Default)]
+pub struct Builder {
+    sources: Option<Box<dyn SourceProvider>>,
+    search: SearchPath,
+    defines: Vec<(String, usize)>,
+    truncation: Level,
+    inputs: Vec<Input>,
+    object: bool,
+    optimize: bool,
+}
+
+/// What [Builder::compile] makes of a program.
+#[derive(Debug)]
+pub struct Build {
+    pub bin: Vec<u8>,
+    /// The contents of the ROM banks code is placed in with `#[bank]`.
+    pub banks: IndexMap<usize, Vec<u8>>,
+    pub symbols: SymbolMap,
+    /// Everything reported that wasn't an error, like truncated immediates at
+    /// [Level::Warning].
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Compiler {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+}
+
+impl Builder {
+    /// Read files from `sources` instead of the filesystem.
+    pub fn sources(mut self, sources: impl SourceProvider + 'static) -> Self {
+        self.sources = Some(Box::new(sources));
+        self
+    }
+
+    pub fn search_path(mut self, search: SearchPath) -> Self {
+        self.search = search;
+        self
+    }
+
+    /// See [Compiler::define].
+    pub fn define(mut self, name: impl Into<String>, value: usize) -> Self {
+        self.defines.push((name.into(), value));
+        self
+    }
+
+    /// See [Compiler::set_truncation_level].
+    pub fn truncation(mut self, level: Level) -> Self {
+        self.truncation = level;
+        self
+    }
+
+    /// See [Compiler::optimize].
+    pub fn optimize(mut self) -> Self {
+        self.optimize = true;
+        self
+    }
+
+    /// Compile `input` too, after the ones before it.
+    pub fn input(mut self, input: Input) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    /// The compiler with every input pushed, ready to [Compiler::compile].
+    pub fn build(self) -> Result<Compiler> {
+        let mut compiler = Compiler::new();
+        if let Some(sources) = self.sources {
+            compiler.sources = sources;
+        }
+        compiler.set_search_path(self.search);
+        compiler.set_truncation_level(self.truncation);
+        if self.object {
+            compiler.set_object_mode();
+        }
+        if self.optimize {
+            compiler.optimize();
+        }
+        for (name, value) in self.defines {
+            compiler.define(name, value)?;
+        }
+        for input in self.inputs {
+            compiler.push(input, Arc::new(PathBuf::new()))?;
+        }
+        Ok(compiler)
+    }
+
+    pub fn compile(self) -> Result<Build> {
+        let mut compiler = self.build()?;
+        compiler.compile()?;
+        Ok(Build {
+            symbols: compiler.symbols(),
+            diagnostics: std::mem::take(&mut compiler.diagnostics),
+            bin: compiler.bin,
+            banks: compiler.rom_banks,
+        })
+    }
+
+    /// Compile the inputs into an [Object] of their own, see [Compiler::set_object_mode].
+    pub fn compile_object(mut self) -> Result<Object> {
+        self.object = true;
+        self.build()?.compile_object()
+    }
+}
@@ -1,23 +1,52 @@
 use anyhow::{anyhow, bail, Result};
+use indexmap::IndexMap;
 use path_clean::clean;
+use serde::Deserialize;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::{
+    env,
     fs::{self, OpenOptions},
     str::FromStr,
 };
 
 use crate::builtin::BUILTIN;
 
-use super::logisim_hex_file;
+use super::lex::{expect_complete, Lexable};
+use super::{intel_hex_file, logisim_hex_file, srecord_file, Manifest, SourceProvider, SymbolMap};
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub input: Input,
-    pub output: Output,
+    /// Where to write the binary, in as many formats as there are outputs.
+    pub outputs: Vec<Output>,
+    /// Where `#[use("...")]` looks for files.
+    pub search: SearchPath,
     pub micro: bool,
     pub debug: bool,
+    pub symbols: bool,
+    pub listing: bool,
+    /// Write an object to link with `cr8-ld` (`-c`), rather than a binary.
+    pub object: bool,
+    /// Only warn about immediates that don't fit, instead of failing.
+    pub warn_truncation: bool,
+    /// Run the peephole optimizer (`-O`).
+    pub optimize: bool,
+    /// Print what takes up the ROM and RAM (`--size`).
+    pub size: bool,
+    /// `#[static]`s set with `-D NAME=VALUE`.
+    pub defines: Vec<(String, usize)>,
+}
+
+/// Where `#[use("...")]` looks for files, besides next to the file that uses them and
+/// `$PWD`.
+#[derive(Debug, Clone, Default)]
+pub struct SearchPath {
+    /// Directories from `-I` and the manifest's `include`, searched in order.
+    pub include: Vec<PathBuf>,
+    /// The manifest's `[dependencies]`: `#[use("NAME/...")]` looks in their directory.
+    pub libraries: IndexMap<String, PathBuf>,
 }
 
 #[derive(Debug, Clone)]
@@ -34,24 +63,48 @@ pub enum OutputKind {
     None,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     #[default]
+    #[serde(rename = "bin")]
     Default,
     Logisim,
+    #[serde(rename = "ihex")]
+    IntelHex,
+    #[serde(rename = "srec")]
+    SRecord,
 }
 
+/// Bytes per data record of [OutputFormat::IntelHex] and [OutputFormat::SRecord] if not
+/// set with `--record-len`.
+pub const DEFAULT_RECORD_LEN: usize = 16;
+
 #[derive(Debug, Clone, Default)]
 pub struct Output {
-    kind: OutputKind,
-    format: OutputFormat,
+    pub(crate) kind: OutputKind,
+    pub(crate) format: OutputFormat,
+    pub(crate) record_len: Option<usize>,
+    /// Added to every address written by the hex formats.
+    pub(crate) base: usize,
 }
 
 impl Output {
+    pub fn new(kind: OutputKind, format: OutputFormat) -> Self {
+        Self {
+            kind,
+            format,
+            ..Default::default()
+        }
+    }
+
     pub fn write(&self, bin: &[u8]) -> Result<()> {
         match &self.kind {
             OutputKind::None => Ok(()),
             OutputKind::File(f) => {
+                if let Some(dir) = Path::new(f).parent() {
+                    fs::create_dir_all(dir)?;
+                }
                 let mut options = OpenOptions::new();
                 let mut file = options
                     .write(true)
@@ -60,21 +113,67 @@ impl Output {
                     .create(true)
                     .open(f)?;
 
-                match self.format {
-                    OutputFormat::Default => {
-                        file.write_all(bin)?;
-                        Ok(())
-                    }
-                    OutputFormat::Logisim => {
-                        logisim_hex_file(bin, 16, &mut file)?;
+                self.encode(bin, &mut file)
+            }
+        }
+    }
 
-                        Ok(())
-                    }
+    /// Write `bin` to `file` in the configured [OutputFormat].
+    pub fn encode<W: Write + Sync>(&self, bin: &[u8], file: &mut W) -> Result<()> {
+        let record_len = self.record_len.unwrap_or(DEFAULT_RECORD_LEN);
+
+        match self.format {
+            OutputFormat::Default => {
+                file.write_all(bin)?;
+                Ok(())
+            }
+            OutputFormat::Logisim => logisim_hex_file(bin, 16, file),
+            OutputFormat::IntelHex => intel_hex_file(bin, record_len, self.base, file),
+            OutputFormat::SRecord => srecord_file(bin, record_len, self.base, file),
+        }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    /// Write `symbols` as JSON next to the output file (`out.bin` -> `out.sym.json`).
+    pub fn write_symbols(&self, symbols: &SymbolMap) -> Result<()> {
+        match &self.kind {
+            OutputKind::None => Ok(()),
+            OutputKind::File(_) => symbols.write(self.path()?.with_extension("sym.json")),
+        }
+    }
+
+    /// Write the ROM bank `bank` next to the output file (`out.bin` -> `out.bank2.bin`), in
+    /// the same format.
+    pub fn write_bank(&self, bank: usize, bin: &[u8]) -> Result<()> {
+        match &self.kind {
+            OutputKind::None => Ok(()),
+            OutputKind::File(_) => {
+                let path = self.path()?;
+                let extension = match path.extension() {
+                    Some(ext) => format!("bank{bank}.{}", ext.to_string_lossy()),
+                    None => format!("bank{bank}"),
+                };
+                let path = path.with_extension(extension);
+                Output {
+                    kind: OutputKind::File(path.to_string_lossy().to_string()),
+                    ..self.clone()
                 }
+                .write(bin)
             }
         }
     }
 
+    /// Write `listing` next to the output file (`out.bin` -> `out.lst`).
+    pub fn write_listing(&self, listing: &str) -> Result<()> {
+        match &self.kind {
+            OutputKind::None => Ok(()),
+            OutputKind::File(_) => Ok(fs::write(self.path()?.with_extension("lst"), listing)?),
+        }
+    }
+
     pub fn path(&self) -> Result<PathBuf> {
         match &self.kind {
             OutputKind::None => Err(anyhow!("No specified output")),
@@ -84,14 +183,37 @@ impl Output {
 }
 
 impl Config {
-    pub fn from_argv() -> Self {
-        let mut input: Option<Input> = None;
-        let mut output = Output {
-            kind: OutputKind::None,
-            format: OutputFormat::Default,
+    /// Read the flags, or the `cr8.toml` manifest with `asm build [DIR]`. Flags given along
+    /// with `build` add to what the manifest says, and `-o` replaces its outputs.
+    pub fn from_argv() -> Result<Self> {
+        let manifest = match std::env::args().nth(1).as_deref() {
+            Some("build") => {
+                let dir = match std::env::args().nth(2).filter(|arg| !arg.starts_with('-')) {
+                    Some(dir) => PathBuf::from(dir),
+                    None => env::current_dir()?,
+                };
+                Some(Manifest::find(&dir)?)
+            }
+            _ => None,
         };
+
+        let mut input: Option<Input> = manifest
+            .as_ref()
+            .map(|m| Input::File(m.entry.to_string_lossy().to_string()));
+        let mut output = Output::default();
         let mut micro = false;
         let mut debug = false;
+        let mut object = false;
+        let mut symbols = manifest.as_ref().is_some_and(|m| m.symbols);
+        let mut listing = manifest.as_ref().is_some_and(|m| m.listing);
+        let mut warn_truncation = manifest.as_ref().is_some_and(|m| m.warn_truncation);
+        let mut optimize = manifest.as_ref().is_some_and(|m| m.optimize);
+        let mut size = false;
+        let mut defines: Vec<(String, usize)> = manifest
+            .as_ref()
+            .map(|m| m.defines.clone().into_iter().collect())
+            .unwrap_or_default();
+        let mut search = manifest.as_ref().map(Manifest::search).unwrap_or_default();
 
         for (i, arg) in std::env::args().enumerate() {
             match arg.as_str() {
@@ -114,9 +236,25 @@ impl Config {
                 "--logisim" => {
                     output.format = OutputFormat::Logisim;
                 }
+                "--ihex" => output.format = OutputFormat::IntelHex,
+                "--srec" => output.format = OutputFormat::SRecord,
+                "--record-len" => {
+                    output.record_len = Some(number_arg(i + 1, &arg)?);
+                }
+                "--base" => output.base = number_arg(i + 1, &arg)?,
                 "-d" | "--debug" => {
                     debug = true;
                 }
+                "-s" | "--symbols" => symbols = true,
+                "-l" | "--listing" => listing = true,
+                "-c" | "--object" => object = true,
+                "--warn-truncation" => warn_truncation = true,
+                "-O" | "--optimize" => optimize = true,
+                "--size" => size = true,
+                "-D" | "--define" => defines.push(define_arg(i + 1)?),
+                "-I" | "--include" => search
+                    .include
+                    .push(std::env::args().nth(i + 1).unwrap_or_default().into()),
                 "--micro" => micro = true,
                 _ => {}
             }
@@ -126,20 +264,70 @@ impl Config {
         }
         let input = input.unwrap();
 
-        Self {
+        let outputs = match (manifest, &output.kind) {
+            (Some(manifest), OutputKind::None) => manifest.outputs,
+            _ => vec![output],
+        };
+
+        Ok(Self {
             input,
-            output,
+            outputs,
+            search,
             micro,
             debug,
-        }
+            symbols,
+            listing,
+            object,
+            warn_truncation,
+            optimize,
+            size,
+            defines,
+        })
     }
 }
 
+/// Parse the argument at `i` as a number (`16`, `0x8000`, `0b1010`).
+fn number_arg(i: usize, flag: &str) -> Result<usize> {
+    let arg = std::env::args().nth(i).unwrap_or_default();
+    match usize::lex(&arg).and_then(|(n, rest)| expect_complete(rest).map(|_| n)) {
+        Ok(n) => Ok(n),
+        Err(_) => bail!("Expected a number after {flag}, found {arg:#?}"),
+    }
+}
+
+/// Parse the argument at `i` as `NAME=VALUE`, or `NAME` to define it as 1.
+fn define_arg(i: usize) -> Result<(String, usize)> {
+    let arg = std::env::args().nth(i).unwrap_or_default();
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (arg.as_str(), None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        bail!("Expected NAME=VALUE after -D, found {arg:#?}");
+    }
+
+    let value = match value {
+        Some(value) => {
+            match usize::lex(value).and_then(|(n, rest)| expect_complete(rest).map(|_| n)) {
+                Ok(n) => n,
+                Err(_) => bail!("Expected a number for -D {name}, found {value:#?}"),
+            }
+        }
+        None => 1,
+    };
+    Ok((name.to_string(), value))
+}
+
 impl Input {
+    /// The contents of the input, and the path they were found at. Files are read from
+    /// `provider`, and looked for in a library named by their first component, next to
+    /// `from`, in the include directories of `search` and in `$PWD`, in that order.
     pub fn source(
         self,
         from: Option<&PathBuf>,
         visited: Option<&Vec<Arc<PathBuf>>>,
+        search: &SearchPath,
+        provider: &dyn SourceProvider,
     ) -> Result<(Option<String>, PathBuf)> {
         match self {
             Input::File(path) => {
@@ -162,32 +350,37 @@ impl Input {
                     }
                 } else {
                     let pb = pb.to_path_buf();
-                    let real = if pb.exists() && pb.is_file() {
+                    let real = if provider.is_file(&pb) {
                         pb.to_path_buf()
                     } else {
-                        let possibilities = match from {
-                            Some(f) => vec![
-                                f.parent().unwrap_or(f).join(&pb),
-                                f.parent().unwrap_or(f).join(&pb).with_extension("asm"),
-                                f.parent().unwrap_or(f).join(&pb).join("mod.asm"),
-                                f.parent().unwrap_or(f).join(&pb).join("main.asm"),
-                                pb.with_extension("asm"),
-                                pb.join("main.asm"),
-                                pb.join("mod.asm"),
-                                pb,
-                            ],
-                            None => vec![
-                                pb.with_extension("asm"),
-                                pb.join("main.asm"),
-                                pb.join("mod.asm"),
-                                pb,
-                            ],
-                        };
+                        let mut possibilities = vec![];
+                        let mut parts = pb.components();
+                        let library = parts.next().and_then(|first| {
+                            search.libraries.get(&*first.as_os_str().to_string_lossy())
+                        });
+                        if let Some(library) = library {
+                            possibilities.extend(match parts.as_path() {
+                                rest if rest.as_os_str().is_empty() => candidates(library.clone()),
+                                rest => candidates(library.join(rest)),
+                            });
+                        }
+                        if let Some(f) = from {
+                            possibilities.extend(candidates(f.parent().unwrap_or(f).join(&pb)));
+                        }
+                        for dir in &search.include {
+                            possibilities.extend(candidates(dir.join(&pb)));
+                        }
+                        possibilities.extend([
+                            pb.with_extension("asm"),
+                            pb.join("main.asm"),
+                            pb.join("mod.asm"),
+                            pb,
+                        ]);
 
                         let mut found = None;
 
                         for possible in possibilities.iter() {
-                            if possible.exists() && possible.is_file() {
+                            if provider.is_file(possible) {
                                 found = Some(possible.to_owned());
                                 break;
                             }
@@ -205,7 +398,7 @@ impl Input {
                         }
                     };
 
-                    match fs::read_to_string(&real) {
+                    match provider.read_to_string(&real) {
                         Ok(file) => Ok((Some(file), real.to_path_buf())),
                         Err(_) => bail!("Failed to read {real:?}"),
                     }
@@ -216,3 +409,13 @@ impl Input {
         }
     }
 }
+
+/// The files `#[use("path")]` can mean, relative to one directory.
+fn candidates(path: PathBuf) -> [PathBuf; 4] {
+    [
+        path.clone(),
+        path.with_extension("asm"),
+        path.join("mod.asm"),
+        path.join("main.asm"),
+    ]
+}
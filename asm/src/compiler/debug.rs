@@ -36,7 +36,7 @@ impl Compiler {
     fn debug_vars(&self) {
         debug!("======= Variables: ========");
         for (k, v) in self.ram_locations.iter() {
-            debug!("  - {}: {:#06X}", k, *v);
+            debug!("  - {}: {:#06X} ({} bytes)", k, v.address, v.size);
         }
         debug!("");
     }
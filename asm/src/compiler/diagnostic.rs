@@ -0,0 +1,197 @@
+use std::fmt::Display;
+
+use crate::compiler::lex::{Instruction, LexError, SourceFile, Span};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Level {
+    #[default]
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Note => "note",
+        })
+    }
+}
+
+/// A message about the program being compiled, pointing at where in the source it was caused.
+///
+/// Renders like rustc:
+///
+/// ```text
+/// error: Unknown variable: "lop"
+///  --> main.asm:4:5
+///   |
+/// 4 |     jnz lop, %a
+///   |     ^^^^^^^^^^^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+    pub children: Vec<Diagnostic>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl Display, span: Option<Span>) -> Self {
+        Self {
+            level,
+            message: message.to_string(),
+            span,
+            children: vec![],
+        }
+    }
+
+    pub fn error(message: impl Display, span: Option<Span>) -> Self {
+        Self::new(Level::Error, message, span)
+    }
+
+    pub fn warning(message: impl Display, span: Option<Span>) -> Self {
+        Self::new(Level::Warning, message, span)
+    }
+
+    pub fn note(message: impl Display, span: Option<Span>) -> Self {
+        Self::new(Level::Note, message, span)
+    }
+
+    pub fn with(mut self, child: Diagnostic) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Point at `inst` and note every macro call it was expanded from.
+    pub fn at_instruction(level: Level, message: impl Display, inst: &Instruction) -> Self {
+        let mut diagnostic = Self::new(level, message, Some(inst.span.clone()));
+        for expansion in inst.expanded_from.iter().rev() {
+            diagnostic = diagnostic.with(Diagnostic::note(
+                format!("in this expansion of macro {:#?}", expansion.id),
+                Some(expansion.span.clone()),
+            ));
+        }
+        diagnostic
+    }
+
+    /// Create an error from a failure to lex the item at the start of `buf`. Points to
+    /// where the lexer gave up if it knows, otherwise at the start of the item.
+    pub fn from_lex_error(
+        err: &anyhow::Error,
+        file: &std::sync::Arc<SourceFile>,
+        buf: &str,
+    ) -> Self {
+        let span = match err.downcast_ref::<LexError>() {
+            Some(e) => e.span(file),
+            None => file.span(buf, buf.find('\n').unwrap_or(buf.len())),
+        };
+        Self::error(format!("{err:#}"), Some(span))
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Level::Error
+    }
+
+    fn render(&self, f: &mut std::fmt::Formatter<'_>, gutter: usize) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.level, self.message)?;
+
+        let Some(span) = &self.span else {
+            return Ok(());
+        };
+
+        let pad = " ".repeat(gutter);
+        let line = span.source_line();
+        let col = span.col - 1;
+        let indent = line
+            .chars()
+            .take(col)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+        let underline = span
+            .text()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .count()
+            .max(1);
+
+        writeln!(f, "{pad}--> {span}")?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{:>gutter$} | {line}", span.line)?;
+        writeln!(f, "{pad} | {indent}{}", "^".repeat(underline))
+    }
+
+    fn gutter(&self) -> usize {
+        self.children
+            .iter()
+            .map(Diagnostic::gutter)
+            .chain(self.span.iter().map(|s| s.line.to_string().len()))
+            .max()
+            .unwrap_or(1)
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let gutter = self.gutter();
+        self.render(f, gutter)?;
+        for child in self.children.iter() {
+            child.render(f, gutter)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Every error that stopped a compilation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics(pub Vec<Diagnostic>);
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for diagnostic in self.0.iter() {
+            writeln!(f, "{diagnostic}")?;
+        }
+        let errors = self.0.iter().filter(|d| d.is_error()).count();
+        match errors {
+            1 => write!(f, "aborting due to previous error"),
+            n => write!(f, "aborting due to {n} previous errors"),
+        }
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn render() {
+        let file = Arc::new(SourceFile::new(
+            Arc::new(PathBuf::from("main.asm")),
+            "main:\n    jnz lop, %a\n".to_string(),
+        ));
+        let span = file.span(&file.content[10..], 11);
+        let diagnostic = Diagnostic::error("Unknown variable: \"lop\"", Some(span));
+
+        assert_eq!(
+            diagnostic.to_string(),
+            r#"error: Unknown variable: "lop"
+ --> main.asm:2:5
+  |
+2 |     jnz lop, %a
+  |     ^^^^^^^^^^^
+"#
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::io::Write;
+
+use anyhow::{bail, Result};
+
+/// Intel HEX, as read by most EEPROM programmers.
+///
+/// ```text
+/// :10000000280300...
+/// :00000001FF
+/// ```
+///
+/// `base` is added to every address. Extended linear address records are written whenever
+/// the data crosses into the next 64K segment.
+pub fn intel_hex_file<W: Write>(
+    bin: &[u8],
+    record_len: usize,
+    base: usize,
+    file: &mut W,
+) -> Result<()> {
+    if !(1..=0xFF).contains(&record_len) {
+        bail!("Invalid Intel HEX record length: {record_len} (expected 1..=255)");
+    }
+    if base + bin.len() > 0x1_0000_0000 {
+        bail!("Intel HEX can not address {:#X}", base + bin.len() - 1);
+    }
+
+    let mut segment = 0;
+    let mut offset = 0;
+
+    while offset < bin.len() {
+        let addr = base + offset;
+        if addr >> 16 != segment {
+            segment = addr >> 16;
+            intel_hex_record(file, 0x04, 0, &(segment as u16).to_be_bytes())?;
+        }
+
+        // Records can't wrap around the end of a segment
+        let len = record_len
+            .min(bin.len() - offset)
+            .min(0x1_0000 - (addr & 0xFFFF));
+        intel_hex_record(file, 0x00, addr as u16, &bin[offset..offset + len])?;
+        offset += len;
+    }
+
+    intel_hex_record(file, 0x01, 0, &[])
+}
+
+fn intel_hex_record<W: Write>(file: &mut W, kind: u8, addr: u16, data: &[u8]) -> Result<()> {
+    let [hi, lo] = addr.to_be_bytes();
+    let header = [data.len() as u8, hi, lo, kind];
+    let sum = header
+        .iter()
+        .chain(data)
+        .fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+    file.write_all(b":")?;
+    for b in header.iter().chain(data) {
+        write!(file, "{b:02X}")?;
+    }
+    writeln!(file, "{:02X}", sum.wrapping_neg())?;
+    Ok(())
+}
+
+/// Motorola S-record. Uses 16, 24 or 32 bit addresses (S1/S2/S3) depending on the highest
+/// address written, and ends with a record count and a terminator pointing at `base`.
+///
+/// ```text
+/// S00600004844521B
+/// S1130000280300...
+/// S5030001FB
+/// S9030000FC
+/// ```
+pub fn srecord_file<W: Write>(
+    bin: &[u8],
+    record_len: usize,
+    base: usize,
+    file: &mut W,
+) -> Result<()> {
+    let end = base + bin.len().max(1) - 1;
+    let (data, term, addr_len) = match end {
+        0..=0xFFFF => (b'1', b'9', 2),
+        0x1_0000..=0xFF_FFFF => (b'2', b'8', 3),
+        0x100_0000..=0xFFFF_FFFF => (b'3', b'7', 4),
+        _ => bail!("S-record can not address {end:#X}"),
+    };
+
+    let max = 0xFF - addr_len - 1;
+    if !(1..=max).contains(&record_len) {
+        bail!("Invalid S-record record length: {record_len} (expected 1..={max})");
+    }
+
+    srecord(file, b'0', 0, 2, b"HDR")?;
+
+    let mut records = 0;
+    for (i, chunk) in bin.chunks(record_len).enumerate() {
+        srecord(file, data, base + i * record_len, addr_len, chunk)?;
+        records += 1;
+    }
+
+    // S5 for a 16 bit count, S6 for 24 bits, omitted if there are even more records
+    match records {
+        0..=0xFFFF => srecord(file, b'5', records, 2, &[])?,
+        0x1_0000..=0xFF_FFFF => srecord(file, b'6', records, 3, &[])?,
+        _ => {}
+    }
+
+    srecord(file, term, base, addr_len, &[])
+}
+
+fn srecord<W: Write>(
+    file: &mut W,
+    kind: u8,
+    addr: usize,
+    addr_len: usize,
+    data: &[u8],
+) -> Result<()> {
+    let addr = &(addr as u32).to_be_bytes()[4 - addr_len..];
+    let count = (addr_len + data.len() + 1) as u8;
+    let sum = addr
+        .iter()
+        .chain(data)
+        .fold(count, |sum, b| sum.wrapping_add(*b));
+
+    write!(file, "S{}{count:02X}", kind as char)?;
+    for b in addr.iter().chain(data) {
+        write!(file, "{b:02X}")?;
+    }
+    writeln!(file, "{:02X}", !sum)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn intel_hex(bin: &[u8], record_len: usize, base: usize) -> String {
+        let mut out = vec![];
+        intel_hex_file(bin, record_len, base, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn srec(bin: &[u8], record_len: usize, base: usize) -> String {
+        let mut out = vec![];
+        srecord_file(bin, record_len, base, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn intel_hex_records() {
+        assert_eq!(
+            intel_hex(&[0x28, 0x03, 0x00, 0x0E, 0x00], 3, 0),
+            ":03000000280300D2\n:020003000E00ED\n:00000001FF\n"
+        );
+
+        // Splits records at the segment boundary
+        assert_eq!(
+            intel_hex(&[1, 2, 3, 4], 16, 0xFFFE),
+            ":02FFFE000102FE\n:020000040001F9\n:020000000304F7\n:00000001FF\n"
+        );
+
+        assert!(intel_hex_file(&[], 0, 0, &mut vec![]).is_err());
+    }
+
+    #[test]
+    fn srecord_records() {
+        assert_eq!(
+            srec(&[0x28, 0x03, 0x00, 0x0E, 0x00], 3, 0),
+            "S00600004844521B\nS1060000280300CE\nS10500030E00E9\nS5030002FA\nS9030000FC\n"
+        );
+
+        assert_eq!(
+            srec(&[0xAA], 16, 0x10000),
+            "S00600004844521B\nS205010000AA4F\nS5030001FB\nS804010000FA\n"
+        );
+
+        assert!(srecord_file(&[], 253, 0, &mut vec![]).is_err());
+    }
+}
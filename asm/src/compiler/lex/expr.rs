@@ -1,16 +1,23 @@
+use std::fmt::Display;
 use std::num::Wrapping;
 
 use anyhow::{bail, Result};
 
 use crate::compiler::Compiler;
-use crate::{lex_enum, token};
+use crate::{lex_enum, path};
 
-use super::lexable::{expect, ignore_whitespace, LexResult, Lexable};
+use super::lexable::{
+    expect, ignore_whitespace, ignore_whitespace_noline, lex_char, LexResult, Lexable,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
     Literal(usize),
     Variable(String),
+    Unary {
+        op: UnaryOperation,
+        expr: Box<Expr>,
+    },
     Expr {
         lhs: Box<Expr>,
         op: ExprOperation,
@@ -20,6 +27,12 @@ pub enum Expr {
 
 impl Expr {
     pub fn resolve(&self, ctx: &Compiler) -> Result<usize> {
+        self.resolve_with(ctx, &mut vec![])
+    }
+
+    /// `statics` are the expression-valued `#[static]`s being resolved, to catch circular
+    /// definitions.
+    fn resolve_with(&self, ctx: &Compiler, statics: &mut Vec<String>) -> Result<usize> {
         match self {
             Self::Literal(lit) => Ok(*lit),
             Self::Variable(var) => Ok(if var.as_str() == "$" {
@@ -28,73 +41,253 @@ impl Expr {
                 *label
             } else if let Some(stat) = ctx.statics.get(var) {
                 *stat
+            } else if let Some((expr, span)) = ctx.static_exprs.get(var) {
+                let circular = statics.contains(var);
+                statics.push(var.to_string());
+                if circular {
+                    bail!(
+                        "#[static] {var} is defined in terms of itself: {}",
+                        statics.join(" -> ")
+                    );
+                }
+                // As written in the module it was defined in
+                let value = ctx
+                    .qualify_expr(expr, ctx.module_of(span))?
+                    .resolve_with(ctx, statics)?;
+                statics.pop();
+                value
             } else if let Some(label) = ctx.labels.get(&format!("{}{var}", &ctx.last_label)) {
                 *label
             } else if let Some(d) = ctx.ram_locations.get(var) {
-                *d
+                d.address
             } else {
                 bail!("Unknown variable: {var:#?}");
             }),
-            Self::Expr { lhs, op, rhs } => Ok(op.apply(lhs.resolve(ctx)?, rhs.resolve(ctx)?)?),
+            Self::Unary {
+                op: UnaryOperation::Bank,
+                expr,
+            } => expr.bank(ctx),
+            Self::Unary { op, expr } => Ok(op.apply(expr.resolve_with(ctx, statics)?)),
+            Self::Expr { lhs, op, rhs } => Ok(op.apply(
+                lhs.resolve_with(ctx, statics)?,
+                rhs.resolve_with(ctx, statics)?,
+            )?),
+        }
+    }
+}
+
+impl Expr {
+    /// The bank of the `#[dyn]`s this refers to, 0 if they aren't in a banked region, or of
+    /// the labels placed with `#[bank]`.
+    fn bank(&self, ctx: &Compiler) -> Result<usize> {
+        let mut banks = vec![];
+        self.visit(&mut |var| {
+            if let Some(var) = ctx.ram_locations.get(var) {
+                banks.push(var.bank.unwrap_or_default());
+            } else if let Some(bank) = ctx.label_banks.get(var) {
+                banks.push(*bank);
+            }
+        });
+        match banks[..] {
+            [] => bail!("bank({self}) does not refer to a #[dyn] or a label in a #[bank]"),
+            [bank, ref rest @ ..] if rest.iter().all(|b| *b == bank) => Ok(bank),
+            _ => bail!("bank({self}) refers to #[dyn]s in different banks"),
+        }
+    }
+
+    /// Call `f` with every variable.
+    pub(crate) fn visit(&self, f: &mut impl FnMut(&str)) {
+        match self {
+            Self::Variable(var) => f(var),
+            Self::Literal(_) => {}
+            Self::Unary { expr, .. } => expr.visit(f),
+            Self::Expr { lhs, rhs, .. } => {
+                lhs.visit(f);
+                rhs.visit(f);
+            }
+        }
+    }
+
+    /// Replace every variable that `with` returns an expression for.
+    pub fn replace(&self, with: &impl Fn(&str) -> Option<Expr>) -> Self {
+        match self {
+            Self::Variable(var) => with(var).unwrap_or_else(|| self.clone()),
+            Self::Literal(_) => self.clone(),
+            Self::Unary { op, expr } => Self::Unary {
+                op: *op,
+                expr: Box::new(expr.replace(with)),
+            },
+            Self::Expr { lhs, op, rhs } => Self::Expr {
+                lhs: Box::new(lhs.replace(with)),
+                op: *op,
+                rhs: Box::new(rhs.replace(with)),
+            },
         }
     }
+
+    /// Like [Expr::replace], failing with the first error of `with`.
+    pub fn try_replace(&self, with: &impl Fn(&str) -> Result<Option<Expr>>) -> Result<Self> {
+        Ok(match self {
+            Self::Variable(var) => with(var)?.unwrap_or_else(|| self.clone()),
+            Self::Literal(_) => self.clone(),
+            Self::Unary { op, expr } => Self::Unary {
+                op: *op,
+                expr: Box::new(expr.try_replace(with)?),
+            },
+            Self::Expr { lhs, op, rhs } => Self::Expr {
+                lhs: Box::new(lhs.try_replace(with)?),
+                op: *op,
+                rhs: Box::new(rhs.try_replace(with)?),
+            },
+        })
+    }
 }
 
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let wrap = |e: &Expr| match e {
+            Self::Expr { .. } => format!("({e})"),
+            _ => e.to_string(),
+        };
+
+        match self {
+            Self::Literal(lit) if *lit > 0xFF => write!(f, "{lit:#06X}"),
+            Self::Literal(lit) => write!(f, "{lit}"),
+            Self::Variable(var) => f.write_str(var),
+            Self::Unary {
+                op: op @ (UnaryOperation::Lo | UnaryOperation::Hi | UnaryOperation::Bank),
+                expr,
+            } => write!(f, "{op}({expr})"),
+            Self::Unary { op, expr } => write!(f, "{op}{}", wrap(expr)),
+            Self::Expr { lhs, op, rhs } => write!(f, "{} {op} {}", wrap(lhs), wrap(rhs)),
+        }
+    }
+}
+
+/// A literal, variable, parenthesized expression, `lo(..)`/`hi(..)`/`bank(..)` call or any of those
+/// behind unary operators.
 fn lex_expr_lhs(buf: &str) -> LexResult<'_, Expr> {
     let buf = ignore_whitespace(buf);
 
+    if let Ok((op, buf)) = lex_enum! { buf;
+        "-" => UnaryOperation::Neg,
+        "~" => UnaryOperation::Not,
+        "!" => UnaryOperation::LogicalNot,
+    } {
+        let (expr, buf) = lex_expr_lhs(buf)?;
+        return Ok((op.to_expr(expr), buf));
+    }
+
     if let Ok(buf) = expect(buf, "(") {
-        let buf = ignore_whitespace(buf);
-        let (ex, buf) = Expr::lex(buf)?;
+        let (ex, buf) = lex_expr(buf, 0)?;
         let buf = ignore_whitespace(buf);
         let buf = expect(buf, ")")?;
         return Ok((ex, buf));
     }
 
     if let Ok((lhs, buf)) = usize::lex(buf) {
-        Ok((Expr::Literal(lhs), buf))
-    } else {
-        let (lhs, buf) = token!(buf; '_' | '$' | '.')?;
-        Ok((Expr::Variable(lhs.to_string()), buf))
+        return Ok((Expr::Literal(lhs), buf));
     }
-}
 
-fn lex_expr(buf: &str) -> LexResult<'_, Expr> {
-    let (lhs, buf) = lex_expr_lhs(buf)?;
-    let buf = ignore_whitespace(buf);
+    if buf.starts_with('\'') {
+        let (ch, buf) = lex_char(buf)?;
+        return Ok((Expr::Literal(ch as usize), buf));
+    }
+
+    let (lhs, buf) = path!(buf; '_' | '$' | '.')?;
 
-    if let Ok((op, buf)) = ExprOperation::lex(buf) {
+    let func = match lhs {
+        "lo" => Some(UnaryOperation::Lo),
+        "hi" => Some(UnaryOperation::Hi),
+        "bank" => Some(UnaryOperation::Bank),
+        _ => None,
+    };
+    if let (Some(op), Ok(buf)) = (func, expect(ignore_whitespace_noline(buf), "(")) {
+        let (expr, buf) = lex_expr(buf, 0)?;
         let buf = ignore_whitespace(buf);
-        if op == ExprOperation::Mul || op == ExprOperation::Div {
-            let (rhs, buf) = lex_expr_lhs(buf)?;
-            let buf = ignore_whitespace(buf);
+        let buf = expect(buf, ")")?;
+        return Ok((op.to_expr(expr), buf));
+    }
 
-            let lhs = op.to_expr(lhs, rhs);
+    Ok((Expr::Variable(lhs.to_string()), buf))
+}
 
-            if let Ok((next_op, buf)) = ExprOperation::lex(buf) {
-                let buf = ignore_whitespace(buf);
+/// Precedence climbing: keep taking operators that bind at least as tightly as
+/// `min_precedence`. The right hand side only takes operators that bind tighter, which makes
+/// every operator left associative.
+fn lex_expr(buf: &str, min_precedence: u8) -> LexResult<'_, Expr> {
+    let (mut lhs, mut buf) = lex_expr_lhs(buf)?;
 
-                let (rhs, buf) = Expr::lex(buf)?;
+    loop {
+        let rest = ignore_whitespace(buf);
+        let Ok((op, rest)) = ExprOperation::lex(rest) else {
+            break;
+        };
+        if op.precedence() < min_precedence {
+            break;
+        }
 
-                Ok((next_op.to_expr(lhs, rhs), buf))
-            } else {
-                Ok((lhs, buf))
-            }
-        } else {
-            let buf = ignore_whitespace(buf);
+        let (rhs, rest) = lex_expr(rest, op.precedence() + 1)?;
+        lhs = op.to_expr(lhs, rhs);
+        buf = rest;
+    }
+
+    Ok((lhs, buf))
+}
+
+impl<'b> Lexable<'b> for Expr {
+    fn lex(buf: &'b str) -> LexResult<'b, Expr> {
+        lex_expr(buf, 0)
+    }
+}
 
-            let (rhs, buf) = Expr::lex(buf)?;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperation {
+    /// `-a`, two's complement
+    Neg,
+    /// `~a`, bitwise not
+    Not,
+    /// `!a`, 1 if `a` is 0, otherwise 0
+    LogicalNot,
+    /// `lo(a)`, the low byte of `a`
+    Lo,
+    /// `hi(a)`, the high byte of a 16 bit `a`
+    Hi,
+    /// `bank(a)`, the bank of the `#[dyn]` in `a`. It depends on the variable rather than
+    /// its address, so [Expr::resolve] handles it instead of [UnaryOperation::apply].
+    Bank,
+}
+
+impl UnaryOperation {
+    pub fn to_expr(&self, expr: Expr) -> Expr {
+        Expr::Unary {
+            op: *self,
+            expr: Box::new(expr),
+        }
+    }
 
-            Ok((op.to_expr(lhs, rhs), buf))
+    pub fn apply(self, val: usize) -> usize {
+        match self {
+            Self::Neg => val.wrapping_neg(),
+            Self::Not => !val,
+            Self::LogicalNot => (val == 0) as usize,
+            Self::Lo => val & 0xFF,
+            Self::Hi => (val >> 8) & 0xFF,
+            Self::Bank => unreachable!("bank() is resolved from its variables"),
         }
-    } else {
-        Ok((lhs, buf))
     }
 }
 
-impl<'b> Lexable<'b> for Expr {
-    fn lex(buf: &'b str) -> LexResult<'b, Expr> {
-        lex_expr(buf)
+impl Display for UnaryOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Neg => "-",
+            Self::Not => "~",
+            Self::LogicalNot => "!",
+            Self::Lo => "lo",
+            Self::Hi => "hi",
+            Self::Bank => "bank",
+        })
     }
 }
 
@@ -104,11 +297,20 @@ pub enum ExprOperation {
     Sub,
     Mul,
     Div,
+    Mod,
     And,
     Xor,
     Or,
     Rsh,
     Lsh,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    LogicalAnd,
+    LogicalOr,
 }
 
 impl ExprOperation {
@@ -120,33 +322,97 @@ impl ExprOperation {
         }
     }
 
-    pub fn apply(self, lhs: usize, rhs: usize) -> Result<usize> {
+    /// How tightly the operator binds, same as in C.
+    pub fn precedence(&self) -> u8 {
         match self {
-            Self::Add => Ok((Wrapping(lhs) + Wrapping(rhs)).0),
-            Self::Sub => Ok((Wrapping(lhs) - Wrapping(rhs)).0),
-            Self::Mul => Ok((Wrapping(lhs) * Wrapping(rhs)).0),
-            Self::Div => Ok((Wrapping(lhs) / Wrapping(rhs)).0),
-            Self::And => Ok((Wrapping(lhs) & Wrapping(rhs)).0),
-            Self::Xor => Ok((Wrapping(lhs) ^ Wrapping(rhs)).0),
-            Self::Or => Ok((Wrapping(lhs) | Wrapping(rhs)).0),
-            Self::Rsh => Ok((Wrapping(lhs) >> rhs).0),
-            Self::Lsh => Ok((Wrapping(lhs) << rhs).0),
+            Self::LogicalOr => 1,
+            Self::LogicalAnd => 2,
+            Self::Or => 3,
+            Self::Xor => 4,
+            Self::And => 5,
+            Self::Eq | Self::Ne => 6,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 7,
+            Self::Lsh | Self::Rsh => 8,
+            Self::Add | Self::Sub => 9,
+            Self::Mul | Self::Div | Self::Mod => 10,
+        }
+    }
+
+    pub fn apply(self, lhs: usize, rhs: usize) -> Result<usize> {
+        if matches!(self, Self::Div | Self::Mod) && rhs == 0 {
+            bail!("Attempted to divide {lhs} by zero");
         }
+
+        Ok(match self {
+            Self::Add => (Wrapping(lhs) + Wrapping(rhs)).0,
+            Self::Sub => (Wrapping(lhs) - Wrapping(rhs)).0,
+            Self::Mul => (Wrapping(lhs) * Wrapping(rhs)).0,
+            Self::Div => lhs / rhs,
+            Self::Mod => lhs % rhs,
+            Self::And => lhs & rhs,
+            Self::Xor => lhs ^ rhs,
+            Self::Or => lhs | rhs,
+            Self::Rsh => lhs.checked_shr(rhs as u32).unwrap_or(0),
+            Self::Lsh => lhs.checked_shl(rhs as u32).unwrap_or(0),
+            Self::Eq => (lhs == rhs) as usize,
+            Self::Ne => (lhs != rhs) as usize,
+            Self::Lt => (lhs < rhs) as usize,
+            Self::Le => (lhs <= rhs) as usize,
+            Self::Gt => (lhs > rhs) as usize,
+            Self::Ge => (lhs >= rhs) as usize,
+            Self::LogicalAnd => (lhs != 0 && rhs != 0) as usize,
+            Self::LogicalOr => (lhs != 0 || rhs != 0) as usize,
+        })
+    }
+}
+
+impl Display for ExprOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::And => "&",
+            Self::Xor => "^",
+            Self::Or => "|",
+            Self::Rsh => ">>",
+            Self::Lsh => "<<",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::LogicalAnd => "&&",
+            Self::LogicalOr => "||",
+        })
     }
 }
 
 impl<'b> Lexable<'b> for ExprOperation {
     fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        // Longer operators first so `<<` isn't read as `<`
         lex_enum! { buf;
+            "&&" => Self::LogicalAnd,
+            "||" => Self::LogicalOr,
+            "==" => Self::Eq,
+            "!=" => Self::Ne,
+            "<=" => Self::Le,
+            ">=" => Self::Ge,
+            ">>" => Self::Rsh,
+            "<<" => Self::Lsh,
+            "<" => Self::Lt,
+            ">" => Self::Gt,
             "*" => Self::Mul,
             "+" => Self::Add,
             "-" => Self::Sub,
             "/" => Self::Div,
+            "%" => Self::Mod,
             "&" => Self::And,
             "^" => Self::Xor,
             "|" => Self::Or,
-            ">>" => Self::Rsh,
-            "<<" => Self::Lsh,
         }
         .map_err(|e| e.context("Unknown Operator"))
     }
@@ -156,6 +422,13 @@ impl<'b> Lexable<'b> for ExprOperation {
 mod test {
     use super::*;
 
+    fn eval(expr: &str) -> usize {
+        let ctx = Compiler::default();
+        let (expr, rest) = Expr::lex(expr).unwrap();
+        assert_eq!(rest.trim(), "", "unparsed input");
+        expr.resolve(&ctx).unwrap()
+    }
+
     #[test]
     fn lex_expression() -> Result<(), Box<dyn std::error::Error>> {
         let ctx = Compiler::default();
@@ -175,7 +448,88 @@ mod test {
         let (expr, _) = Expr::lex("1 + (0b01 + 2) * 3")?;
         let res = expr.resolve(&ctx).unwrap();
         assert_eq!(res, 1 + (0b01 + 2) * 3);
+        assert_eq!(expr.to_string(), "1 + ((1 + 2) * 3)");
 
         Ok(())
     }
+
+    #[test]
+    fn operations() {
+        assert_eq!(eval("7 + 3"), 10);
+        assert_eq!(eval("7 - 3"), 4);
+        assert_eq!(eval("7 * 3"), 21);
+        assert_eq!(eval("7 / 3"), 2);
+        assert_eq!(eval("7 % 3"), 1);
+        assert_eq!(eval("0b1100 & 0b1010"), 0b1000);
+        assert_eq!(eval("0b1100 ^ 0b1010"), 0b0110);
+        assert_eq!(eval("0b1100 | 0b1010"), 0b1110);
+        assert_eq!(eval("0xC000 >> 8"), 0xC0);
+        assert_eq!(eval("1 << 4"), 16);
+        assert_eq!(eval("3 - 5"), 3usize.wrapping_sub(5));
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eq!(eval("10 - 2 - 3"), 5);
+        assert_eq!(eval("100 / 10 / 5"), 2);
+        assert_eq!(eval("17 % 10 % 4"), 3);
+        assert_eq!(eval("1 << 2 << 3"), 32);
+        assert_eq!(eval("256 >> 2 >> 3"), 8);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4"), 14);
+        assert_eq!(eval("2 * 3 + 4 * 5"), 26);
+        assert_eq!(eval("1 + 2 << 2 & 0xFF"), 12);
+        assert_eq!(eval("0x12 | 0x0F & 0xF0 ^ 0x01"), 0x13);
+        assert_eq!(eval("1 + 1 == 2"), 1);
+        assert_eq!(eval("1 < 2 == 2 < 1"), 0);
+        assert_eq!(eval("0 || 1 && 0"), 0);
+        assert_eq!(eval("(2 + 3) * 4"), 20);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(eval("3 == 3"), 1);
+        assert_eq!(eval("3 != 3"), 0);
+        assert_eq!(eval("2 < 3"), 1);
+        assert_eq!(eval("3 <= 3"), 1);
+        assert_eq!(eval("2 > 3"), 0);
+        assert_eq!(eval("2 >= 3"), 0);
+        assert_eq!(eval("2 && 3"), 1);
+        assert_eq!(eval("0 && 3"), 0);
+        assert_eq!(eval("0 || 3"), 1);
+        assert_eq!(eval("0 || 0"), 0);
+    }
+
+    #[test]
+    fn unary() {
+        assert_eq!(eval("-1") & 0xFF, 0xFF);
+        assert_eq!(eval("10 + -2"), 8);
+        assert_eq!(eval("-(2 + 3) + 10"), 5);
+        assert_eq!(eval("~0 & 0xFF"), 0xFF);
+        assert_eq!(eval("~0x0F & 0xFF"), 0xF0);
+        assert_eq!(eval("!0"), 1);
+        assert_eq!(eval("!5"), 0);
+        assert_eq!(eval("!!5"), 1);
+        assert_eq!(eval("1 - -1"), 2);
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(eval("lo(0xC012)"), 0x12);
+        assert_eq!(eval("hi(0xC012)"), 0xC0);
+        assert_eq!(eval("hi(0xC000 + 0x100) + 1"), 0xC2);
+        assert_eq!(eval("lo (0x1234)"), 0x34);
+
+        let (expr, _) = Expr::lex("hi(0xC000 + $) | -x").unwrap();
+        assert_eq!(expr.to_string(), "hi(0xC000 + $) | -x");
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        let (expr, _) = Expr::lex("1 / (2 - 2)").unwrap();
+        assert!(expr.resolve(&Compiler::default()).is_err());
+    }
 }
@@ -1,76 +1,54 @@
-use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::compiler::lex::{lexable::*, Instruction, Meta, Node, Value};
-use crate::token;
+use crate::compiler::lex::{lexable::*, Instruction, Meta, Node, SourceFile, Span};
+use crate::{path, token};
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub enum ItemInner {
     Meta(Meta),
     Node(Node),
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Item {
     pub item: ItemInner,
-    pub file: Arc<PathBuf>,
+    pub span: Span,
 }
 
-impl<'b> LexableWith<'b, Arc<PathBuf>> for Item {
-    fn lex_with(buf: &'b str, file: Arc<PathBuf>) -> LexResult<'b, Self> {
-        let (item, buf) = ItemInner::lex(buf)?;
-        Ok((Self { item, file }, buf))
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for Item {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
+        let (item, rest) = ItemInner::lex_with(buf, file)?;
+        let span = file.span_between(buf, rest);
+        Ok((Self { item, span }, rest))
     }
 }
 
-impl<'b> Lexable<'b> for ItemInner {
-    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for ItemInner {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
         if buf.starts_with('#') {
-            let (dir, buf) = Meta::lex(buf)?;
+            let (dir, buf) = Meta::lex_with(buf, file)?;
             return Ok((Self::Meta(dir), buf));
         }
 
+        let start = buf;
+
         if expect(buf, ".").is_ok() {
             let (label, buf) = token!(buf; '_' |'.')?;
+            let span = file.span(start, label.len());
             let buf = ignore_whitespace(buf);
             let buf = expect(buf, ":")?;
-            return Ok((Self::Node(Node::Label(label.to_string())), buf));
+            return Ok((Self::Node(Node::Label(label.to_string(), span)), buf));
         }
 
-        let (id, buf) = token!(buf; '_')?;
+        let (id, buf) = path!(buf; '_')?;
 
         let buf = ignore_whitespace_noline(buf);
-        if let Ok(buf) = expect(buf, ":") {
-            return Ok((Self::Node(Node::Label(id.to_string())), buf));
-        }
-
-        if buf.is_empty() {
-            return Ok((
-                Self::Node(Node::Instruction(Instruction {
-                    id: id.to_string(),
-                    args: vec![],
-                })),
-                buf,
-            ));
-        }
-
-        if let Ok(buf) = expect(buf, "\n") {
-            return Ok((
-                Self::Node(Node::Instruction(Instruction {
-                    id: id.to_string(),
-                    args: vec![],
-                })),
-                buf,
-            ));
+        if let (false, Ok(buf)) = (id.contains("::"), expect(buf, ":")) {
+            let span = file.span(start, id.len());
+            return Ok((Self::Node(Node::Label(id.to_string(), span)), buf));
         }
 
-        let (args, buf) = Vec::<Value>::lex(buf)?;
-        Ok((
-            Self::Node(Node::Instruction(Instruction {
-                id: id.to_string(),
-                args,
-            })),
-            buf,
-        ))
+        let (inst, buf) = Instruction::lex_with(start, file)?;
+        Ok((Self::Node(Node::Instruction(inst)), buf))
     }
 }
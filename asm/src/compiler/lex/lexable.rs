@@ -1,10 +1,53 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
 use crate::op::Operation;
 use crate::reg::Register;
 
-use anyhow::{bail, Result};
+use anyhow::Result;
+
+use super::span::{SourceFile, Span};
 
 pub type LexResult<'b, T> = Result<(T, &'b str)>;
 
+/// Error raised while lexing that remembers where in the buffer it happened, so it can be
+/// reported with a [Span] once the [SourceFile] is known.
+#[derive(Debug)]
+pub struct LexError {
+    ptr: usize,
+    pub len: usize,
+    pub msg: String,
+}
+
+impl LexError {
+    pub fn span(&self, file: &Arc<SourceFile>) -> Span {
+        file.span_at(file.offset_of_ptr(self.ptr), self.len)
+    }
+}
+
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Create a [LexError] pointing at the first word of `buf`.
+pub fn error_at(buf: &str, msg: impl Display) -> anyhow::Error {
+    let len = buf
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .next()
+        .map(str::len)
+        .unwrap_or_default()
+        .max(1);
+    anyhow::Error::new(LexError {
+        ptr: buf.as_ptr() as usize,
+        len,
+        msg: msg.to_string(),
+    })
+}
+
 pub trait Lexable<'b>: Sized {
     fn lex(buf: &'b str) -> LexResult<'b, Self>;
 }
@@ -26,6 +69,13 @@ macro_rules! lex_enum {
     }
 }
 
+#[macro_export]
+macro_rules! bail_at {
+    ($buf:expr, $($arg:tt)*) => {
+        return Err($crate::compiler::lex::lexable::error_at($buf, format!($($arg)*)))
+    };
+}
+
 #[macro_export]
 macro_rules! token {
     ($buf:ident $(; $ch:literal $(| $oth:literal)*)?) => {
@@ -33,6 +83,14 @@ macro_rules! token {
     }
 }
 
+/// Like [token], along with any `::name` after it, as in `std::math::mul`.
+#[macro_export]
+macro_rules! path {
+    ($buf:ident $(; $ch:literal $(| $oth:literal)*)?) => {
+        $crate::compiler::lex::lexable::collect_path($buf, |c| c.is_alphanumeric() $( || c == $ch  $(|| c == $oth  )*  )?  )
+    }
+}
+
 #[macro_export]
 macro_rules! surround_inline {
     ($start:literal $buf:ident $end:literal $inner:block ) => {{
@@ -81,7 +139,7 @@ macro_rules! repeated {
                 $buf = b;
                 break $buf;
             }
-            bail!("Expected {} or {}\n\n{}", $delimeter, $end, $buf);
+            $crate::bail_at!($buf, "Expected {:#?} or {:#?}", $delimeter, $end);
         };
         (_items, $buf)
     }};
@@ -110,21 +168,46 @@ pub fn ignore_whitespace_noline(buf: &str) -> &str {
     buf.trim_start_matches([' ', '\t'])
 }
 
+/// Skip past an item that failed to lex `at` bytes into `buf`, so that lexing can resume at the
+/// next item. Any `{ ... }` block the item opened is skipped as a whole.
+pub fn recover(buf: &str, at: usize) -> &str {
+    let mut depth = 0;
+    let mut comment = false;
+    let mut string = false;
+
+    for (i, ch) in buf.char_indices() {
+        match ch {
+            '\n' => {
+                comment = false;
+                string = false;
+                if i >= at && depth <= 0 {
+                    return &buf[i + 1..];
+                }
+            }
+            _ if comment => {}
+            '"' => string = !string,
+            _ if string => {}
+            ';' => comment = true,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+
+    ""
+}
+
 pub fn collect<M: Fn(char) -> bool>(buf: &str, check: M) -> LexResult<'_, &str> {
-    for (i, ch) in buf.chars().enumerate() {
+    for (i, ch) in buf.char_indices() {
         if check(ch) {
-            let remaining = &buf[i..];
             if i == 0 {
-                bail!(
+                bail_at!(
+                    buf,
                     "Unexpected {:#?}",
                     buf.split_ascii_whitespace().next().unwrap_or_default()
                 );
             }
-            return if remaining.is_empty() {
-                bail!("Unexpected end of input");
-            } else {
-                Ok(buf.split_at(i))
-            };
+            return Ok(buf.split_at(i));
         }
     }
 
@@ -139,10 +222,19 @@ pub fn collect_while<M: Fn(char) -> bool>(buf: &str, check: M) -> LexResult<'_,
     collect(buf, |ch| !check(ch))
 }
 
+/// Collect what `check` accepts, then again after every `::` that is followed by more of it.
+pub fn collect_path<M: Fn(char) -> bool + Copy>(buf: &str, check: M) -> LexResult<'_, &str> {
+    let (_, mut rest) = collect_while(buf, check)?;
+    while let Some(Ok((_, after))) = rest.strip_prefix("::").map(|b| collect_while(b, check)) {
+        rest = after;
+    }
+    Ok(buf.split_at(buf.len() - rest.len()))
+}
+
 pub fn expect_complete(buf: &str) -> LexResult<'_, ()> {
     let buf = ignore_whitespace(buf);
     if !buf.is_empty() {
-        bail!("Unexpected {:#?}", buf);
+        bail_at!(buf, "Unexpected {:#?}", buf);
     }
     Ok(((), buf))
 }
@@ -151,7 +243,8 @@ pub fn expect<'b>(buf: &'b str, expect: &'static str) -> Result<&'b str> {
     if let Some(buf) = buf.strip_prefix(expect) {
         Ok(buf)
     } else {
-        bail!(
+        bail_at!(
+            buf,
             "Expected {expect:#?}, got {:#?}",
             buf.split_ascii_whitespace().next().unwrap_or_default()
         );
@@ -167,7 +260,8 @@ pub fn expect_enum<'b, E: Clone + Copy>(
             return Ok((*variant, buf));
         }
     }
-    bail!(
+    bail_at!(
+        buf,
         "Expected one of {:#?}, got {:#?}",
         variants
             .iter()
@@ -208,6 +302,7 @@ impl<'b, T: Lexable<'b>> Lexable<'b> for Vec<T> {
 
 impl<'b> Lexable<'b> for usize {
     fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        let start = buf;
         let (radix, buf) = if let Ok(buf) = expect(buf, "0x") {
             (16, buf)
         } else if let Ok(buf) = expect(buf, "0b") {
@@ -220,18 +315,75 @@ impl<'b> Lexable<'b> for usize {
         let num = &num.replace('_', "");
         match usize::from_str_radix(num, radix) {
             Ok(val) => Ok((val, buf)),
-            Err(_) => bail!("Failed to parse number {num:#?}"),
+            Err(_) => bail_at!(start, "Failed to parse number {num:#?}"),
         }
     }
 }
 
+/// `"text"`, as bytes. See [lex_char] for escapes.
+pub fn lex_string(buf: &str) -> LexResult<'_, Vec<u8>> {
+    let start = buf;
+    let mut buf = expect(buf, "\"")?;
+    let mut bytes = vec![];
+    loop {
+        if let Ok(buf) = expect(buf, "\"") {
+            return Ok((bytes, buf));
+        }
+        if buf.is_empty() || buf.starts_with('\n') {
+            bail_at!(start, "Unterminated string");
+        }
+        let (mut ch, rest) = lex_escaped(buf)?;
+        bytes.append(&mut ch);
+        buf = rest;
+    }
+}
+
+/// `'c'`, as the byte it encodes to. Escapes are `\n`, `\r`, `\t`, `\0`, `\\`, `\'`, `\"`
+/// and `\xNN` for any byte.
+pub fn lex_char(buf: &str) -> LexResult<'_, u8> {
+    let start = buf;
+    let buf = expect(buf, "'")?;
+    let (ch, buf) = lex_escaped(buf)?;
+    let buf = expect(buf, "'")?;
+    match ch[..] {
+        [byte] => Ok((byte, buf)),
+        _ => bail_at!(start, "Character literal is more than one byte"),
+    }
+}
+
+/// One character of a string or character literal, as UTF-8.
+fn lex_escaped(buf: &str) -> LexResult<'_, Vec<u8>> {
+    let Some(ch) = buf.chars().next() else {
+        bail_at!(buf, "Expected a character");
+    };
+    let rest = &buf[ch.len_utf8()..];
+    if ch != '\\' {
+        return Ok((ch.to_string().into_bytes(), rest));
+    }
+
+    let byte = match rest.chars().next() {
+        Some('n') => b'\n',
+        Some('r') => b'\r',
+        Some('t') => b'\t',
+        Some('0') => 0,
+        Some(ch @ ('\\' | '\'' | '"')) => ch as u8,
+        Some('x') => match rest.get(1..3).map(|hex| u8::from_str_radix(hex, 16)) {
+            Some(Ok(byte)) => return Ok((vec![byte], &rest[3..])),
+            _ => bail_at!(buf, "Expected two hex digits after \\x"),
+        },
+        _ => bail_at!(buf, "Unknown escape"),
+    };
+    Ok((vec![byte], &rest[1..]))
+}
+
 impl<'b> Lexable<'b> for Register {
     fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        let start = buf;
         let buf = expect(buf, "%")?;
         let (reg, buf) = collect_while(buf, |c| c.is_alphabetic())?;
         let reg = match Register::try_from(reg) {
             Ok(r) => r,
-            Err(()) => bail!("Unknown register {reg:#?} at {buf:#?}"),
+            Err(()) => bail_at!(start, "Unknown register {reg:#?}"),
         };
         Ok((reg, buf))
     }
@@ -239,10 +391,11 @@ impl<'b> Lexable<'b> for Register {
 
 impl<'b> Lexable<'b> for Operation {
     fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        let start = buf;
         let (op, buf) = collect_while(buf, |c| c.is_alphabetic())?;
         let op = match Operation::try_from(op) {
             Ok(o) => o,
-            Err(()) => bail!("Unknown operation {op:#?} at {buf:#?}"),
+            Err(()) => bail_at!(start, "Unknown operation {op:#?}"),
         };
         Ok((op, buf))
     }
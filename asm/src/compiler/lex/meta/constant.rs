@@ -0,0 +1,212 @@
+use anyhow::Result;
+
+use crate::compiler::lex::lexable::*;
+use crate::compiler::lex::Expr;
+use crate::compiler::Compiler;
+use crate::op::Overflow;
+use crate::{bail_at, repeated};
+
+use super::{lex_repeat_header, Repeat};
+
+/// The data of a `#[const]`, with its `#[repeat]`s expanded. Expressions are evaluated once
+/// labels are known, see [Constant::compile].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Constant(pub Vec<Data>);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Data {
+    Byte(Expr),
+    /// Little endian.
+    Word(Expr),
+    Bytes(Vec<u8>),
+}
+
+impl Data {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Byte(_) => 1,
+            Self::Word(_) => 2,
+            Self::Bytes(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Constant {
+    pub fn len(&self) -> usize {
+        self.0.iter().map(Data::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encode the data. Values that are too large are truncated and reported as [Overflow]s,
+    /// with the index of the entry as the operand.
+    pub fn compile(&self, ctx: &Compiler) -> Result<(Vec<u8>, Vec<Overflow>)> {
+        let mut bytes = Vec::with_capacity(self.len());
+        let mut overflows = vec![];
+
+        for (i, data) in self.0.iter().enumerate() {
+            match data {
+                Data::Byte(e) => {
+                    let val = ctx.immediate(e, ctx.bin.len() + bytes.len(), 8)?;
+                    bytes.push(val as u8);
+                    overflows.extend(Overflow::check(i, val, 8));
+                }
+                Data::Word(e) => {
+                    let val = ctx.immediate(e, ctx.bin.len() + bytes.len(), 16)?;
+                    bytes.extend((val as u16).to_le_bytes());
+                    overflows.extend(Overflow::check(i, val, 16));
+                }
+                Data::Bytes(b) => bytes.extend(b),
+            }
+        }
+
+        Ok((bytes, overflows))
+    }
+}
+
+/// An entry between the `{ ... }` of a `#[const]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ConstantItem {
+    Data(Data),
+    Repeat(Repeat<ConstantItem>),
+}
+
+/// The `{ ... }` of a `#[const]`.
+pub fn lex_constant(buf: &str) -> LexResult<'_, Vec<ConstantItem>> {
+    let (items, buf) = repeated!("{" buf "," "}" {
+        ConstantItem::lex(buf)?
+    });
+    Ok((items, buf))
+}
+
+impl<'b> Lexable<'b> for ConstantItem {
+    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        if let Ok(buf) = expect(buf, "#[repeat") {
+            let ((count, var), buf) = lex_repeat_header(buf)?;
+            let buf = ignore_whitespace(buf);
+            let (body, buf) = lex_constant(buf)?;
+            return Ok((Self::Repeat(Repeat { count, var, body }), buf));
+        }
+
+        // "text", z"NUL terminated" or p"length prefixed"
+        let (prefix, rest) = match buf.strip_prefix(['z', 'p']) {
+            Some(rest) if rest.starts_with('"') => (buf.chars().next(), rest),
+            _ => (None, buf),
+        };
+        if rest.starts_with('"') {
+            let (mut bytes, rest) = lex_string(rest)?;
+            match prefix {
+                Some('z') => bytes.push(0),
+                Some(_) => {
+                    let Ok(len) = u8::try_from(bytes.len()) else {
+                        bail_at!(buf, "String of {} bytes is too long for p\"\"", bytes.len());
+                    };
+                    bytes.insert(0, len);
+                }
+                None => {}
+            }
+            return Ok((Self::Data(Data::Bytes(bytes)), rest));
+        }
+
+        if let Ok(rest) = expect(buf, "word") {
+            if let Ok(rest) = expect(ignore_whitespace_noline(rest), "(") {
+                let (word, rest) = Expr::lex(rest)?;
+                let rest = expect(ignore_whitespace(rest), ")")?;
+                return Ok((Self::Data(Data::Word(word)), rest));
+            }
+        }
+
+        let (byte, buf) = Expr::lex(buf)?;
+        Ok((Self::Data(Data::Byte(byte)), buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::test::{compile, compile_errors};
+    use crate::compiler::Level;
+
+    fn byte(b: usize) -> ConstantItem {
+        ConstantItem::Data(Data::Byte(Expr::Literal(b)))
+    }
+
+    fn bytes(b: &[u8]) -> ConstantItem {
+        ConstantItem::Data(Data::Bytes(b.to_vec()))
+    }
+
+    #[test]
+    fn lex_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let (b, remaining) = lex_constant(r#"{ 0, 0, 1, 0 }"#)?;
+
+        assert!(remaining.is_empty());
+        assert!(b == [0, 0, 1, 0].map(byte));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lex_data() -> Result<(), Box<dyn std::error::Error>> {
+        let (b, remaining) = lex_constant(
+            r#"{
+                "hi\n", z"a\x7F", p"\"b\"",
+                'A', '\'', word(table + 1), lo(table),
+            }"#,
+        )?;
+
+        assert!(remaining.is_empty());
+        assert_eq!(
+            b,
+            [
+                bytes(b"hi\n"),
+                bytes(b"a\x7F\0"),
+                bytes(b"\x03\"b\""),
+                byte(0x41),
+                byte(0x27),
+                ConstantItem::Data(Data::Word(Expr::lex("table + 1")?.0)),
+                ConstantItem::Data(Data::Byte(Expr::lex("lo(table)")?.0)),
+            ]
+        );
+
+        assert!(lex_constant(r#"{ "open }"#).is_err());
+        assert!(lex_constant(r#"{ 'ab' }"#).is_err());
+        assert!(lex_constant(r#"{ "\q" }"#).is_err());
+        let long = format!("{{ p\"{}\" }}", "x".repeat(256));
+        assert!(lex_constant(&long).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn constant_data() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[const(HANDLERS)] { word(on_up), word(on_down), lo(end - HANDLERS) }
+on_up:
+    mov %a, 'u'
+on_down:
+#[const(TEXT)] { z"ok\n", p"ab", 'c' }
+end:
+"#,
+            Level::Error,
+        )?;
+        assert_eq!(
+            compiler.bin,
+            [0x05, 0x00, 0x07, 0x00, 0x0F, 0x08, b'u', b'o', b'k', b'\n', 0, 2, b'a', b'b', b'c']
+        );
+
+        let errors = compile_errors("#[const(DATA)] { 1, 0x100 }");
+        assert_eq!(
+            errors[0].message,
+            "Entry 2 of #[const(DATA)] is 256 (0x100), which does not fit in 8 bits"
+        );
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use crate::compiler::lex::lexable::*;
 use crate::token;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Use {
     File(String),
     Module(String),
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use png::Format;
+
+use crate::compiler::lex::lexable::*;
+use crate::{lex_enum, token};
+
+/// `#[include_bytes(NAME, "file.bin")]` or `#[include_image(NAME, "sprite.png", mono)]`, the
+/// contents of a file as a `#[const]`. `NAME` is optional.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Include {
+    pub name: Option<String>,
+    pub path: String,
+    pub kind: IncludeKind,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IncludeKind {
+    Bytes,
+    Image(Format),
+}
+
+impl Include {
+    /// The name of the `#[const]`, by default the file name in upper case (`sprites/ship.png`
+    /// -> `SHIP`).
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => Path::new(&self.path)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .chars()
+                .map(|c| match c {
+                    c if c.is_alphanumeric() => c.to_ascii_uppercase(),
+                    _ => '_',
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Lex the arguments of `#[include_bytes(...)]` or `#[include_image(...)]`, without the
+/// parentheses.
+pub(super) fn lex_include(buf: &str, image: bool) -> LexResult<'_, Include> {
+    let (name, buf) = match token!(buf; '_') {
+        Ok((name, buf)) => {
+            let buf = ignore_whitespace_noline(buf);
+            let buf = expect(buf, ",")?;
+            (Some(name.to_string()), ignore_whitespace_noline(buf))
+        }
+        Err(_) => (None, buf),
+    };
+
+    let buf = expect(buf, "\"")?;
+    let (path, buf) = collect_until(buf, |c| c == '"')?;
+    let buf = expect(buf, "\"")?;
+
+    let (kind, buf) = if image {
+        let buf = ignore_whitespace_noline(buf);
+        let buf = expect(buf, ",")?;
+        let buf = ignore_whitespace_noline(buf);
+        let (format, buf) = lex_enum! { buf;
+            "mono" => Format::Mono,
+            "rgb222" => Format::Rgb222,
+        }?;
+        (IncludeKind::Image(format), buf)
+    } else {
+        (IncludeKind::Bytes, buf)
+    };
+
+    Ok((
+        Include {
+            name,
+            path: path.to_string(),
+            kind,
+        },
+        buf,
+    ))
+}
@@ -1,19 +1,22 @@
-use crate::compiler::lex::lexable::*;
-use crate::compiler::lex::node::{Instruction, Value};
-use crate::{lex_enum, repeated, token};
+use std::fmt::Display;
+use std::sync::Arc;
 
-use anyhow::bail;
+use crate::compiler::lex::lexable::*;
+use crate::compiler::lex::{ItemInner, Node, SourceFile};
+use crate::{bail_at, lex_enum, repeated, token};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Macro {
     pub id: String,
     pub captures: Vec<MacroCapture>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MacroCapture {
     pub args: Vec<MacroCaptureArg>,
-    pub content: Vec<Instruction>,
+    /// Instructions and labels. Labels are renamed for every expansion, so they are only
+    /// visible inside of it.
+    pub content: Vec<Node>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -24,14 +27,37 @@ pub enum MacroCaptureArgType {
     Any,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MacroCaptureArg {
     pub id: String,
     pub ty: MacroCaptureArgType,
 }
 
-impl<'b> Lexable<'b> for Macro {
-    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+impl Display for MacroCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = self
+            .args
+            .iter()
+            .map(|arg| format!("{}: {}", arg.id, arg.ty))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "({args})")
+    }
+}
+
+impl Display for MacroCaptureArgType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Register => "reg",
+            Self::Literal => "lit",
+            Self::Expr => "expr",
+            Self::Any => "any",
+        })
+    }
+}
+
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for Macro {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
         let buf = ignore_whitespace(buf);
         let (id, buf) = token!(buf; '_')?;
 
@@ -40,7 +66,7 @@ impl<'b> Lexable<'b> for Macro {
         let buf = ignore_whitespace(buf);
 
         let (captures, buf) = repeated!("{" buf "}" {
-            MacroCapture::lex(buf)?
+            MacroCapture::lex_with(buf, file)?
         });
 
         Ok((
@@ -53,8 +79,8 @@ impl<'b> Lexable<'b> for Macro {
     }
 }
 
-impl<'b> Lexable<'b> for MacroCapture {
-    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for MacroCapture {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
         let (args, buf) = repeated!("(" buf "," ")" {
             MacroCaptureArg::lex(buf)?
         });
@@ -64,19 +90,11 @@ impl<'b> Lexable<'b> for MacroCapture {
         let buf = ignore_whitespace(buf);
 
         let (content, buf) = repeated!("{" buf "}" {
-            let (id, buf) = collect_while(buf, |c| c.is_alphanumeric() || c == '_')?;
-            let buf = ignore_whitespace_noline(buf);
-            if let Ok(buf) = expect(buf, "\n") {
-                (Instruction {
-                    id: id.to_string(),
-                    args: vec![],
-                }, buf)
-            } else {
-                let (args, buf) = Vec::<Value>::lex(buf)?;
-                (Instruction {
-                    id: id.to_string(),
-                    args,
-                }, buf)
+            match ItemInner::lex_with(buf, file)? {
+                (ItemInner::Node(node @ (Node::Instruction(_) | Node::Label(..))), rest) => {
+                    (node, rest)
+                }
+                _ => bail_at!(buf, "Expected an instruction or label in macro body"),
             }
         });
 
@@ -117,11 +135,17 @@ impl<'b> Lexable<'b> for MacroCaptureArgType {
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
     use crate::compiler::lex::*;
 
+    fn source(buf: &str) -> Arc<SourceFile> {
+        Arc::new(SourceFile::new(Arc::default(), buf.to_string()))
+    }
+
     #[test]
     fn lex_macro() -> Result<(), Box<dyn std::error::Error>> {
-        let (mac, remaining) = Macro::lex(
+        let file = source(
             r#"jnz: {
                 ($addr: expr, $if: any) => {
                     ldxy $addr
@@ -131,7 +155,8 @@ mod test {
                     jnz $if
                 }
             }"#,
-        )?;
+        );
+        let (mac, remaining) = Macro::lex_with(&file.content, &file)?;
 
         assert!(remaining.is_empty());
         assert!(mac.id == "jnz");
@@ -142,27 +167,35 @@ mod test {
 
     #[test]
     fn lex_macro_capture() -> Result<(), Box<dyn std::error::Error>> {
-        let (cap, remaining) = MacroCapture::lex(
+        let file = source(
             r#"($addr: expr, $if: any) => {
                 ldxy $addr
                 jnz $if
             }"#,
-        )?;
+        );
+        let (cap, remaining) = MacroCapture::lex_with(&file.content, &file)?;
 
         assert!(remaining.is_empty());
         assert!(cap.args.len() == 2);
         assert!(cap.content.len() == 2);
+        let span = cap.content[1].span().unwrap();
+        assert_eq!(span.line, 3);
+        assert_eq!(span.text(), "jnz $if");
         assert_eq!(
             cap.content,
             vec![
-                Instruction {
+                Node::Instruction(Instruction {
                     id: "ldxy".to_string(),
-                    args: vec![Value::MacroVariable("$addr".to_string())]
-                },
-                Instruction {
+                    args: vec![Value::MacroVariable("$addr".to_string())],
+                    span: file.span_at(45, 10),
+                    expanded_from: vec![],
+                }),
+                Node::Instruction(Instruction {
                     id: "jnz".to_string(),
-                    args: vec![Value::MacroVariable("$if".to_string())]
-                },
+                    args: vec![Value::MacroVariable("$if".to_string())],
+                    span: file.span_at(72, 7),
+                    expanded_from: vec![],
+                }),
             ]
         );
         assert_eq!(
@@ -1,174 +1,573 @@
+use std::sync::Arc;
+
+use crate::bail_at;
 use crate::compiler::lex::lexable::*;
+use crate::compiler::lex::{Expr, Item, SourceFile, Span};
 use crate::lex_enum;
 use crate::repeated;
 use crate::surround_inline;
 use crate::token;
 
-use anyhow::bail;
-
+mod constant;
 mod import;
+mod include;
 mod mac;
+mod placement;
+mod region;
+mod repeat;
 
+pub use constant::*;
 pub use import::*;
+pub use include::*;
 pub use mac::*;
+pub use placement::*;
+pub use region::*;
+pub use repeat::*;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Meta {
     Main(String),
-    Constant(String, Constant),
-    Dyn(String, usize),
+    Constant(String, Vec<ConstantItem>),
+    /// `#[dyn(VAR: 2)]`, or `#[dyn(VAR: 2 = { 5, 0 })]` with the bytes it starts with.
+    Dyn(String, usize, Option<Vec<ConstantItem>>),
     DynOrigin(usize),
+    /// `#[dyn(zero)]`: set every `#[dyn]` without initial bytes to 0 at startup.
+    DynZero,
+    /// `#[dyn(region NAME: bank N, &ADDR, LEN)]`, or `#[dyn(region NAME)]` to allocate in
+    /// a region defined before.
+    DynRegion(String, Option<Region>),
     Macro(Macro),
-    Static(String, usize),
+    Static(String, Expr),
     Use(Use),
+    Include(Include),
+    Placement(Placement),
+    /// `#[if(EXPR)] { ... } #[else] { ... }` or `#[cfg(NAME)] { ... }`. `span` covers the
+    /// condition.
+    If {
+        cond: Condition,
+        span: Span,
+        then: Vec<Item>,
+        otherwise: Vec<Item>,
+    },
+    Repeat(Repeat<Item>),
+    /// `#[pub static(...)]` and such: what the meta defines can be used by the modules that
+    /// `#[use]` this one. `#[pub use(...)]` passes on what the used module exports.
+    Pub(Box<Meta>),
+    /// `#[pub]` in front of a label.
+    PubLabel(String),
+    /// `#[keep]` in front of a label or `#[const]`: it stays in ROM even if nothing reaches
+    /// it from `#[main]`.
+    Keep(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Condition {
+    /// Taken if the expression is not 0.
+    Expr(Expr),
+    /// Taken if the static is defined, whatever its value.
+    Cfg(String),
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum MetaKind {
     Main,
+    Pub,
+    Keep,
     Constant,
     Dyn,
     Macro,
     Static,
     Use,
+    IncludeBytes,
+    IncludeImage,
+    Org,
+    Align,
+    Bank,
+    If,
+    Cfg,
+    Else,
+    Repeat,
 }
 
-impl<'b> Lexable<'b> for Meta {
-    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for Meta {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
+        let start = buf;
         let buf = expect(buf, "#[")?;
         let buf = ignore_whitespace_noline(buf);
+        lex_meta(start, buf, file)
+    }
+}
 
-        let (word, buf) = lex_enum! { buf;
-            "main" => MetaKind::Main,
-            "macro" => MetaKind::Macro,
-            "static" => MetaKind::Static,
-            "const" => MetaKind::Constant,
-            "use" => MetaKind::Use,
-            "dyn" => MetaKind::Dyn,
-        }
-        .map_err(|e| e.context("Unknown meta keyword"))?;
+/// Lex what follows the `#[` at `start`.
+fn lex_meta<'b>(start: &'b str, buf: &'b str, file: &Arc<SourceFile>) -> LexResult<'b, Meta> {
+    let (word, buf) = lex_enum! { buf;
+        "main" => MetaKind::Main,
+        "pub" => MetaKind::Pub,
+        "keep" => MetaKind::Keep,
+        "macro" => MetaKind::Macro,
+        "static" => MetaKind::Static,
+        "const" => MetaKind::Constant,
+        "use" => MetaKind::Use,
+        "include_bytes" => MetaKind::IncludeBytes,
+        "include_image" => MetaKind::IncludeImage,
+        "org" => MetaKind::Org,
+        "align" => MetaKind::Align,
+        "bank" => MetaKind::Bank,
+        "dyn" => MetaKind::Dyn,
+        "if" => MetaKind::If,
+        "cfg" => MetaKind::Cfg,
+        "else" => MetaKind::Else,
+        "repeat" => MetaKind::Repeat,
+    }
+    .map_err(|e| e.context("Unknown meta keyword"))?;
 
-        match word {
-            MetaKind::Main => {
-                let buf = expect(buf, "]")?;
+    match word {
+        MetaKind::Main => {
+            let buf = expect(buf, "]")?;
+            let buf = ignore_whitespace(buf);
+            let b = buf;
+            let (label, buf) = token!(buf; '_')?;
+            let buf = ignore_whitespace(buf);
+            let _ = expect(buf, ":")?;
+            Ok((Meta::Main(label.to_string()), b))
+        }
+        MetaKind::Pub => {
+            if let Ok(buf) = expect(buf, "]") {
                 let buf = ignore_whitespace(buf);
                 let b = buf;
                 let (label, buf) = token!(buf; '_')?;
                 let buf = ignore_whitespace(buf);
                 let _ = expect(buf, ":")?;
-                Ok((Meta::Main(label.to_string()), b))
+                return Ok((Meta::PubLabel(label.to_string()), b));
             }
-            MetaKind::Macro => {
-                let buf = expect(buf, "]")?;
-                let buf = ignore_whitespace(buf);
-                let (mac, buf) = Macro::lex(buf)?;
-                Ok((Meta::Macro(mac), buf))
+            let buf = ignore_whitespace_noline(buf);
+            let (meta, buf) = lex_meta(start, buf, file)?;
+            match meta {
+                    Meta::Static(..)
+                    | Meta::Dyn(..)
+                    | Meta::Macro(_)
+                    | Meta::Constant(..)
+                    | Meta::Use(_)
+                    | Meta::Include(_) => Ok((Meta::Pub(Box::new(meta)), buf)),
+                    _ => bail_at!(
+                        start,
+                        "Only labels, #[static], #[dyn], #[const], #[macro], #[use] and includes can be #[pub]"
+                    ),
+                }
+        }
+        MetaKind::Keep => {
+            let buf = expect(buf, "]")?;
+            let buf = ignore_whitespace(buf);
+            let b = buf;
+            // Lexed again after this, like the label
+            if buf.starts_with("#[") {
+                return match Meta::lex_with(buf, file)? {
+                    (Meta::Constant(id, _), _) => Ok((Meta::Keep(id), b)),
+                    _ => bail_at!(start, "Only labels and #[const]s can be #[keep]"),
+                };
             }
-            MetaKind::Static => {
+            let (label, buf) = token!(buf; '_')?;
+            let buf = ignore_whitespace(buf);
+            let _ = expect(buf, ":")?;
+            Ok((Meta::Keep(label.to_string()), b))
+        }
+        MetaKind::Macro => {
+            let buf = expect(buf, "]")?;
+            let buf = ignore_whitespace(buf);
+            let (mac, buf) = Macro::lex_with(buf, file)?;
+            Ok((Meta::Macro(mac), buf))
+        }
+        MetaKind::Static => {
+            let buf = ignore_whitespace(buf);
+            let ((id, val), buf) = surround_inline!("(" buf ")" {
+                let (id, buf) = token!(buf; '_')?;
                 let buf = ignore_whitespace(buf);
-                let ((id, val), buf) = surround_inline!("(" buf ")" {
-                    let (id, buf) = token!(buf; '_')?;
-                    let buf = ignore_whitespace(buf);
-                    let buf = expect(buf, ":")?;
-                    let buf = ignore_whitespace(buf);
-                    let (val, buf) = usize::lex(buf)?;
-                    ((id, val), buf)
-                });
+                let buf = expect(buf, ":")?;
                 let buf = ignore_whitespace(buf);
+                let (val, buf) = Expr::lex(buf)?;
+                ((id, val), buf)
+            });
+            let buf = ignore_whitespace(buf);
+            let buf = expect(buf, "]")?;
+            Ok((Meta::Static(id.to_string(), val), buf))
+        }
+        MetaKind::Use => {
+            let buf = ignore_whitespace(buf);
+            let (import, buf) = surround_inline!("(" buf ")" {
+                Use::lex(buf)?
+            });
+            let buf = expect(buf, "]")?;
+            Ok((Meta::Use(import), buf))
+        }
+        MetaKind::IncludeBytes | MetaKind::IncludeImage => {
+            let buf = ignore_whitespace(buf);
+            let (include, buf) = surround_inline!("(" buf ")" {
+                lex_include(buf, matches!(word, MetaKind::IncludeImage))?
+            });
+            let buf = ignore_whitespace_noline(buf);
+            let buf = expect(buf, "]")?;
+            Ok((Meta::Include(include), buf))
+        }
+        MetaKind::Org | MetaKind::Align => {
+            let buf = ignore_whitespace(buf);
+            let (placement, buf) = surround_inline!("(" buf ")" {
+                lex_placement(buf, matches!(word, MetaKind::Align))?
+            });
+            let buf = ignore_whitespace_noline(buf);
+            let buf = expect(buf, "]")?;
+            Ok((Meta::Placement(placement), buf))
+        }
+        MetaKind::Bank => {
+            let buf = ignore_whitespace(buf);
+            let (bank, buf) = surround_inline!("(" buf ")" {
+                Expr::lex(buf)?
+            });
+            let buf = ignore_whitespace_noline(buf);
+            let buf = expect(buf, "]")?;
+            let placement = Placement {
+                kind: PlacementKind::Bank(Some(bank)),
+                fill: Expr::Literal(0),
+            };
+            Ok((Meta::Placement(placement), buf))
+        }
+        MetaKind::Dyn => {
+            let buf = ignore_whitespace(buf);
+            let buf = expect(buf, "(")?;
+            let buf = ignore_whitespace(buf);
+            if let Ok(buf) = expect(buf, "&") {
+                let (org, buf) = usize::lex(buf)?;
+                let buf = expect(buf, ")")?;
                 let buf = expect(buf, "]")?;
-                Ok((Self::Static(id.to_string(), val), buf))
+                return Ok((Meta::DynOrigin(org), buf));
             }
-            MetaKind::Use => {
-                let buf = ignore_whitespace(buf);
-                let (import, buf) = surround_inline!("(" buf ")" {
-                    Use::lex(buf)?
-                });
-                let buf = expect(buf, "]")?;
-                Ok((Self::Use(import), buf))
+            let (id, buf) = collect_while(buf, |c| c.is_alphanumeric() || c == '_')?;
+            let buf = ignore_whitespace(buf);
+            if id == "region" && !buf.starts_with(':') {
+                let ((name, region), buf) = lex_region(buf)?;
+                return Ok((Meta::DynRegion(name, region), buf));
             }
-            MetaKind::Dyn => {
-                let buf = ignore_whitespace(buf);
-                let buf = expect(buf, "(")?;
-                let buf = ignore_whitespace(buf);
-                if let Ok(buf) = expect(buf, "&") {
-                    let (org, buf) = usize::lex(buf)?;
-                    let buf = expect(buf, ")")?;
+            if id == "zero" {
+                if let Ok(buf) = expect(buf, ")") {
                     let buf = expect(buf, "]")?;
-                    return Ok((Self::DynOrigin(org), buf));
+                    return Ok((Meta::DynZero, buf));
                 }
-                let (id, buf) = collect_while(buf, |c| c.is_alphanumeric() || c == '_')?;
-                let buf = ignore_whitespace(buf);
-                let buf = expect(buf, ":")?;
-                let buf = ignore_whitespace(buf);
-                let (num, buf) = usize::lex(buf)?;
-                let buf = ignore_whitespace(buf);
-                let buf = expect(buf, ")")?;
-                let buf = expect(buf, "]")?;
-                Ok((Self::Dyn(id.to_string(), num), buf))
-            }
-            MetaKind::Constant => {
-                let buf = ignore_whitespace(buf);
-                let (id, buf) = surround_inline!("(" buf ")" {
-                    token!(buf; '_')?
-                });
-                let buf = ignore_whitespace(buf);
-                let buf = expect(buf, "]")?;
-                let buf = ignore_whitespace(buf);
-                let (explicit, buf) = Constant::lex(buf)?;
-                Ok((Self::Constant(id.to_string(), explicit), buf))
             }
+            let buf = expect(buf, ":")?;
+            let buf = ignore_whitespace(buf);
+            let (num, buf) = usize::lex(buf)?;
+            let buf = ignore_whitespace(buf);
+            let (init, buf) = match expect(buf, "=") {
+                Ok(buf) => {
+                    let (init, buf) = lex_constant(ignore_whitespace(buf))?;
+                    (Some(init), ignore_whitespace(buf))
+                }
+                Err(_) => (None, buf),
+            };
+            let buf = expect(buf, ")")?;
+            let buf = expect(buf, "]")?;
+            Ok((Meta::Dyn(id.to_string(), num, init), buf))
         }
-    }
-}
+        MetaKind::If | MetaKind::Cfg => {
+            let buf = ignore_whitespace(buf);
+            let (cond, buf) = surround_inline!("(" buf ")" {
+                match word {
+                    MetaKind::If => {
+                        let (expr, buf) = Expr::lex(buf)?;
+                        (Condition::Expr(expr), buf)
+                    }
+                    _ => {
+                        let (id, buf) = token!(buf; '_')?;
+                        (Condition::Cfg(id.to_string()), buf)
+                    }
+                }
+            });
+            let buf = ignore_whitespace_noline(buf);
+            let buf = expect(buf, "]")?;
+            let span = file.span_between(start, buf);
 
-#[derive(Debug, PartialEq, Eq)]
-pub struct Constant(pub Vec<u8>);
+            let buf = ignore_whitespace(buf);
+            let (then, buf) = lex_block(buf, file)?;
+
+            let (otherwise, buf) = match expect(ignore_whitespace(buf), "#[else]") {
+                Ok(rest) => {
+                    let rest = ignore_whitespace(rest);
+                    if rest.starts_with('{') {
+                        lex_block(rest, file)?
+                    } else {
+                        // `#[else] #[if(...)] { ... }`
+                        let (item, rest) = Item::lex_with(rest, file)?;
+                        (vec![item], rest)
+                    }
+                }
+                Err(_) => (vec![], buf),
+            };
 
-impl<'b> Lexable<'b> for Constant {
-    fn lex(buf: &'b str) -> LexResult<'b, Self> {
-        let (bytes, buf) = repeated!("{" buf "," "}" {
-            let (byte, buf) = usize::lex(buf)?;
-            (byte as u8, buf)
-        });
-        Ok((Self(bytes), buf))
+            Ok((
+                Meta::If {
+                    cond,
+                    span,
+                    then,
+                    otherwise,
+                },
+                buf,
+            ))
+        }
+        MetaKind::Repeat => {
+            let ((count, var), buf) = lex_repeat_header(buf)?;
+            let buf = ignore_whitespace(buf);
+            let (body, buf) = lex_block(buf, file)?;
+            Ok((Meta::Repeat(Repeat { count, var, body }), buf))
+        }
+        MetaKind::Else => bail_at!(start, "#[else] without #[if] or #[cfg] before it"),
+        MetaKind::Constant => {
+            let buf = ignore_whitespace(buf);
+            let (id, buf) = surround_inline!("(" buf ")" {
+                token!(buf; '_')?
+            });
+            let buf = ignore_whitespace(buf);
+            let buf = expect(buf, "]")?;
+            let buf = ignore_whitespace(buf);
+            let (items, buf) = lex_constant(buf)?;
+            Ok((Meta::Constant(id.to_string(), items), buf))
+        }
     }
 }
 
+/// Items between `{` and `}`.
+fn lex_block<'b>(buf: &'b str, file: &Arc<SourceFile>) -> LexResult<'b, Vec<Item>> {
+    let (items, buf) = repeated!("{" buf "}" {
+        Item::lex_with(buf, file)?
+    });
+    Ok((items, buf))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::compiler::lex::ItemInner;
+
+    fn lex(buf: &str) -> anyhow::Result<Meta> {
+        let file = Arc::new(SourceFile::new(Arc::default(), buf.to_string()));
+        Meta::lex_with(&file.content, &file).map(|(meta, _)| meta)
+    }
 
     #[test]
     fn stat() -> Result<(), Box<dyn std::error::Error>> {
-        let (buf, _) = Meta::lex("#[static(HELLO: 0xFF00)]")?;
-        assert_eq!(buf, Meta::Static("HELLO".to_string(), 0xFF00));
+        let buf = lex("#[static(HELLO: 0xFF00)]")?;
+        assert_eq!(
+            buf,
+            Meta::Static("HELLO".to_string(), Expr::Literal(0xFF00))
+        );
+
+        let buf = lex("#[static(HELLO: 2)]")?;
+        assert_eq!(buf, Meta::Static("HELLO".to_string(), Expr::Literal(2)));
 
-        let (buf, _) = Meta::lex("#[static(HELLO: 2)]")?;
-        assert_eq!(buf, Meta::Static("HELLO".to_string(), 2));
+        let buf = lex("#[static(HELLO: 0b1001)]")?;
+        assert_eq!(
+            buf,
+            Meta::Static("HELLO".to_string(), Expr::Literal(0b1001))
+        );
 
-        let (buf, _) = Meta::lex("#[static(HELLO: 0b1001)]")?;
-        assert_eq!(buf, Meta::Static("HELLO".to_string(), 0b1001));
+        let buf = lex("#[static(HELLO: BRAM + 0x3FFF)]")?;
+        assert_eq!(
+            buf,
+            Meta::Static("HELLO".to_string(), Expr::lex("BRAM + 0x3FFF")?.0)
+        );
 
         Ok(())
     }
 
     #[test]
     fn lex_dyn() -> Result<(), Box<dyn std::error::Error>> {
-        let (buf, _) = Meta::lex("#[dyn(TEST: 4)]")?;
-        assert_eq!(buf, Meta::Dyn("TEST".to_string(), 4));
+        let buf = lex("#[dyn(TEST: 4)]")?;
+        assert_eq!(buf, Meta::Dyn("TEST".to_string(), 4, None));
+
+        let buf = lex("#[dyn(TEST: 2 = { 5, word(X) })]")?;
+        assert_eq!(
+            buf,
+            Meta::Dyn(
+                "TEST".to_string(),
+                2,
+                Some(vec![
+                    ConstantItem::Data(Data::Byte(Expr::Literal(5))),
+                    ConstantItem::Data(Data::Word(Expr::Variable("X".to_string())))
+                ])
+            )
+        );
+
+        let buf = lex("#[dyn(zero)]")?;
+        assert_eq!(buf, Meta::DynZero);
+
+        let buf = lex("#[dyn(region vram: bank 1, &0x8000, 0x4000)]")?;
+        assert_eq!(
+            buf,
+            Meta::DynRegion(
+                "vram".to_string(),
+                Some(Region {
+                    bank: Some(1),
+                    start: 0x8000,
+                    len: 0x4000
+                })
+            )
+        );
 
-        let (buf, _) = Meta::lex("#[dyn(&0xC000)]")?;
+        let buf = lex("#[dyn(region vram)]")?;
+        assert_eq!(buf, Meta::DynRegion("vram".to_string(), None));
+
+        let buf = lex("#[dyn(&0xC000)]")?;
         assert_eq!(buf, Meta::DynOrigin(0xC000));
 
         Ok(())
     }
 
     #[test]
-    fn lex_bytes() -> Result<(), Box<dyn std::error::Error>> {
-        let (b, remaining) = Constant::lex(r#"{ 0, 0, 1, 0 }"#)?;
+    fn lex_include() -> Result<(), Box<dyn std::error::Error>> {
+        let Meta::Include(include) = lex(r#"#[include_bytes("data/font.bin")]"#)? else {
+            panic!("expected #[include_bytes]");
+        };
+        assert_eq!(include.kind, IncludeKind::Bytes);
+        assert_eq!(include.name(), "FONT");
+
+        let Meta::Include(include) = lex(r#"#[include_image(SHIP, "ship.png", rgb222)]"#)? else {
+            panic!("expected #[include_image]");
+        };
+        assert_eq!(include.path, "ship.png");
+        assert_eq!(include.kind, IncludeKind::Image(png::Format::Rgb222));
+        assert_eq!(include.name(), "SHIP");
+
+        assert!(lex(r#"#[include_image("ship.png")]"#).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lex_placement() -> Result<(), Box<dyn std::error::Error>> {
+        let buf = lex("#[org(0x4000)]")?;
+        assert_eq!(
+            buf,
+            Meta::Placement(Placement {
+                kind: PlacementKind::Org(Expr::Literal(0x4000)),
+                fill: Expr::Literal(0),
+            })
+        );
+
+        let buf = lex("#[align(PAGE, 0xFF)]")?;
+        assert_eq!(
+            buf,
+            Meta::Placement(Placement {
+                kind: PlacementKind::Align(Expr::Variable("PAGE".to_string())),
+                fill: Expr::Literal(0xFF),
+            })
+        );
+
+        let buf = lex("#[bank(2)]")?;
+        assert_eq!(
+            buf,
+            Meta::Placement(Placement {
+                kind: PlacementKind::Bank(Some(Expr::Literal(2))),
+                fill: Expr::Literal(0),
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn lex_pub() -> Result<(), Box<dyn std::error::Error>> {
+        let buf = lex("#[pub static(WIDTH: 2)]")?;
+        assert_eq!(
+            buf,
+            Meta::Pub(Box::new(Meta::Static(
+                "WIDTH".to_string(),
+                Expr::Literal(2)
+            )))
+        );
+
+        let buf = lex("#[pub]\nwait:\n    ret")?;
+        assert_eq!(buf, Meta::PubLabel("wait".to_string()));
+
+        assert!(lex("#[pub main]").is_err());
+
+        assert!(lex("#[pub]\nmov %a, 1").is_err());
+
+        let buf = lex("#[keep]\nhandler:\n    ret")?;
+        assert_eq!(buf, Meta::Keep("handler".to_string()));
+        let buf = lex("#[keep]\n#[const(TABLE)] { 1, 2 }")?;
+        assert_eq!(buf, Meta::Keep("TABLE".to_string()));
+        assert!(lex("#[keep] #[static(A: 1)]").is_err());
+
+        let buf = lex("#[static(A: frame::WIDTH + 1)]")?;
+        assert_eq!(
+            buf,
+            Meta::Static("A".to_string(), Expr::lex("frame::WIDTH + 1")?.0)
+        );
+        let Meta::Static(_, Expr::Expr { lhs, .. }) = buf else {
+            panic!("{buf:?}");
+        };
+        assert_eq!(*lhs, Expr::Variable("frame::WIDTH".to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lex_if() -> Result<(), Box<dyn std::error::Error>> {
+        let Meta::If {
+            cond,
+            span,
+            then,
+            otherwise,
+        } = lex("#[if(X > 1)] {\n    mov %a, 1\n} #[else] {\n    mov %a, 2\n    mov %b, 2\n}")?
+        else {
+            panic!("expected #[if]");
+        };
+        assert_eq!(cond, Condition::Expr(Expr::lex("X > 1")?.0));
+        assert_eq!(span.text(), "#[if(X > 1)]");
+        assert_eq!(then.len(), 1);
+        assert_eq!(otherwise.len(), 2);
 
+        let Meta::If {
+            cond, otherwise, ..
+        } = lex("#[cfg(LOGISIM)] { } #[else] #[cfg(WEB)] {\n    mov %a, 1\n}")?
+        else {
+            panic!("expected #[cfg]");
+        };
+        assert_eq!(cond, Condition::Cfg("LOGISIM".to_string()));
+        assert!(matches!(
+            otherwise[..],
+            [Item {
+                item: ItemInner::Meta(Meta::If { .. }),
+                ..
+            }]
+        ));
+
+        assert!(lex("#[else] { }").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lex_repeat() -> Result<(), Box<dyn std::error::Error>> {
+        let Meta::Repeat(repeat) = lex("#[repeat(N * 2, $i)] {\n    add %a, $i\n}")? else {
+            panic!("expected #[repeat]");
+        };
+        assert_eq!(repeat.count, Expr::lex("N * 2")?.0);
+        assert_eq!(repeat.var, "$i");
+        assert_eq!(repeat.body.len(), 1);
+
+        let (b, remaining) = lex_constant("{ 1, #[repeat(3, $i)] { $i * $i, 0 }, 2 }")?;
         assert!(remaining.is_empty());
-        assert!(b.0 == vec![0, 0, 1, 0]);
+        let [_, ConstantItem::Repeat(repeat), _] = &b[..] else {
+            panic!("expected #[repeat]");
+        };
+        assert_eq!(
+            repeat.iteration(2),
+            [
+                ConstantItem::Data(Data::Byte(Expr::lex("2 * 2")?.0)),
+                ConstantItem::Data(Data::Byte(Expr::Literal(0)))
+            ]
+        );
+
+        assert!(lex("#[repeat(3, i)] { }").is_err());
 
         Ok(())
     }
@@ -0,0 +1,108 @@
+use anyhow::{bail, Result};
+
+use crate::compiler::lex::lexable::*;
+use crate::compiler::lex::Expr;
+use crate::compiler::Compiler;
+
+/// `#[org(ADDR, FILL)]` or `#[align(N, FILL)]`: where the nodes after it are placed in ROM.
+/// The gap before them is padded with `FILL`, 0 if it is left out. `#[bank(N)]` has no fill.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Placement {
+    pub kind: PlacementKind,
+    pub fill: Expr,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PlacementKind {
+    /// At exactly this address.
+    Org(Expr),
+    /// At the next multiple of this.
+    Align(Expr),
+    /// In the ROM bank `#[bank(N)]`, which is mapped at [BANK_START](crate::compiler::BANK_START) with `bank N`, up to the
+    /// end of the file. `None` goes back to the fixed ROM.
+    Bank(Option<Expr>),
+}
+
+impl Placement {
+    /// The address the next node starts at, if the ROM currently ends at `pc`.
+    pub fn target(&self, pc: usize, ctx: &Compiler) -> Result<usize> {
+        match &self.kind {
+            PlacementKind::Org(addr) => {
+                if ctx.is_object() {
+                    bail!(
+                        "#[org] cannot be used in an object, place it with a linker script instead"
+                    );
+                }
+                let addr = addr.resolve(ctx)?;
+                if addr < pc {
+                    bail!(
+                        "#[org({addr:#06X})] overlaps the code before it, which already reaches {pc:#06X}"
+                    );
+                }
+                Ok(addr)
+            }
+            PlacementKind::Align(n) => match n.resolve(ctx)? {
+                0 => bail!("Cannot #[align] to 0"),
+                n => {
+                    ctx.align_object(n);
+                    Ok(pc.next_multiple_of(n))
+                }
+            },
+            // Which ROM the nodes go in is up to the caller
+            PlacementKind::Bank(_) => Ok(pc),
+        }
+    }
+}
+
+/// Lex the arguments of `#[org(...)]` or `#[align(...)]`, without the parentheses.
+pub(super) fn lex_placement(buf: &str, align: bool) -> LexResult<'_, Placement> {
+    let (at, buf) = Expr::lex(buf)?;
+    let buf = ignore_whitespace_noline(buf);
+    let (fill, buf) = match expect(buf, ",") {
+        Ok(buf) => Expr::lex(ignore_whitespace_noline(buf))?,
+        Err(_) => (Expr::Literal(0), buf),
+    };
+
+    let kind = match align {
+        true => PlacementKind::Align(at),
+        false => PlacementKind::Org(at),
+    };
+    Ok((Placement { kind, fill }, buf))
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile, compile_errors};
+    use crate::compiler::Level;
+
+    #[test]
+    fn placement() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[const(A)] { 1, 2, 3 }
+#[align(4)]
+#[const(B)] { 4 }
+#[org(0x08, 0xFF)]
+#[const(C)] { lo(C), lo(B) }
+"#,
+            Level::Error,
+        )?;
+        assert_eq!(compiler.bin, [1, 2, 3, 0, 4, 0xFF, 0xFF, 0xFF, 8, 4]);
+
+        let errors =
+            compile_errors("#[const(A)] { 1, 2, 3 }\n#[org(2)]\n#[org(0x8000)]\n#[const(B)] { 1 }");
+        assert_eq!(
+            errors[0].message,
+            "#[org(0x0002)] overlaps the code before it, which already reaches 0x0003"
+        );
+        assert_eq!(
+            errors[1].message,
+            "The program reaches 0x8001, past the end of the 32K ROM"
+        );
+        assert_eq!(errors[1].span.as_ref().unwrap().line, 4);
+
+        Ok(())
+    }
+}
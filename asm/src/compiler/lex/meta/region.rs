@@ -0,0 +1,58 @@
+use crate::compiler::lex::lexable::*;
+use crate::token;
+
+/// A part of RAM that `#[dyn]`s are allocated in, one after the other.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Region {
+    /// The bank to select to reach the region, if it is banked.
+    pub bank: Option<usize>,
+    pub start: usize,
+    pub len: usize,
+}
+
+impl Region {
+    pub fn end(&self) -> usize {
+        self.start + self.len
+    }
+}
+
+/// Lex what follows `#[dyn(region`: `NAME: bank N, &ADDR, LEN)]` to define a region, or
+/// `NAME)]` to go back to one.
+pub(super) fn lex_region(buf: &str) -> LexResult<'_, (String, Option<Region>)> {
+    let buf = ignore_whitespace_noline(buf);
+    let (name, buf) = token!(buf; '_')?;
+    let name = name.to_string();
+    let buf = ignore_whitespace_noline(buf);
+
+    if let Ok(buf) = expect(buf, ")") {
+        let buf = expect(buf, "]")?;
+        return Ok(((name, None), buf));
+    }
+
+    let buf = expect(buf, ":")?;
+    let (region, buf) = Region::lex(ignore_whitespace_noline(buf))?;
+    let buf = expect(ignore_whitespace_noline(buf), ")")?;
+    let buf = expect(buf, "]")?;
+
+    Ok(((name, Some(region)), buf))
+}
+
+/// `bank N, &ADDR, LEN`, with the bank left out if it isn't banked.
+impl<'b> Lexable<'b> for Region {
+    fn lex(buf: &'b str) -> LexResult<'b, Self> {
+        let (bank, buf) = match expect(buf, "bank") {
+            Ok(buf) => {
+                let (bank, buf) = usize::lex(ignore_whitespace_noline(buf))?;
+                let buf = expect(ignore_whitespace_noline(buf), ",")?;
+                (Some(bank), ignore_whitespace_noline(buf))
+            }
+            Err(_) => (None, buf),
+        };
+        let buf = expect(buf, "&")?;
+        let (start, buf) = usize::lex(buf)?;
+        let buf = expect(ignore_whitespace_noline(buf), ",")?;
+        let (len, buf) = usize::lex(ignore_whitespace_noline(buf))?;
+
+        Ok((Region { bank, start, len }, buf))
+    }
+}
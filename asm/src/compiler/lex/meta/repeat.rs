@@ -0,0 +1,158 @@
+use crate::compiler::lex::lexable::*;
+use crate::compiler::lex::{Expr, Instruction, Item, ItemInner, Meta, Node, Value};
+use crate::{bail_at, surround_inline, token};
+
+use super::{Condition, ConstantItem, Data, Placement, PlacementKind};
+
+/// `#[repeat(N, $i)] { ... }`: the body `N` times, with `$i` counting from 0.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Repeat<T> {
+    pub count: Expr,
+    pub var: String,
+    pub body: Vec<T>,
+}
+
+impl<T: Bind> Repeat<T> {
+    /// The body of the `i`th repetition.
+    pub fn iteration(&self, i: usize) -> Vec<T> {
+        self.body.iter().map(|t| t.bind(&self.var, i)).collect()
+    }
+}
+
+/// Lex `(N, $i)]`, what follows `#[repeat`.
+pub(super) fn lex_repeat_header(buf: &str) -> LexResult<'_, (Expr, String)> {
+    let buf = ignore_whitespace(buf);
+    let (header, buf) = surround_inline!("(" buf ")" {
+        let (count, buf) = Expr::lex(buf)?;
+        let buf = ignore_whitespace_noline(buf);
+        let buf = expect(buf, ",")?;
+        let buf = ignore_whitespace_noline(buf);
+        if expect(buf, "$").is_err() {
+            bail_at!(buf, "Expected a variable like `$i`");
+        }
+        let (var, buf) = token!(buf; '_' | '$')?;
+        ((count, var.to_string()), buf)
+    });
+    let buf = ignore_whitespace_noline(buf);
+    let buf = expect(buf, "]")?;
+    Ok((header, buf))
+}
+
+/// Replace the variable of a `#[repeat]` with its value in one iteration.
+pub trait Bind {
+    fn bind(&self, var: &str, value: usize) -> Self;
+}
+
+impl Bind for Expr {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        self.replace(&|v| (v == var).then_some(Expr::Literal(value)))
+    }
+}
+
+impl Bind for Value {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        match self {
+            Self::MacroVariable(v) if v == var => Self::Literal(value),
+            Self::Expr(e) => Self::Expr(e.bind(var, value)),
+            v => v.clone(),
+        }
+    }
+}
+
+impl Bind for Item {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        let item = match &self.item {
+            ItemInner::Node(Node::Instruction(inst)) => {
+                ItemInner::Node(Node::Instruction(Instruction {
+                    args: inst.args.iter().map(|a| a.bind(var, value)).collect(),
+                    ..inst.clone()
+                }))
+            }
+            ItemInner::Meta(meta) => ItemInner::Meta(meta.bind(var, value)),
+            item => item.clone(),
+        };
+        Item {
+            item,
+            span: self.span.clone(),
+        }
+    }
+}
+
+impl Bind for Meta {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        match self {
+            Self::Constant(id, items) => Self::Constant(
+                id.clone(),
+                items.iter().map(|i| i.bind(var, value)).collect(),
+            ),
+            Self::If {
+                cond,
+                span,
+                then,
+                otherwise,
+            } => Self::If {
+                cond: match cond {
+                    Condition::Expr(e) => Condition::Expr(e.bind(var, value)),
+                    cond => cond.clone(),
+                },
+                span: span.clone(),
+                then: then.iter().map(|i| i.bind(var, value)).collect(),
+                otherwise: otherwise.iter().map(|i| i.bind(var, value)).collect(),
+            },
+            Self::Static(id, e) => Self::Static(id.clone(), e.bind(var, value)),
+            Self::Repeat(repeat) => Self::Repeat(repeat.bind(var, value)),
+            Self::Placement(placement) => Self::Placement(placement.bind(var, value)),
+            Self::Pub(meta) => Self::Pub(Box::new(meta.bind(var, value))),
+            meta => meta.clone(),
+        }
+    }
+}
+
+impl Bind for ConstantItem {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        match self {
+            Self::Data(data) => Self::Data(data.bind(var, value)),
+            Self::Repeat(repeat) => Self::Repeat(repeat.bind(var, value)),
+        }
+    }
+}
+
+impl Bind for Data {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        match self {
+            Self::Byte(e) => Self::Byte(e.bind(var, value)),
+            Self::Word(e) => Self::Word(e.bind(var, value)),
+            Self::Bytes(b) => Self::Bytes(b.clone()),
+        }
+    }
+}
+
+impl Bind for Placement {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        Placement {
+            kind: match &self.kind {
+                PlacementKind::Org(e) => PlacementKind::Org(e.bind(var, value)),
+                PlacementKind::Align(e) => PlacementKind::Align(e.bind(var, value)),
+                PlacementKind::Bank(e) => {
+                    PlacementKind::Bank(e.as_ref().map(|e| e.bind(var, value)))
+                }
+            },
+            fill: self.fill.bind(var, value),
+        }
+    }
+}
+
+impl<T: Bind + Clone> Bind for Repeat<T> {
+    fn bind(&self, var: &str, value: usize) -> Self {
+        Repeat {
+            count: self.count.bind(var, value),
+            var: self.var.clone(),
+            // An inner `#[repeat]` with the same variable shadows it
+            body: if self.var == var {
+                self.body.clone()
+            } else {
+                self.body.iter().map(|t| t.bind(var, value)).collect()
+            },
+        }
+    }
+}
@@ -4,6 +4,7 @@ pub mod lexable;
 mod meta;
 mod node;
 mod pragma;
+mod span;
 
 pub use expr::*;
 pub use item::*;
@@ -11,3 +12,4 @@ pub use lexable::*;
 pub use meta::*;
 pub use node::*;
 pub use pragma::*;
+pub use span::*;
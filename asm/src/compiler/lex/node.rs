@@ -1,22 +1,98 @@
+use std::fmt::Display;
+use std::sync::Arc;
+
 use crate::reg::Register;
-use crate::token;
+use crate::{path, token};
 
 use super::expr::Expr;
 use super::lexable::*;
-use super::meta::{Constant, Use};
+use super::meta::{Constant, Placement, Use};
+use super::span::{SourceFile, Span};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Node {
     Instruction(Instruction),
-    Label(String),
-    Constant(String, Constant),
+    Label(String, Span),
+    Constant(String, Constant, Span),
+    /// Padding up to an `#[org]` or `#[align]`.
+    Placement(Placement, Span),
     Use(Use),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Node {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::Instruction(inst) => Some(&inst.span),
+            Self::Label(_, span) | Self::Constant(_, _, span) | Self::Placement(_, span) => {
+                Some(span)
+            }
+            Self::Use(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Instruction {
     pub id: String,
     pub args: Vec<Value>,
+    pub span: Span,
+    /// Macro calls this instruction was expanded from, outermost first.
+    pub expanded_from: Vec<Expansion>,
+}
+
+/// A macro call that was replaced by the body of one of its captures.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Expansion {
+    pub id: String,
+    pub span: Span,
+}
+
+impl Instruction {
+    /// The span of the text the programmer actually wrote: the outermost macro call this
+    /// instruction came from, or the instruction itself.
+    pub fn origin(&self) -> &Span {
+        self.expanded_from
+            .first()
+            .map(|e| &e.span)
+            .unwrap_or(&self.span)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.id)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{sep}{arg}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'b, 's> LexableWith<'b, &'s Arc<SourceFile>> for Instruction {
+    fn lex_with(buf: &'b str, file: &'s Arc<SourceFile>) -> LexResult<'b, Self> {
+        let start = buf;
+        let (id, buf) = path!(buf; '_')?;
+        let buf = ignore_whitespace_noline(buf);
+
+        let (args, buf) = if buf.is_empty() || buf.starts_with(';') {
+            (vec![], buf)
+        } else if let Ok(buf) = expect(buf, "\n") {
+            (vec![], buf)
+        } else {
+            Vec::<Value>::lex(buf)?
+        };
+
+        Ok((
+            Instruction {
+                id: id.to_string(),
+                args,
+                span: file.span_between(start, buf),
+                expanded_from: vec![],
+            },
+            buf,
+        ))
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -37,10 +113,25 @@ impl Value {
     }
 }
 
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Expr(e) => write!(f, "{e}"),
+            Self::Literal(lit) => write!(f, "{lit}"),
+            Self::Register(r) => write!(f, "%{r}"),
+            Self::MacroVariable(var) => f.write_str(var),
+        }
+    }
+}
+
 impl<'b> Lexable<'b> for Value {
     fn lex(buf: &'b str) -> LexResult<'b, Self> {
-        if let Ok((lit, buf)) = usize::lex(buf) {
-            return Ok((Value::Literal(lit), buf));
+        // Only a literal if it isn't the start of an expression (`1 + 2`)
+        if let Ok((lit, rest)) = usize::lex(buf) {
+            let next = rest.trim_start_matches([' ', '\t']).chars().next();
+            if matches!(next, None | Some(',' | ';' | '\n' | '\r')) {
+                return Ok((Value::Literal(lit), rest));
+            }
         }
 
         if buf.chars().nth(0) == Some('%') {
@@ -48,9 +139,13 @@ impl<'b> Lexable<'b> for Value {
             return Ok((Value::Register(reg), buf));
         }
 
+        // Only a macro variable if it isn't the start of an expression (`$n + 1`)
         if expect(buf, "$").is_ok() {
-            let (var, buf) = token!(buf; '_' | '$' | '.')?;
-            return Ok((Value::MacroVariable(var.to_string()), buf));
+            let (var, rest) = token!(buf; '_' | '$' | '.')?;
+            let next = rest.trim_start_matches([' ', '\t']).chars().next();
+            if matches!(next, None | Some(',' | ';' | '\n' | '\r')) {
+                return Ok((Value::MacroVariable(var.to_string()), rest));
+            }
         }
         let (expr_buf, buf) = collect_until(buf, |c| c == ',' || c == '\n' || c == ';')?;
         let (expr, expr_buf) = Expr::lex(expr_buf)?;
@@ -72,11 +167,17 @@ mod test {
 
     #[test]
     fn lex_instruction() -> Result<(), Box<dyn std::error::Error>> {
-        let (n, _) = Item::lex_with("mov %c, %d, BRAM + OFFSET", Arc::new(PathBuf::new()))?;
+        let file = Arc::new(SourceFile::new(
+            Arc::new(PathBuf::new()),
+            "mov %c, %d, BRAM + OFFSET ; comment".to_string(),
+        ));
+        let (n, _) = Item::lex_with(&file.content, &file)?;
         assert_eq!(
             n.item,
             ItemInner::Node(Node::Instruction(Instruction {
                 id: "mov".to_string(),
+                span: file.span_at(0, 25),
+                expanded_from: vec![],
                 args: vec![
                     Value::Register(Register::C),
                     Value::Register(Register::D),
@@ -89,6 +190,11 @@ mod test {
             }))
         );
 
+        let ItemInner::Node(Node::Instruction(inst)) = n.item else {
+            unreachable!()
+        };
+        assert_eq!(inst.to_string(), "mov %c, %d, BRAM + OFFSET");
+
         Ok(())
     }
 }
@@ -0,0 +1,157 @@
+use std::fmt::{Debug, Display};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use path_clean::clean;
+
+/// The contents of a file that is being lexed. Every [Span] points into one.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: Arc<PathBuf>,
+    pub content: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(path: Arc<PathBuf>, content: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+
+        Self {
+            path,
+            content,
+            line_starts,
+        }
+    }
+
+    /// Byte offset of `at` into [SourceFile::content]. `at` must be a slice of the content
+    /// (which every buffer handed out by the lexer is), otherwise the end of the file is used.
+    pub fn offset_of(&self, at: &str) -> usize {
+        self.offset_of_ptr(at.as_ptr() as usize)
+    }
+
+    pub(crate) fn offset_of_ptr(&self, ptr: usize) -> usize {
+        let start = self.content.as_ptr() as usize;
+        if ptr < start || ptr > start + self.content.len() {
+            return self.content.len();
+        }
+        ptr - start
+    }
+
+    /// Create a [Span] that starts at `at` and is `len` bytes long.
+    pub fn span(self: &Arc<Self>, at: &str, len: usize) -> Span {
+        self.span_at(self.offset_of(at), len)
+    }
+
+    /// Create a [Span] covering everything from `from` up until `to`, with
+    /// trailing whitespace trimmed.
+    pub fn span_between(self: &Arc<Self>, from: &str, to: &str) -> Span {
+        let start = self.offset_of(from);
+        let end = self.offset_of(to).max(start);
+        let len = self.content[start..end].trim_end().len();
+        self.span_at(start, len)
+    }
+
+    pub fn span_at(self: &Arc<Self>, offset: usize, len: usize) -> Span {
+        let offset = offset.min(self.content.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(l) => l,
+            Err(l) => l - 1,
+        };
+        let col = self.content[self.line_starts[line]..offset].chars().count();
+
+        Span {
+            file: self.clone(),
+            offset,
+            line: line + 1,
+            col: col + 1,
+            len: len.min(self.content.len() - offset),
+        }
+    }
+
+    /// The text of the (1-indexed) line `line`, without its line ending.
+    pub fn line(&self, line: usize) -> &str {
+        let Some(start) = self.line_starts.get(line.wrapping_sub(1)) else {
+            return "";
+        };
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.content.len());
+        self.content[*start..end].trim_end_matches(['\n', '\r'])
+    }
+
+    /// Cleaned up path used when printing locations.
+    pub fn name(&self) -> String {
+        clean(self.path.as_path()).display().to_string()
+    }
+}
+
+/// Location of some lexed text: the file, the (1-indexed) line and column it starts on, and its
+/// byte length.
+#[derive(Clone, Default)]
+pub struct Span {
+    pub file: Arc<SourceFile>,
+    pub offset: usize,
+    pub line: usize,
+    pub col: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// The source text that the span covers.
+    pub fn text(&self) -> &str {
+        &self.file.content[self.offset..self.offset + self.len]
+    }
+
+    /// The full text of the line that the span starts on.
+    pub fn source_line(&self) -> &str {
+        self.file.line(self.line)
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, other: &Self) -> bool {
+        (Arc::ptr_eq(&self.file, &other.file) || self.file.path == other.file.path)
+            && self.offset == other.offset
+            && self.len == other.len
+    }
+}
+
+impl Eq for Span {}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.file.name(), self.line, self.col)
+    }
+}
+
+impl Debug for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn locate() {
+        let file = Arc::new(SourceFile::new(
+            Arc::new(PathBuf::from("test.asm")),
+            "main:\n    mov %a, 1\n    jmp main\n".to_string(),
+        ));
+
+        let at = file.content.find("jmp").unwrap();
+        let span = file.span(&file.content[at..], 3);
+
+        assert_eq!(span.line, 3);
+        assert_eq!(span.col, 5);
+        assert_eq!(span.text(), "jmp");
+        assert_eq!(span.source_line(), "    jmp main");
+        assert_eq!(span.to_string(), "test.asm:3:5");
+    }
+}
@@ -0,0 +1,209 @@
+use std::fmt::Write;
+
+use super::lex::{Node, Placement, PlacementKind, Span};
+use super::{is_local_label, Compiler, Emitted};
+
+/// Bytes shown on a single row of the listing, longer runs are truncated or wrapped.
+const ROW_BYTES: usize = 6;
+
+impl Compiler {
+    /// Render the compiled binary as a listing: one row per source line with its address and
+    /// encoded bytes. Macro calls are followed by the native instructions they expanded to,
+    /// indented beneath them.
+    ///
+    /// ```text
+    /// 0003                         3  main:
+    /// 0003  58 00 58 0A 28 ..      4      call func
+    /// 0003  58 00                           push ($ + 7) >> 8
+    /// 0005  58 0A                           push ($ + 5) & 255
+    /// 0007  28 0A 00                        jmp func
+    /// ```
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        let mut file = None;
+        let mut i = 0;
+
+        while i < self.emitted.len() {
+            let emitted = &self.emitted[i];
+            i += 1;
+
+            let Some(span) = origin(&emitted.node) else {
+                continue;
+            };
+            // Shown with the expansion they are in
+            if matches!(&emitted.node, Node::Label(ln, _) if is_local_label(ln)) {
+                continue;
+            }
+            // Back in the fixed ROM at the end of a file
+            if let Node::Placement(
+                Placement {
+                    kind: PlacementKind::Bank(None),
+                    ..
+                },
+                _,
+            ) = &emitted.node
+            {
+                continue;
+            }
+
+            if file != Some(&span.file.path) {
+                file = Some(&span.file.path);
+                if !out.is_empty() {
+                    out.push('\n');
+                }
+                let _ = writeln!(out, "; {}", span.file.name());
+            }
+
+            match &emitted.node {
+                Node::Constant(..) => {
+                    let mut rows = self.bytes(emitted).chunks(ROW_BYTES);
+                    let first = rows.next().unwrap_or_default();
+                    self.row(&mut out, emitted.address, first, Some(span), "");
+                    for (n, bytes) in rows.enumerate() {
+                        let address = emitted.address + (n + 1) * ROW_BYTES;
+                        self.row(&mut out, address, bytes, None, "");
+                    }
+                }
+                Node::Instruction(inst) if !inst.expanded_from.is_empty() => {
+                    let mut expansion = vec![emitted];
+                    while let Some(next) = self.emitted.get(i) {
+                        match &next.node {
+                            Node::Instruction(other) if other.origin() == span => {
+                                expansion.push(next)
+                            }
+                            Node::Label(ln, _) if is_local_label(ln) => expansion.push(next),
+                            _ => break,
+                        }
+                        i += 1;
+                    }
+
+                    let end = expansion.last().map(|e| e.address + e.size).unwrap();
+                    let bytes = self.rom_bytes(emitted.bank, emitted.address..end);
+                    self.row(&mut out, emitted.address, bytes, Some(span), "");
+
+                    let indent = leading_whitespace(span.source_line());
+                    for emitted in expansion {
+                        let text = match &emitted.node {
+                            Node::Instruction(inst) => {
+                                format!("{indent}{}{inst}", "  ".repeat(inst.expanded_from.len()))
+                            }
+                            Node::Label(ln, _) => format!("{indent}  {ln}:"),
+                            _ => continue,
+                        };
+                        self.row(&mut out, emitted.address, self.bytes(emitted), None, &text);
+                    }
+                }
+                _ => self.row(
+                    &mut out,
+                    emitted.address,
+                    self.bytes(emitted),
+                    Some(span),
+                    "",
+                ),
+            }
+        }
+
+        out
+    }
+
+    fn bytes(&self, emitted: &Emitted) -> &[u8] {
+        self.rom_bytes(
+            emitted.bank,
+            emitted.address..emitted.address + emitted.size,
+        )
+    }
+
+    /// Write a row of the listing. Shows the source line of `span` if there is one,
+    /// otherwise `text`.
+    fn row(&self, out: &mut String, address: usize, bytes: &[u8], span: Option<&Span>, text: &str) {
+        let mut hex = bytes
+            .iter()
+            .take(if bytes.len() > ROW_BYTES {
+                ROW_BYTES - 1
+            } else {
+                ROW_BYTES
+            })
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        if bytes.len() > ROW_BYTES {
+            hex.push_str(" ..");
+        }
+
+        let (line, text) = match span {
+            Some(span) => (span.line.to_string(), span.source_line()),
+            None => (String::new(), text),
+        };
+
+        let row = format!(
+            "{address:04X}  {hex:<width$}  {line:>5}  {text}",
+            width = ROW_BYTES * 3 - 1
+        );
+        let _ = writeln!(out, "{}", row.trim_end());
+    }
+}
+
+/// The span of the source line that `node` was written on.
+pub(super) fn origin(node: &Node) -> Option<&Span> {
+    match node {
+        Node::Instruction(inst) => Some(inst.origin()),
+        node => node.span(),
+    }
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::compiler::Input;
+
+    #[test]
+    fn list_expansions() -> Result<()> {
+        let mut compiler = Compiler::new();
+        compiler.push(
+            Input::Raw(
+                r#"
+#[main]
+main:
+    call func
+func:
+    ret
+#[keep]
+#[const(DATA)] { 1, 2, 3, 4, 5, 6, 7 }
+"#
+                .to_string(),
+            ),
+            Arc::new(PathBuf::from("test")),
+        )?;
+        compiler.compile()?;
+
+        assert_eq!(
+            compiler.listing(),
+            r#"; raw
+0000  28 03 00               2  #[main]
+0003                         3  main:
+0003  58 00 58 0A 28 ..      4      call func
+0003  58 00                           push ($ + 7) >> 8
+0005  58 0A                           push ($ + 5) & 255
+0007  28 0A 00                        jmp func
+000A                         5  func:
+000A  64 65 20               6      ret
+000A  64                              pop %x
+000B  65                              pop %y
+000C  20                              jmp
+000D  01 02 03 04 05 06      8  #[const(DATA)] { 1, 2, 3, 4, 5, 6, 7 }
+0013  07
+"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use path_clean::clean;
+use serde::Deserialize;
+
+use super::{Output, OutputFormat, OutputKind, SearchPath};
+
+/// The file `asm build` looks for, in the directory it is given and the ones above it.
+pub const MANIFEST: &str = "cr8.toml";
+
+/// A `cr8.toml`, with every path made relative to the directory it is in.
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    /// The file to compile.
+    pub entry: PathBuf,
+    /// Directories to look for `#[use("...")]`d files in, like `-I`.
+    pub include: Vec<PathBuf>,
+    /// Where to write the binary, and in which format.
+    pub outputs: Vec<Output>,
+    /// `#[static]`s, like `-D`.
+    pub defines: IndexMap<String, usize>,
+    /// Directories of shared code, `#[use]`d by their name.
+    pub dependencies: IndexMap<String, PathBuf>,
+    pub symbols: bool,
+    pub listing: bool,
+    pub warn_truncation: bool,
+    pub optimize: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawManifest {
+    entry: PathBuf,
+    #[serde(default)]
+    include: Vec<PathBuf>,
+    #[serde(default)]
+    output: Vec<RawOutput>,
+    #[serde(default)]
+    defines: IndexMap<String, usize>,
+    #[serde(default)]
+    dependencies: IndexMap<String, Dependency>,
+    #[serde(default)]
+    symbols: bool,
+    #[serde(default)]
+    listing: bool,
+    #[serde(default)]
+    warn_truncation: bool,
+    #[serde(default)]
+    optimize: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RawOutput {
+    path: PathBuf,
+    #[serde(default)]
+    format: OutputFormat,
+    record_len: Option<usize>,
+    #[serde(default)]
+    base: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Dependency {
+    path: PathBuf,
+}
+
+impl Manifest {
+    /// Load the `cr8.toml` in `dir` or the closest directory above it. `dir` can also be the
+    /// manifest itself.
+    pub fn find(dir: &Path) -> Result<Self> {
+        if dir.is_file() {
+            return Self::load(dir);
+        }
+        let dir = clean(dir);
+        match dir
+            .ancestors()
+            .map(|dir| dir.join(MANIFEST))
+            .find(|path| path.is_file())
+        {
+            Some(path) => Self::load(&path),
+            None => bail!("No {MANIFEST} in {dir:?} or any directory above it"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&content, dir).with_context(|| format!("Invalid manifest {path:?}"))
+    }
+
+    /// Where `#[use("...")]` looks for files when building this.
+    pub fn search(&self) -> SearchPath {
+        SearchPath {
+            include: self.include.clone(),
+            libraries: self.dependencies.clone(),
+        }
+    }
+
+    fn parse(content: &str, dir: &Path) -> Result<Self> {
+        let raw: RawManifest = toml::from_str(content)?;
+        let path = |p: &Path| clean(dir.join(p));
+
+        let mut dependencies = IndexMap::new();
+        for (name, dependency) in raw.dependencies {
+            let at = path(&dependency.path);
+            if !at.is_dir() {
+                bail!("Dependency {name} at {at:?} is not a directory");
+            }
+            dependencies.insert(name, at);
+        }
+
+        let entry = path(&raw.entry);
+        let mut outputs = raw
+            .output
+            .into_iter()
+            .map(|output| Output {
+                kind: OutputKind::File(path(&output.path).to_string_lossy().to_string()),
+                format: output.format,
+                record_len: output.record_len,
+                base: output.base,
+            })
+            .collect::<Vec<_>>();
+        if outputs.is_empty() {
+            outputs.push(Output {
+                kind: OutputKind::File(entry.with_extension("bin").to_string_lossy().to_string()),
+                ..Default::default()
+            });
+        }
+
+        Ok(Self {
+            entry,
+            include: raw.include.iter().map(|p| path(p)).collect(),
+            outputs,
+            defines: raw.defines,
+            dependencies,
+            symbols: raw.symbols,
+            listing: raw.listing,
+            warn_truncation: raw.warn_truncation,
+            optimize: raw.optimize,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let dir = env::temp_dir();
+        let manifest = Manifest::parse(
+            r#"
+entry = "main.asm"
+include = ["../lib"]
+symbols = true
+
+[defines]
+LOGISIM = 1
+BASE = 0x8000
+
+[dependencies]
+shared = { path = ".." }
+
+[[output]]
+path = "build/game.bin"
+
+[[output]]
+path = "build/game.hex"
+format = "ihex"
+record-len = 32
+"#,
+            &dir.join("game"),
+        )?;
+
+        assert_eq!(manifest.entry, dir.join("game/main.asm"));
+        assert_eq!(manifest.include, [dir.join("lib")]);
+        assert_eq!(manifest.dependencies["shared"], dir);
+        assert_eq!(manifest.defines["BASE"], 0x8000);
+        assert!(manifest.symbols && !manifest.listing);
+        assert_eq!(manifest.outputs[0].path()?, dir.join("game/build/game.bin"));
+        assert!(matches!(manifest.outputs[1].format, OutputFormat::IntelHex));
+        assert_eq!(manifest.outputs[1].record_len, Some(32));
+
+        // Next to the entry if no output is given
+        let manifest = Manifest::parse(r#"entry = "main.asm""#, &dir)?;
+        assert_eq!(manifest.outputs[0].path()?, dir.join("main.bin"));
+
+        assert!(Manifest::parse(r#"entyr = "main.asm""#, &dir).is_err());
+        let err = Manifest::parse(
+            "entry = \"main.asm\"\n[dependencies]\nx = { path = \"nope\" }",
+            &dir,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Dependency x at {:?} is not a directory", dir.join("nope"))
+        );
+
+        Ok(())
+    }
+}
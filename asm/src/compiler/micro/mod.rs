@@ -4,9 +4,9 @@ use anyhow::{bail, Result};
 
 use super::{
     lex::{expect_complete, Lexable, Pragma},
-    Output,
+    Output, OutputFormat,
 };
-use super::{logisim_hex_file, Input};
+use super::{logisim_hex_file, FileSystem, Input, SearchPath};
 
 use indexmap::IndexMap;
 
@@ -118,7 +118,7 @@ pub struct Microcode(Vec<(u8, Vec<RawControlSignal>)>);
 impl TryFrom<Input> for Microcode {
     type Error = anyhow::Error;
     fn try_from(input: Input) -> Result<Self> {
-        let (buf, _) = input.source(None, None)?;
+        let (buf, _) = input.source(None, None, &SearchPath::default(), &FileSystem)?;
         let buf = buf.unwrap_or_default();
 
         let (prag, buf) = Pragma::lex(&buf)?;
@@ -163,9 +163,7 @@ impl TryFrom<Input> for Microcode {
                                     let variant = if imm { "imm" } else { "reg" };
                                     e.context(format!(
                                         "Operation \"{}\"\nVariant \"{}\" \nSignal {}",
-                                        op.to_string(),
-                                        variant,
-                                        i
+                                        op, variant, i
                                     ))
                                 })
                         })
@@ -223,19 +221,31 @@ impl Microcode {
     }
 }
 
+/// Write the 3 microcode ROM images into the directory `to`, as Logisim images unless
+/// `to` asks for Intel HEX or S-records.
 pub fn compile_to_logisim(input: Input, to: Output) -> Result<()> {
     let microcode = Microcode::try_from(input)?;
     let dir = to.path()?;
     fs::create_dir_all(&dir)?;
 
+    let ext = match to.format() {
+        OutputFormat::IntelHex => ".hex",
+        OutputFormat::SRecord => ".srec",
+        _ => "",
+    };
+
     for (i, bytes) in microcode.rom().iter().enumerate() {
         let mut file = OpenOptions::new()
             .write(true)
             .truncate(true)
             .append(false)
             .create(true)
-            .open(dir.join(format!("microcode-{i}")))?;
-        logisim_hex_file(bytes, 8, &mut file)?;
+            .open(dir.join(format!("microcode-{i}{ext}")))?;
+
+        match to.format() {
+            OutputFormat::Default | OutputFormat::Logisim => logisim_hex_file(bytes, 8, &mut file)?,
+            _ => to.encode(bytes, &mut file)?,
+        }
     }
     Ok(())
 }
@@ -1,24 +1,54 @@
 #![doc(alias = "assembler")]
 
-use anyhow::{anyhow, bail, Result};
-use indexmap::IndexMap;
-use path_clean::clean;
-use std::env;
+use anyhow::{bail, Result};
+use indexmap::{IndexMap, IndexSet};
 use std::sync::Arc;
 use std::{io::Write, path::PathBuf};
 
+mod builder;
 mod config;
 mod debug;
+mod diagnostic;
+mod hex;
 pub mod lex;
+mod listing;
+mod manifest;
 pub mod micro;
+mod object;
+mod peephole;
 mod resolver;
+mod size;
+mod source;
+mod symbols;
 
-use crate::compiler::lex::Node;
-use crate::op::Operation;
+use crate::compiler::lex::{
+    Constant, Data, Expr, Instruction, Node, Placement, PlacementKind, Region,
+};
+use crate::op::{Operation, Overflow};
 
+pub use builder::*;
 pub use config::*;
+pub use diagnostic::*;
+pub use hex::*;
+pub use manifest::*;
+pub use peephole::*;
+pub use size::*;
+pub use source::*;
+pub use symbols::*;
 
-use self::lex::{ignore_whitespace, Item, LexableWith, Macro};
+use object::ObjectState;
+use resolver::Module;
+pub(crate) use resolver::{check_reserved, is_local_label};
+
+use self::lex::{ignore_whitespace, recover, Item, LexableWith, Macro, SourceFile, Span};
+
+/// Bytes of ROM, which programs are placed at the start of the address space.
+pub const ROM_LEN: usize = 0x8000;
+/// Where the selected bank is mapped, and the code of `#[bank]`s is placed.
+pub const BANK_START: usize = 0x8000;
+pub const BANK_LEN: usize = 0x4000;
+/// Banks below this are the builtin RAM and the VRAM, so they can't hold code.
+pub const FIRST_ROM_BANK: usize = 2;
 
 #[derive(Debug, Default)]
 pub struct Compiler {
@@ -29,29 +59,80 @@ pub struct Compiler {
     last_label: String,
     pc: usize,
     files: Vec<Arc<PathBuf>>,
+    /// Where `#[use("...")]` looks for files.
+    search: SearchPath,
+    /// What files are read from.
+    sources: Box<dyn SourceProvider>,
+    /// Every module loaded so far, by path.
+    modules: IndexMap<String, Module>,
+    /// The module each file was loaded as.
+    file_modules: IndexMap<PathBuf, String>,
+    /// `#[static]`s set with [Compiler::define], which every module can use.
+    defines: Vec<String>,
     macros: IndexMap<String, Macro>,
     statics: IndexMap<String, usize>,
-    ram_locations: IndexMap<String, usize>,
-    ram_length: usize,
-    ram_origin: usize,
+    /// `#[static]`s defined with an expression, resolved when they are used.
+    static_exprs: IndexMap<String, (Expr, Span)>,
+    ram_locations: IndexMap<String, Variable>,
+    /// Initial bytes of `#[dyn]`s, copied to RAM at startup.
+    ram_init: IndexMap<String, (Constant, Span)>,
+    /// Where `#[dyn(zero)]` was set, if it was.
+    ram_zero: Option<Span>,
+    /// Where `#[dyn]`s are allocated, and the next free address in each.
+    regions: IndexMap<String, (Region, usize)>,
+    /// The region `#[dyn]`s are allocated in now.
+    region: String,
+    diagnostics: Vec<Diagnostic>,
+    emitted: Vec<Emitted>,
+    truncation: Level,
+    /// Set when compiling an object instead of a whole program.
+    object: Option<ObjectState>,
+    /// The contents of each ROM bank that code is placed in with `#[bank]`, from
+    /// [BANK_START].
+    pub rom_banks: IndexMap<usize, Vec<u8>>,
+    /// The ROM bank of the labels and `#[const]`s placed in one.
+    label_banks: IndexMap<String, usize>,
+    /// The `#[bank]` the file being read is in, and where it was set.
+    code_bank: Option<(usize, Span)>,
+    /// The ROM bank being placed or compiled into, `None` for the fixed ROM.
+    bank: Option<usize>,
+    /// Labels set with `#[keep]`, which are compiled even if nothing reaches them.
+    keep: IndexSet<String>,
+    /// The labels and `#[const]`s left out as nothing reaches them, and their size.
+    eliminated: IndexMap<String, usize>,
+    /// Iterations of the `#[repeat]`s expanded so far.
+    repeated: usize,
+    /// What the peephole optimizer rewrites, nothing unless optimizing.
+    rules: Vec<Box<dyn Rule>>,
 }
 
 impl Compiler {
     pub fn new() -> Self {
         let mut ctx = Self::default();
 
-        ctx.push(
-            Input::File("core".to_string()),
-            Arc::new(env::current_dir().unwrap()),
-        )
-        .unwrap();
+        ctx.push(Input::File("core".to_string()), Arc::default())
+            .unwrap();
 
         ctx
     }
 
     pub fn compile(&mut self) -> Result<()> {
-        self.resolve_macros()?;
-        self.resolve_labels()?;
+        self.abort_if_errors()?;
+        self.resolve_startup();
+        self.qualify_tree();
+        self.abort_if_errors()?;
+        if self.object.is_some() {
+            self.keep_own_nodes();
+        }
+        self.resolve_macros();
+        self.abort_if_errors()?;
+        self.resolve_far_calls();
+        self.eliminate_dead_code();
+        self.apply_rules();
+        self.resolve_labels();
+        self.abort_if_errors()?;
+        self.resolve_statics();
+        self.abort_if_errors()?;
 
         self.last_label = String::new();
 
@@ -60,83 +141,304 @@ impl Compiler {
         self.tree = vec![];
 
         for node in tree {
-            match node {
-                Node::Constant(_, mut val) => self.bin.append(&mut val.0),
-                Node::Label(ln) => {
-                    if !ln.contains('.') {
-                        self.last_label = ln.to_string();
+            if let Node::Placement(
+                Placement {
+                    kind: PlacementKind::Bank(bank),
+                    ..
+                },
+                _,
+            ) = &node
+            {
+                match self.check_rom_bank(bank.as_ref()) {
+                    Ok(bank) => self.select_bank(bank),
+                    Err(_) => unreachable!("checked when placed"),
+                }
+            }
+            let address = self.bin.len();
+
+            match &node {
+                Node::Constant(name, val, span) => match val.compile(self) {
+                    Ok((mut bytes, overflows)) => {
+                        for overflow in overflows {
+                            let diagnostic = self.constant_overflow(name, val, span, overflow);
+                            self.diagnostics.push(diagnostic);
+                        }
+                        self.bin.append(&mut bytes);
+                    }
+                    Err(e) => self
+                        .diagnostics
+                        .push(Diagnostic::error(format!("{e:#}"), Some(span.clone()))),
+                },
+                Node::Label(ln, _) if !ln.contains('.') => {
+                    self.last_label = ln.to_string();
+                }
+                Node::Placement(placement, span) => {
+                    let padded = placement.target(self.bin.len(), self).and_then(|target| {
+                        let fill = placement.fill.resolve(self)?;
+                        if let Some(overflow) = Overflow::check(1, fill, 8) {
+                            bail!(
+                                "The fill byte is {} ({:#X}), which does not fit in 8 bits",
+                                overflow.value,
+                                overflow.value
+                            );
+                        }
+                        Ok((target, fill as u8))
+                    });
+                    match padded {
+                        Ok((target, fill)) => self.bin.resize(target, fill),
+                        Err(e) => self
+                            .diagnostics
+                            .push(Diagnostic::error(format!("{e:#}"), Some(span.clone()))),
                     }
                 }
                 Node::Instruction(inst) => {
-                    let op = Operation::try_from(inst.id.as_str())
-                        .map_err(|_| anyhow!("Invalid operation {:#?}", inst.id))?;
+                    let compiled = Operation::try_from(inst.id.as_str())
+                        .map_err(|_| anyhow::anyhow!("Invalid operation {:#?}", inst.id))
+                        .and_then(|op| op.compile(&inst.args, self));
 
-                    self.bin.append(&mut op.compile(inst.args, self)?);
+                    match compiled {
+                        Ok((mut bytes, overflows)) => {
+                            for overflow in overflows {
+                                let diagnostic = self.overflow(inst, overflow);
+                                self.diagnostics.push(diagnostic);
+                            }
+                            self.bin.append(&mut bytes);
+                        }
+                        Err(e) => self.diagnostics.push(Diagnostic::at_instruction(
+                            Level::Error,
+                            format!("{e:#}"),
+                            inst,
+                        )),
+                    }
                 }
                 _ => {}
             }
+
+            self.emitted.push(Emitted {
+                address,
+                size: self.bin.len() - address,
+                node,
+                bank: self.bank,
+            });
+        }
+        self.select_bank(None);
+        for bin in self.rom_banks.values_mut() {
+            bin.drain(..BANK_START);
         }
 
-        Ok(())
+        self.abort_if_errors()
     }
 
     pub fn push(&mut self, input: Input, from: Arc<PathBuf>) -> Result<()> {
-        let content = {
-            let (content, path) = input.source(Some(&from), Some(&self.files))?;
+        let _ = self.include(input, from, None);
+        self.abort_if_errors()
+    }
+
+    /// Define a `#[static]`, like `-D NAME=VALUE` does, for the sources pushed after this.
+    pub fn define(&mut self, name: impl Into<String>, value: usize) -> Result<()> {
+        let name = name.into();
+        if self.is_static(&name) {
+            bail!("Attempted to define {name} twice");
+        }
+        self.statics.insert(name.clone(), value);
+        self.defines.push(name);
+        Ok(())
+    }
+
+    /// Look for `#[use("...")]`d files in `search` too, for the sources pushed after this.
+    pub fn set_search_path(&mut self, search: SearchPath) {
+        self.search = search;
+    }
+
+    /// Read the files pushed after this, and those they use, from `sources`.
+    pub fn set_sources(&mut self, sources: impl SourceProvider + 'static) {
+        self.sources = Box::new(sources);
+    }
+
+    /// Report immediates that don't fit in their encoding at `level` instead of as errors.
+    pub fn set_truncation_level(&mut self, level: Level) {
+        self.truncation = level;
+    }
+
+    fn overflow(&self, inst: &Instruction, overflow: Overflow) -> Diagnostic {
+        let Overflow {
+            operand,
+            value,
+            bits,
+        } = overflow;
+        let arg = &inst.args[operand];
+
+        let mut diagnostic = Diagnostic::at_instruction(
+            self.truncation,
+            format!(
+                "Operand {} of `{inst}` is {value} ({value:#X}), which does not fit in {bits} bits",
+                operand + 1,
+            ),
+            inst,
+        );
+        if bits == 8 {
+            diagnostic.children.insert(
+                0,
+                Diagnostic::note(format!("use `lo({arg})` to keep only the low byte"), None),
+            );
+        }
+        diagnostic
+    }
+
+    fn constant_overflow(
+        &self,
+        name: &str,
+        constant: &Constant,
+        span: &Span,
+        overflow: Overflow,
+    ) -> Diagnostic {
+        let Overflow {
+            operand,
+            value,
+            bits,
+        } = overflow;
+
+        let mut diagnostic = Diagnostic::new(
+            self.truncation,
+            format!(
+                "Entry {} of #[const({name})] is {value} ({value:#X}), which does not fit in {bits} bits",
+                operand + 1,
+            ),
+            Some(span.clone()),
+        );
+        if let Data::Byte(e) = &constant.0[operand] {
+            diagnostic = diagnostic.with(Diagnostic::note(
+                format!("use `lo({e})` to keep only the low byte"),
+                None,
+            ));
+        }
+        diagnostic
+    }
+
+    /// Every error and warning reported so far.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// The labels and `#[const]`s that [Compiler::compile] left out as nothing reaches them
+    /// from `#[main]`, with the bytes each took.
+    pub fn eliminated(&self) -> &IndexMap<String, usize> {
+        &self.eliminated
+    }
+
+    /// Every macro that has been defined so far.
+    pub fn macros(&self) -> &IndexMap<String, Macro> {
+        &self.macros
+    }
+
+    /// Every `#[static]` that has been defined so far, except for the ones that depend on
+    /// something that isn't known yet (like a label before [Compiler::compile]).
+    pub fn statics(&self) -> IndexMap<String, usize> {
+        let mut statics = self.statics.clone();
+        for name in self.static_exprs.keys() {
+            if let Ok(value) = Expr::Variable(name.clone()).resolve(self) {
+                statics.insert(name.clone(), value);
+            }
+        }
+        statics
+    }
+
+    pub(crate) fn is_static(&self, name: &str) -> bool {
+        self.statics.contains_key(name) || self.static_exprs.contains_key(name)
+    }
+
+    /// Lex and resolve the meta of `input`, and give the module it was loaded as. `at` is the
+    /// `#[use]` it comes from, `None` if it is part of the program itself. Problems are
+    /// recorded as diagnostics, pointing at `at` if the file itself could not be found.
+    pub(crate) fn include(
+        &mut self,
+        input: Input,
+        from: Arc<PathBuf>,
+        at: Option<&Span>,
+    ) -> Option<String> {
+        let (file, module) = {
+            let (content, path) =
+                match input.source(Some(&from), Some(&self.files), &self.search, &*self.sources) {
+                    Ok(source) => source,
+                    Err(e) => {
+                        self.diagnostics
+                            .push(Diagnostic::error(format!("{e:#}"), at.cloned()));
+                        return None;
+                    }
+                };
 
-            self.files.push(Arc::new(path));
+            // Modules are only loaded once, however many others use them
+            if let Some(module) = self.file_modules.get(&path).filter(|_| at.is_some()) {
+                return Some(module.clone());
+            }
+            let module = match self.add_module(&path, at.is_none() && self.object.is_none()) {
+                Ok(module) => module,
+                Err(e) => {
+                    self.diagnostics
+                        .push(Diagnostic::error(format!("{e:#}"), at.cloned()));
+                    return None;
+                }
+            };
+
+            if let Some(object) = self.object.as_mut().filter(|_| at.is_none()) {
+                object.own.insert(module.clone());
+            }
+            let path = Arc::new(path);
+            self.files.push(path.clone());
             match content {
-                Some(c) => c,
-                None => return Ok(()),
+                Some(c) => (Arc::new(SourceFile::new(path, c)), module),
+                None => return Some(module),
             }
         };
 
-        let mut buf = content.as_str();
+        let mut buf = file.content.as_str();
         let mut nodes = vec![];
-        let path = self.files.last().unwrap();
 
         loop {
             buf = ignore_whitespace(buf);
             if buf.is_empty() {
                 break;
             }
-            let (item, b) = Item::lex_with(buf, path.clone()).map_err(|e| {
-                e.context(find_err_location(
-                    buf,
-                    &content,
-                    clean(path.as_path()).display().to_string().as_str(),
-                ))
-            })?;
-            nodes.push(item);
-            buf = b;
+            match Item::lex_with(buf, &file) {
+                Ok((item, b)) => {
+                    nodes.push(item);
+                    buf = b;
+                }
+                Err(e) => {
+                    let diagnostic = Diagnostic::from_lex_error(&e, &file, buf);
+                    let at = diagnostic
+                        .span
+                        .as_ref()
+                        .map(|s| s.offset)
+                        .unwrap_or_default()
+                        .saturating_sub(file.offset_of(buf));
+                    self.diagnostics.push(diagnostic);
+                    buf = recover(buf, at);
+                }
+            }
         }
 
-        self.resolve_meta(nodes)?;
+        // A #[bank] lasts up to the end of its file, and doesn't take in the files it uses
+        let outer = self.code_bank.take();
+        if let Some((_, span)) = &outer {
+            self.enter_bank(None, span.clone());
+        }
+        self.resolve_meta(nodes);
+        if let Some((_, span)) = self.code_bank.take().or(outer.clone()) {
+            self.enter_bank(outer.as_ref().map(|(bank, _)| *bank), span);
+        }
+        self.code_bank = outer;
 
-        Ok(())
+        Some(module)
     }
-}
-
-pub fn find_err_location(at: &str, file_content: &str, file_path: &str) -> String {
-    let mut lines = 0;
-    let mut col = 0;
-    let Some(idx) = file_content.find(at) else {
-        return file_path.to_string();
-    };
-
-    for (i, ch) in file_content.chars().take(idx).enumerate() {
-        if i == idx {
-            break;
-        }
 
-        if ch == '\n' {
-            lines += 1;
-            col = 0;
-        } else {
-            col += 1;
+    /// Fail with every error reported so far, if there are any.
+    fn abort_if_errors(&self) -> Result<()> {
+        if self.diagnostics.iter().any(Diagnostic::is_error) {
+            bail!(Diagnostics(self.diagnostics.clone()));
         }
+        Ok(())
     }
-    format!("{file_path}:{}:{}", lines + 1, col + 1)
 }
 
 // v3.0 hex words addressed
@@ -171,3 +473,477 @@ pub fn logisim_hex_file<W: Write + Sync>(
     }
     Ok(())
 }
+
+/// Read a Logisim memory image back into bytes. Understands the `v3.0 hex words addressed`
+/// files written by [logisim_hex_file] as well as `v2.0 raw` images (with `N*XX` runs).
+pub fn read_logisim_hex_file(content: &str) -> Result<Vec<u8>> {
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or_default().trim();
+    let byte = |b: &str| {
+        u8::from_str_radix(b, 16)
+            .map_err(|_| anyhow::anyhow!("Invalid byte {b:#?} in Logisim image"))
+    };
+
+    let mut bin = vec![];
+
+    match header {
+        "v3.0 hex words addressed" => {
+            for line in lines.filter(|l| !l.trim().is_empty()) {
+                let Some((addr, bytes)) = line.split_once(':') else {
+                    bail!("Expected \"address: bytes\" in Logisim image, found {line:#?}");
+                };
+                let addr = usize::from_str_radix(addr.trim(), 16)?;
+                for (addr, b) in (addr..).zip(bytes.split_whitespace()) {
+                    if bin.len() <= addr {
+                        bin.resize(addr + 1, 0);
+                    }
+                    bin[addr] = byte(b)?;
+                }
+            }
+        }
+        "v2.0 raw" => {
+            for word in
+                lines.flat_map(|l| l.split('#').next().unwrap_or_default().split_whitespace())
+            {
+                match word.split_once('*') {
+                    Some((n, b)) => {
+                        let b = byte(b)?;
+                        bin.extend(std::iter::repeat_n(b, n.parse()?));
+                    }
+                    None => bin.push(byte(word)?),
+                }
+            }
+        }
+        h => bail!("Unknown Logisim image format {h:#?}"),
+    }
+
+    Ok(bin)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    pub(crate) fn compile(source: &str, truncation: Level) -> Result<Compiler> {
+        compile_with(MemoryFiles::new(), source, truncation)
+    }
+
+    /// [compile] with `files` to `#[use]` and include.
+    pub(crate) fn compile_with(
+        files: MemoryFiles,
+        source: &str,
+        truncation: Level,
+    ) -> Result<Compiler> {
+        let mut compiler = Compiler::builder()
+            .sources(files)
+            .truncation(truncation)
+            .input(Input::Raw(source.to_string()))
+            .build()?;
+        compiler.compile()?;
+        Ok(compiler)
+    }
+
+    /// The errors compiling `source` fails with.
+    pub(crate) fn compile_errors(source: &str) -> Vec<Diagnostic> {
+        compile_errors_with(MemoryFiles::new(), source)
+    }
+
+    /// [compile_errors] with `files` to `#[use]` and include.
+    pub(crate) fn compile_errors_with(files: MemoryFiles, source: &str) -> Vec<Diagnostic> {
+        match compile_with(files, source, Level::Error) {
+            Ok(_) => panic!("{source:?} compiled"),
+            Err(err) => match err.downcast::<Diagnostics>() {
+                Ok(Diagnostics(errors)) => errors,
+                Err(err) => panic!("{err}"),
+            },
+        }
+    }
+
+    #[test]
+    fn lex_errors() -> Result<()> {
+        // Lexing goes on after an error, so both are reported
+        let errors = compile_errors("#[bogus]\nmain:\n    mov %q, 1\n    nop");
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].message.starts_with("Unknown meta keyword"));
+        assert_eq!(errors[0].span.as_ref().unwrap().line, 1);
+        assert_eq!(errors[1].message, "Unknown register \"q\"");
+        assert_eq!(errors[1].span.as_ref().unwrap().line, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn macro_labels() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[macro] spin: {
+    ($n: lit) => {
+        mov %a, $n
+    loop:
+        dec %a
+        jnz loop, %a
+    .done:
+    }
+}
+main:
+    spin 3
+    spin 4
+    jmp .end
+.end:
+"#,
+            Level::Error,
+        )?;
+
+        // Every expansion gets its own labels, and they don't start a new `.sub` scope
+        assert_eq!(compiler.labels["spin@1.loop"], 2);
+        assert_eq!(compiler.labels["spin@1.done"], 9);
+        assert_eq!(compiler.labels["spin@5.loop"], 11);
+        assert_eq!(compiler.labels["main.end"], 21);
+        assert_eq!(compiler.bin[6..9], [0x18, 2, 0]);
+        assert_eq!(compiler.bin[15..18], [0x18, 11, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn conditional() -> Result<()> {
+        let source = r#"
+#[static(VERSION: 2)]
+#[cfg(LOGISIM)] {
+    #[static(SCREEN: 0x10)]
+} #[else] {
+    #[static(SCREEN: 0x20)]
+}
+#[if(VERSION >= 2 && SCREEN == 0x10)] {
+    mov %a, 1
+} #[else] #[if(VERSION >= 2)] {
+    mov %a, 2
+} #[else] {
+    mov %a, 3
+}
+"#;
+
+        let compiler = compile(source, Level::Error)?;
+        assert_eq!(compiler.statics["SCREEN"], 0x20);
+        assert_eq!(compiler.bin, [0x08, 2]);
+
+        let mut compiler = Compiler::new();
+        compiler.define("LOGISIM", 1)?;
+        compiler.push(
+            Input::Raw(source.to_string()),
+            Arc::new(PathBuf::from("test")),
+        )?;
+        compiler.compile()?;
+        assert_eq!(compiler.statics["SCREEN"], 0x10);
+        assert_eq!(compiler.bin, [0x08, 1]);
+
+        assert!(compiler.define("LOGISIM", 0).is_err());
+        assert!(compile("#[if(UNKNOWN)] { }", Level::Error).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeat() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[static(N: 2)]
+#[repeat(N, $i)] {
+    #[repeat(2, $j)] {
+        mov %a, $i * 0x10 + $j
+    }
+}
+#[const(SQUARES)] { #[repeat(N + 2, $i)] { $i * $i }, 0xFF }
+"#,
+            Level::Error,
+        )?;
+
+        assert_eq!(
+            compiler.bin,
+            [0x08, 0x00, 0x08, 0x01, 0x08, 0x10, 0x08, 0x11, 0, 1, 4, 9, 0xFF]
+        );
+        assert!(compile("#[repeat(0x10_0000, $i)] { }", Level::Error).is_err());
+        // Nested ones add up
+        let errors = compile_errors(
+            "#[repeat(0x100, $i)] {\n    #[repeat(0x100, $j)] {\n        nop\n    }\n}",
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "Cannot #[repeat] 256 times, #[repeat]s would add up to more than 32768 iterations"
+        );
+        assert!(compile(
+            "#[const(T)] { #[repeat(0x10000, $i)] { #[repeat(0x10000, $j)] { 0 } } }",
+            Level::Error
+        )
+        .is_err());
+
+        let errors = compile_errors(
+            "#[repeat(2, $i)] {\n    #[if($i == 1)] {\n    l:\n    }\n    mov %a, $i\n}",
+        );
+        assert_eq!(
+            errors[0].message,
+            "Label \"l\" can't be in a #[repeat], as every iteration would define it"
+        );
+        assert_eq!(errors[0].span.as_ref().unwrap().line, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dead_code() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[main]
+main:
+    call used
+    jmp .end
+.end:
+    halt
+after_halt:
+    ret
+used:
+    lw %a, table
+    ret
+unused:
+    call used
+    lw %a, unused_table
+    ret
+#[const(table)] { 1, 2 }
+#[const(unused_table)] { 3 }
+#[keep]
+handler:
+    ret
+#[static(ENTRY: entry)]
+entry:
+    nop
+after_entry:
+    ret
+"#,
+            Level::Error,
+        )?;
+        for label in [
+            "main",
+            "main.end",
+            "used",
+            "table",
+            "handler",
+            "entry",
+            "after_entry",
+        ] {
+            assert!(compiler.labels.contains_key(label), "{label} was left out");
+        }
+        let own = compiler
+            .eliminated
+            .iter()
+            .filter(|(name, _)| !name.contains("::"))
+            .map(|(name, size)| (name.as_str(), *size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            own,
+            [("after_halt", 3), ("unused", 13), ("unused_table", 1)]
+        );
+        assert!(compiler.diagnostics.iter().any(|d| d.level == Level::Note
+            && d.message.starts_with("Left out")
+            && d.message.contains("unused_table")));
+
+        // Anything could be called without a #[main]
+        let compiler = compile("unused:\n    ret", Level::Error)?;
+        assert!(compiler.labels.contains_key("unused"));
+        assert!(compiler.eliminated.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn search_path() -> Result<()> {
+        let files = MemoryFiles::new()
+            .with("inc/util.asm", "#[pub]\nutil:\n    ret")
+            .with("sprites/mod.asm", "#[pub]\nsprites:\n    ret")
+            .with("sprites/ship.asm", "#[pub]\nship:\n    ret");
+        let src = "#[use(\"util\")]\n#[use(\"shared\")]\n#[use(\"shared/ship\")]\n\
+                   call util\ncall sprites\ncall ship";
+
+        let mut compiler = Compiler::builder()
+            .sources(files.clone())
+            .search_path(SearchPath {
+                include: vec!["inc".into()],
+                libraries: IndexMap::from([("shared".to_string(), "sprites".into())]),
+            })
+            .input(Input::Raw(src.to_string()))
+            .build()?;
+        compiler.compile()?;
+        assert_eq!(compiler.labels["util::util"], 0);
+        assert!(compiler.labels.contains_key("sprites::sprites"));
+        assert!(compiler.labels.contains_key("ship::ship"));
+
+        assert!(compile_with(files, src, Level::Error).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn builder() -> Result<()> {
+        let build = Compiler::builder()
+            .sources(MemoryFiles::new().with("main.asm", "mov %a, 300\nmov %b, WIDTH"))
+            .define("WIDTH", 4)
+            .truncation(Level::Warning)
+            .input(Input::File("main.asm".to_string()))
+            .compile()?;
+        assert_eq!(build.bin, [0x08, 44, 0x09, 4]);
+        assert_eq!(build.symbols.files.last().unwrap(), "main.asm");
+        assert_eq!(build.diagnostics[0].level, Level::Warning);
+
+        let err = Compiler::builder()
+            .sources(BuiltinOnly)
+            .input(Input::File("main.asm".to_string()))
+            .compile()
+            .unwrap_err();
+        assert!(err.to_string().contains("No file \"main.asm\" found"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn objects() -> Result<()> {
+        use crate::link::{link, RelocationKind, Script, Section, Target};
+
+        let files = MemoryFiles::new()
+            .with(
+                "main.asm",
+                "#[use(\"lib\")]\n#[dyn(count: 1)]\n#[main]\nmain:\n    sw count, %a\n    \
+                 lw %a, lib::total + 1\n    mov %b, hi(lib::table)\n    jmp main",
+            )
+            .with(
+                "lib.asm",
+                "#[pub dyn(total: 2)]\n#[pub]\nlib:\n    ret\n#[pub const(table)] { 1, word(lib) }",
+            );
+        let object = |file: &str| {
+            Compiler::builder()
+                .sources(files.clone())
+                .input(Input::File(file.to_string()))
+                .compile_object()
+        };
+        let main = object("main.asm")?;
+        let lib = object("lib.asm")?;
+
+        // What other objects define is left out, and filled in with relocations
+        assert!(main.entry && !lib.entry);
+        assert_eq!(main.text.len(), 3 + 3 + 3 + 2 + 3);
+        assert_eq!(main.sections["ram"], 1);
+        assert_eq!(lib.sections["ram"], 2);
+        assert_eq!(lib.symbols["lib::table"].section, Section::Text);
+        assert_eq!(
+            lib.symbols["lib::total"].section,
+            Section::Ram("ram".into())
+        );
+        let total = &main.relocations[2];
+        assert_eq!(total.target, Target::Symbol("lib::total".into()));
+        assert_eq!((total.offset, total.addend), (7, 1));
+        assert_eq!(main.relocations[3].kind, RelocationKind::Hi);
+
+        let linked = link(
+            &[
+                ("lib.o".into(), lib.clone()),
+                ("main.o".into(), main.clone()),
+            ],
+            &Script::default(),
+        )?;
+        assert_eq!(linked.symbols["main"], 3);
+        assert_eq!(linked.symbols["lib::lib"], 14);
+        assert_eq!(linked.symbols["lib::total"], 0xC001);
+        assert_eq!(linked.bin[..3], [0x28, 3, 0]);
+        assert_eq!(linked.bin[4..6], [0, 0xC0]);
+        assert_eq!(linked.bin[7..9], [2, 0xC0]);
+        assert_eq!(linked.bin[10], 0);
+        let table = linked.symbols["lib::table"];
+        assert_eq!(linked.bin[table..], [1, 14, 0]);
+
+        let script = Script::parse("rom: &0x0000, 0x8000\nram: &0xC000, 2")?;
+        let err = link(
+            &[("main.o".into(), main.clone()), ("lib.o".into(), lib)],
+            &script,
+        );
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "lib.o needs 2 bytes of ram, which only has 1 left"
+        );
+        let err = link(&[("main.o".into(), main)], &Script::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "main.o uses lib::total, which no object defines"
+        );
+
+        let errors = |src: &str| {
+            let err = Compiler::builder()
+                .input(Input::Raw(src.to_string()))
+                .compile_object()
+                .unwrap_err();
+            match err.downcast::<Diagnostics>() {
+                Ok(Diagnostics(errors)) => errors,
+                Err(err) => panic!("{err}"),
+            }
+        };
+        assert!(errors("#[org(4)]\nmov %a, 1")[0]
+            .message
+            .starts_with("#[org] cannot be used in an object"));
+        assert!(
+            errors("label:\n    mov %a, lo(label)\n    mov %b, label")[0]
+                .message
+                .starts_with("`raw::label` is an address that is only known once linked")
+        );
+        assert!(errors("label:\n    mov %a, lo(label * 2)")[0]
+            .message
+            .starts_with("`raw::label * 2` depends on an address"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn range_check() -> Result<()> {
+        let errors = compile_errors("mov %a, 300");
+        assert_eq!(
+            errors[0].message,
+            "Operand 2 of `mov %a, 300` is 300 (0x12C), which does not fit in 8 bits"
+        );
+        assert_eq!(
+            errors[0].children[0].message,
+            "use `lo(300)` to keep only the low byte"
+        );
+
+        // Named through the macro it was expanded from
+        let errors = compile_errors("#[static(X: 0x1FF)]\nsw X * 0x100, 1");
+        assert_eq!(
+            errors[0].message,
+            "Operand 1 of `sw X * 0x0100, %f` is 130816 (0x1FF00), which does not fit in 16 bits"
+        );
+        assert_eq!(
+            errors[0].children[0].message,
+            "in this expansion of macro \"sw\""
+        );
+
+        // Fine: negative numbers, explicit truncation
+        compile("mov %a, -1\nmov %a, lo(300)\nmov %a, 255", Level::Error)?;
+
+        let compiler = compile("mov %a, 300", Level::Warning)?;
+        assert_eq!(compiler.diagnostics()[0].level, Level::Warning);
+        assert_eq!(compiler.bin[1], 300u16 as u8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn logisim_roundtrip() -> Result<()> {
+        let bin = (0..40).collect::<Vec<u8>>();
+        let mut file = vec![];
+        logisim_hex_file(&bin, 16, &mut file)?;
+        assert_eq!(read_logisim_hex_file(&String::from_utf8(file)?)?, bin);
+
+        assert_eq!(
+            read_logisim_hex_file("v2.0 raw\n01 3*ff 02\n")?,
+            vec![1, 0xFF, 0xFF, 0xFF, 2]
+        );
+
+        Ok(())
+    }
+}
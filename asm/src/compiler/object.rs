@@ -0,0 +1,352 @@
+use std::cell::{Cell, RefCell};
+
+use anyhow::{bail, Result};
+use indexmap::{IndexMap, IndexSet};
+
+use super::lex::{Expr, ExprOperation, Node, Span, UnaryOperation};
+use super::{Compiler, Diagnostic};
+use crate::link::{Object, Relocation, RelocationKind, Section, Symbol, Target};
+
+/// What a [Compiler] keeps track of while compiling an [Object].
+#[derive(Debug, Default)]
+pub(crate) struct ObjectState {
+    /// The modules of the files pushed, which are the ones compiled into the object.
+    pub(crate) own: IndexSet<String>,
+    /// Names defined by the modules that are only `#[use]`d, which other objects define.
+    pub(crate) externs: IndexSet<String>,
+    /// `#[dyn]`s the linker places, with their section and offset in it.
+    pub(crate) placed: IndexMap<String, (String, usize)>,
+    /// The bytes each section takes so far.
+    pub(crate) sections: IndexMap<String, usize>,
+    pub(crate) relocations: RefCell<Vec<Relocation>>,
+    /// The largest `#[align]`.
+    pub(crate) align: Cell<usize>,
+    pub(crate) entry: bool,
+}
+
+/// What an expression comes to in an object.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Address {
+    Known(usize),
+    /// `kind` of the address of `target` plus `addend`, known once linked.
+    Linked {
+        target: Target,
+        addend: usize,
+        kind: RelocationKind,
+    },
+}
+
+impl Compiler {
+    /// Compile the files pushed after this into an [Object] of their own, with
+    /// [Compiler::compile_object]. The modules they `#[use]` are left for other objects to
+    /// define, and `#[dyn]`s for the linker to place.
+    pub fn set_object_mode(&mut self) {
+        self.object = Some(ObjectState::default());
+        // Including the default one `core` sets, which the linker script places instead
+        self.regions.clear();
+        self.region = String::new();
+    }
+
+    /// [Compiler::compile] the files pushed since [Compiler::set_object_mode].
+    pub fn compile_object(&mut self) -> Result<Object> {
+        if self.object.is_none() {
+            bail!("Not compiling an object, call Compiler::set_object_mode before pushing files");
+        }
+        // Copying values to RAM at startup only works with every #[dyn] in one place
+        for (name, (_, span)) in &self.ram_init {
+            self.diagnostics.push(Diagnostic::error(
+                format!(
+                    "#[dyn] {name} cannot be initialized in an object, only in a whole program"
+                ),
+                Some(span.clone()),
+            ));
+        }
+        if let Some(span) = self.ram_zero.take().filter(|span| !self.is_foreign(span)) {
+            self.diagnostics.push(Diagnostic::error(
+                "#[dyn(zero)] cannot be used in an object, only in a whole program",
+                Some(span),
+            ));
+        }
+        self.ram_init.clear();
+
+        self.compile()?;
+
+        let Some(object) = &self.object else {
+            unreachable!("checked above");
+        };
+        let mut symbols = IndexMap::new();
+        for (name, address) in self.labels.iter().filter(|(name, _)| !name.contains('@')) {
+            symbols.insert(name.clone(), symbol(Section::Text, *address, None));
+        }
+        for (name, var) in &self.ram_locations {
+            symbols.insert(
+                name.clone(),
+                symbol(Section::Absolute, var.address, var.bank),
+            );
+        }
+        for (name, (section, offset)) in &object.placed {
+            symbols.insert(
+                name.clone(),
+                symbol(Section::Ram(section.clone()), *offset, None),
+            );
+        }
+
+        Ok(Object {
+            text: self.bin.clone(),
+            align: object.align.get().max(1),
+            entry: object.entry,
+            sections: object.sections.clone(),
+            symbols,
+            relocations: object.relocations.take(),
+        })
+    }
+
+    pub(crate) fn is_object(&self) -> bool {
+        self.object.is_some()
+    }
+
+    /// In an object, whether `span` is in a module another object defines.
+    pub(crate) fn is_foreign(&self, span: &Span) -> bool {
+        self.object
+            .as_ref()
+            .is_some_and(|object| !object.own.contains(self.module_of(span)))
+    }
+
+    /// Leave `name` for another object to define.
+    pub(crate) fn add_extern(&mut self, name: String) {
+        if let Some(object) = &mut self.object {
+            object.externs.insert(name);
+        }
+    }
+
+    /// Start the object at a multiple of `n`, for an `#[align(n)]`.
+    pub(crate) fn align_object(&self, n: usize) {
+        if let Some(object) = &self.object {
+            object.align.set(object.align.get().max(n));
+        }
+    }
+
+    /// Take the nodes of other modules out of the tree, and remember what they define.
+    pub(crate) fn keep_own_nodes(&mut self) {
+        let mut last_label = String::new();
+        for node in std::mem::take(&mut self.tree) {
+            if !node.span().is_some_and(|span| self.is_foreign(span)) {
+                self.tree.push(node);
+                continue;
+            }
+            match node {
+                Node::Label(ln, _) if ln.starts_with('.') => {
+                    self.add_extern(format!("{last_label}{ln}"));
+                }
+                Node::Label(ln, _) => {
+                    if !ln.contains('.') {
+                        last_label = ln.clone();
+                    }
+                    self.add_extern(ln);
+                }
+                Node::Constant(name, ..) => self.add_extern(name),
+                _ => {}
+            }
+        }
+    }
+
+    /// The value of the `#[static]` `name`, `None` in an object if it is an address that is
+    /// only known once linked.
+    pub(crate) fn resolve_static(&self, name: &str) -> Result<Option<usize>> {
+        let var = Expr::Variable(name.to_string());
+        match self.object {
+            Some(_) => match self.address(&var, &mut vec![])? {
+                Address::Known(value) => Ok(Some(value)),
+                Address::Linked { .. } => Ok(None),
+            },
+            None => var.resolve(self).map(Some),
+        }
+    }
+
+    /// The value of `expr`, encoded into `bits` at `at` in the binary. In an object,
+    /// addresses that are only known once linked are 0, with a relocation to fill them in.
+    pub(crate) fn immediate(&self, expr: &Expr, at: usize, bits: u32) -> Result<usize> {
+        let Some(object) = &self.object else {
+            return expr.resolve(self);
+        };
+        match self.address(expr, &mut vec![])? {
+            Address::Known(value) => Ok(value),
+            Address::Linked {
+                target,
+                addend,
+                kind,
+            } => {
+                if kind == RelocationKind::Word && bits < 16 {
+                    bail!(
+                        "`{expr}` is an address that is only known once linked, use lo() or hi() to take one of its bytes"
+                    );
+                }
+                object.relocations.borrow_mut().push(Relocation {
+                    offset: at,
+                    kind,
+                    target,
+                    addend,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    /// [Expr::resolve], with addresses the linker places kept apart from the numbers added
+    /// to them. `statics` are the `#[static]`s being resolved, as in [Expr::resolve].
+    fn address(&self, expr: &Expr, statics: &mut Vec<String>) -> Result<Address> {
+        let Some(object) = &self.object else {
+            return expr.resolve(self).map(Address::Known);
+        };
+        let linked = |target, addend| Address::Linked {
+            target,
+            addend,
+            kind: RelocationKind::Word,
+        };
+        let text = |addend| linked(Target::Section(Section::Text), addend);
+
+        Ok(match expr {
+            Expr::Literal(lit) => Address::Known(*lit),
+            Expr::Variable(var) => {
+                let sub = format!("{}{var}", self.last_label);
+                if var == "$" {
+                    text(self.bin.len())
+                } else if let Some(label) = self.labels.get(var) {
+                    text(*label)
+                } else if let Some(stat) = self.statics.get(var) {
+                    Address::Known(*stat)
+                } else if let Some((expr, span)) = self.static_exprs.get(var) {
+                    let circular = statics.contains(var);
+                    statics.push(var.to_string());
+                    if circular {
+                        bail!(
+                            "#[static] {var} is defined in terms of itself: {}",
+                            statics.join(" -> ")
+                        );
+                    }
+                    let expr = self.qualify_expr(expr, self.module_of(span))?;
+                    let value = self.address(&expr, statics)?;
+                    statics.pop();
+                    value
+                } else if let Some(label) = self.labels.get(&sub) {
+                    text(*label)
+                } else if let Some((section, offset)) = object.placed.get(var) {
+                    linked(Target::Section(Section::Ram(section.clone())), *offset)
+                } else if let Some(var) = self.ram_locations.get(var) {
+                    Address::Known(var.address)
+                } else if object.externs.contains(var) {
+                    linked(Target::Symbol(var.clone()), 0)
+                } else {
+                    bail!("Unknown variable: {var:#?}");
+                }
+            }
+            Expr::Unary { op, expr: inner } => match (op, self.address(inner, statics)?) {
+                (UnaryOperation::Bank, Address::Known(_)) => Address::Known(expr.resolve(self)?),
+                (_, Address::Known(value)) => Address::Known(op.apply(value)),
+                (
+                    UnaryOperation::Bank,
+                    Address::Linked {
+                        target,
+                        kind: RelocationKind::Word,
+                        ..
+                    },
+                ) => part(target, 0, RelocationKind::Bank),
+                (
+                    UnaryOperation::Lo | UnaryOperation::Hi,
+                    Address::Linked {
+                        target,
+                        addend,
+                        kind: RelocationKind::Word,
+                    },
+                ) => match op {
+                    UnaryOperation::Hi => part(target, addend, RelocationKind::Hi),
+                    _ => part(target, addend, RelocationKind::Lo),
+                },
+                _ => unlinkable(expr)?,
+            },
+            Expr::Expr { lhs, op, rhs } => {
+                match (self.address(lhs, statics)?, op, self.address(rhs, statics)?) {
+                    (Address::Known(lhs), op, Address::Known(rhs)) => {
+                        Address::Known(op.apply(lhs, rhs)?)
+                    }
+                    (
+                        Address::Linked {
+                            target,
+                            addend,
+                            kind: RelocationKind::Word,
+                        },
+                        op @ (ExprOperation::Add | ExprOperation::Sub),
+                        Address::Known(n),
+                    ) => linked(target, op.apply(addend, n)?),
+                    (
+                        Address::Known(n),
+                        ExprOperation::Add,
+                        Address::Linked {
+                            target,
+                            addend,
+                            kind: RelocationKind::Word,
+                        },
+                    ) => linked(target, addend.wrapping_add(n)),
+                    // The distance between two addresses in the same place
+                    (
+                        Address::Linked {
+                            target: a,
+                            addend: lhs,
+                            kind: RelocationKind::Word,
+                        },
+                        ExprOperation::Sub,
+                        Address::Linked {
+                            target: b,
+                            addend: rhs,
+                            kind: RelocationKind::Word,
+                        },
+                    ) if a == b => Address::Known(lhs.wrapping_sub(rhs)),
+                    (
+                        Address::Linked {
+                            target,
+                            addend,
+                            kind: RelocationKind::Word,
+                        },
+                        ExprOperation::Rsh,
+                        Address::Known(8),
+                    ) => part(target, addend, RelocationKind::Hi),
+                    (
+                        Address::Linked {
+                            target,
+                            addend,
+                            kind,
+                        },
+                        ExprOperation::And,
+                        Address::Known(0xFF),
+                    ) if kind != RelocationKind::Bank => match kind {
+                        RelocationKind::Word => part(target, addend, RelocationKind::Lo),
+                        kind => part(target, addend, kind),
+                    },
+                    _ => unlinkable(expr)?,
+                }
+            }
+        })
+    }
+}
+
+fn symbol(section: Section, value: usize, bank: Option<usize>) -> Symbol {
+    Symbol {
+        section,
+        value,
+        bank,
+    }
+}
+
+fn part(target: Target, addend: usize, kind: RelocationKind) -> Address {
+    Address::Linked {
+        target,
+        addend,
+        kind,
+    }
+}
+
+fn unlinkable(expr: &Expr) -> Result<Address> {
+    bail!(
+        "`{expr}` depends on an address that is only known once linked, which can only be offset, subtracted from one in the same place, or split with lo(), hi() and bank()"
+    )
+}
@@ -0,0 +1,328 @@
+//! The peephole optimizer of `asm -O`: [Rule]s that rewrite a few native instructions in a
+//! row into fewer, once macros are expanded.
+//!
+//! Rules have to keep what the program does, flags included. They can assume that code
+//! doesn't jump into the middle of what they rewrite, as jumps go to labels, and that
+//! addresses aren't computed from `$` across it, except by `call`.
+
+use std::fmt::Debug;
+
+use indexmap::IndexMap;
+
+use super::lex::{Expr, Instruction, Node, Value};
+use super::{Compiler, Diagnostic};
+use crate::reg::Register;
+
+/// A rewrite the optimizer can make, see [Compiler::add_rule].
+pub trait Rule: Debug + Send + Sync {
+    /// What the rule is called when the bytes it saved are reported.
+    fn name(&self) -> &str;
+
+    /// How many of the nodes from `at` to replace, and with what, if the rule applies there.
+    fn apply(&self, code: &Code, at: usize) -> Option<(usize, Vec<Node>)>;
+}
+
+/// The tree being optimized, as [Rule]s see it.
+pub struct Code<'a> {
+    pub nodes: &'a [Node],
+    ctx: &'a Compiler,
+    /// The top-level label in front of each node, which `.sub` labels are in.
+    scopes: Vec<Option<&'a str>>,
+}
+
+impl<'a> Code<'a> {
+    fn new(nodes: &'a [Node], ctx: &'a Compiler) -> Self {
+        let mut scope = None;
+        let scopes = nodes
+            .iter()
+            .map(|node| {
+                if let Node::Label(ln, _) = node {
+                    if !ln.contains('.') {
+                        scope = Some(ln.as_str());
+                    }
+                }
+                scope
+            })
+            .collect();
+        Self { nodes, ctx, scopes }
+    }
+
+    pub fn instruction(&self, at: usize) -> Option<&'a Instruction> {
+        match self.nodes.get(at) {
+            Some(Node::Instruction(inst)) => Some(inst),
+            _ => None,
+        }
+    }
+
+    /// The full name of the label `var` refers to at `at`, like `main.loop` for `.loop`.
+    pub fn label(&self, at: usize, var: &str) -> String {
+        match (var.starts_with('.'), self.scopes.get(at).copied().flatten()) {
+            (true, Some(scope)) => format!("{scope}{var}"),
+            _ => var.to_string(),
+        }
+    }
+
+    /// Whether `inst` is part of a `call` of the prelude.
+    pub fn is_call(&self, inst: &Instruction) -> bool {
+        inst.expanded_from
+            .last()
+            .is_some_and(|expansion| self.ctx.is_prelude_macro(expansion, "call"))
+    }
+}
+
+/// The rules of `-O`.
+pub fn default_rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(RepeatedMov),
+        Box::new(PushPop),
+        Box::new(JumpToNext),
+        Box::new(TailCall),
+    ]
+}
+
+/// A `mov` into a register that already holds the same value, because the same `mov` came
+/// before it and neither register was written since. Like the second `mov %x, %a` and
+/// `mov %y, %b` of two `lw %c, %a, %b` in a row.
+#[derive(Debug)]
+pub struct RepeatedMov;
+
+impl Rule for RepeatedMov {
+    fn name(&self) -> &str {
+        "repeated mov"
+    }
+
+    fn apply(&self, code: &Code, at: usize) -> Option<(usize, Vec<Node>)> {
+        let inst = code.instruction(at)?;
+        let [Value::Register(to), from] = &inst.args[..] else {
+            return None;
+        };
+        if inst.id != "mov" || matches!(from, Value::Expr(e) if refers_to_pc(e)) {
+            return None;
+        }
+        let from_reg = match from {
+            Value::Register(r) => Some(*r),
+            _ => None,
+        };
+
+        for i in (0..at).rev() {
+            let before = code.instruction(i)?;
+            if before.id == "mov" && before.args == inst.args {
+                return Some((1, vec![]));
+            }
+            // What comes after a `jmp` isn't reached from it
+            let written = writes(before)?;
+            if before.id == "jmp" || written.iter().any(|r| *r == *to || Some(*r) == from_reg) {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+/// `push r` right before `pop r`. Popping clears the byte, so the stack is left as it was.
+#[derive(Debug)]
+pub struct PushPop;
+
+impl Rule for PushPop {
+    fn name(&self) -> &str {
+        "push and pop"
+    }
+
+    fn apply(&self, code: &Code, at: usize) -> Option<(usize, Vec<Node>)> {
+        let (push, pop) = (code.instruction(at)?, code.instruction(at + 1)?);
+        let same = match (&push.args[..], &pop.args[..]) {
+            ([Value::Register(a)], [Value::Register(b)]) => a == b,
+            _ => false,
+        };
+        (push.id == "push" && pop.id == "pop" && same).then(|| (2, vec![]))
+    }
+}
+
+/// A `jmp` to the label right after it.
+#[derive(Debug)]
+pub struct JumpToNext;
+
+impl Rule for JumpToNext {
+    fn name(&self) -> &str {
+        "jmp to the next address"
+    }
+
+    fn apply(&self, code: &Code, at: usize) -> Option<(usize, Vec<Node>)> {
+        let inst = code.instruction(at)?;
+        let [Value::Expr(Expr::Variable(to))] = &inst.args[..] else {
+            return None;
+        };
+        // The return address a `call` pushes is past its `jmp`
+        if inst.id != "jmp" || code.is_call(inst) {
+            return None;
+        }
+        let to = code.label(at, to);
+        for i in at + 1..code.nodes.len() {
+            match &code.nodes[i] {
+                Node::Label(ln, _) if code.label(i, ln) == to => return Some((1, vec![])),
+                Node::Label(..) => {}
+                _ => return None,
+            }
+        }
+        None
+    }
+}
+
+/// A `call` followed by `ret`, which can jump to the routine instead, so that it returns
+/// straight to the caller. `%x` and `%y` are left the same, as the `ret` of the routine sets
+/// them like the one after the `call` did.
+#[derive(Debug)]
+pub struct TailCall;
+
+impl Rule for TailCall {
+    fn name(&self) -> &str {
+        "call and ret"
+    }
+
+    fn apply(&self, code: &Code, at: usize) -> Option<(usize, Vec<Node>)> {
+        let insts = (at..at + 6)
+            .map(|i| code.instruction(i))
+            .collect::<Option<Vec<_>>>()?;
+        let [hi, lo, jmp, pop_x, pop_y, ret] = insts[..] else {
+            return None;
+        };
+        let call = [hi, lo, jmp].iter().all(|inst| code.is_call(inst))
+            && (hi.id.as_str(), lo.id.as_str(), jmp.id.as_str()) == ("push", "push", "jmp");
+        let returns = (pop_x.id.as_str(), pop_y.id.as_str(), ret.id.as_str())
+            == ("pop", "pop", "jmp")
+            && pop_x.args == [Value::Register(Register::X)]
+            && pop_y.args == [Value::Register(Register::Y)]
+            && ret.args.is_empty();
+        (call && returns).then(|| (6, vec![Node::Instruction(jmp.clone())]))
+    }
+}
+
+/// The registers `inst` writes, `None` if it isn't a native instruction.
+fn writes(inst: &Instruction) -> Option<Vec<Register>> {
+    let first = match inst.args.first() {
+        Some(Value::Register(r)) => vec![*r],
+        _ => vec![],
+    };
+    Some(match inst.id.as_str() {
+        "mov" | "lw" | "pop" | "in" | "and" | "or" | "nor" => first,
+        "adc" | "sbb" => [first, vec![Register::F]].concat(),
+        "cmp" => vec![Register::F],
+        "jnz" | "jmp" | "sw" | "push" | "out" | "bank" => vec![],
+        _ => return None,
+    })
+}
+
+/// Whether `expr` depends on where it is.
+fn refers_to_pc(expr: &Expr) -> bool {
+    let mut found = false;
+    expr.visit(&mut |var| found |= var == "$");
+    found
+}
+
+impl Compiler {
+    /// Optimize with the [default_rules], like `-O`.
+    pub fn optimize(&mut self) {
+        self.rules = default_rules();
+    }
+
+    /// Optimize with `rule` too, after the ones added before it.
+    pub fn add_rule(&mut self, rule: impl Rule + 'static) {
+        self.rules.push(Box::new(rule));
+    }
+
+    /// Apply the [Rule]s until none of them does anything.
+    pub(crate) fn apply_rules(&mut self) {
+        if self.rules.is_empty() {
+            return;
+        }
+        let rules = std::mem::take(&mut self.rules);
+        let mut tree = std::mem::take(&mut self.tree);
+        let mut saved = IndexMap::new();
+        let mut start = 0;
+        loop {
+            let code = Code::new(&tree, self);
+            let found = (start..tree.len()).find_map(|at| {
+                rules
+                    .iter()
+                    .find_map(|rule| Some((at, rule, rule.apply(&code, at)?)))
+            });
+            let Some((at, rule, (len, with))) = found else {
+                break;
+            };
+            let size = |nodes: &[Node]| -> usize {
+                nodes
+                    .iter()
+                    .map(|node| match node {
+                        Node::Instruction(inst) => inst.size().unwrap_or_default(),
+                        _ => 0,
+                    })
+                    .sum()
+            };
+            let bytes = size(&tree[at..at + len]).saturating_sub(size(&with));
+            *saved.entry(rule.name().to_string()).or_insert(0) += bytes;
+            tree.splice(at..at + len, with);
+            // Rules look at a few nodes before where they apply
+            start = at.saturating_sub(8);
+        }
+        self.tree = tree;
+        self.rules = rules;
+
+        if !saved.is_empty() {
+            let total = saved.values().sum::<usize>();
+            let rules = saved
+                .iter()
+                .map(|(rule, bytes)| format!("{rule}: {bytes}"))
+                .collect::<Vec<_>>();
+            self.diagnostics.push(Diagnostic::note(
+                format!("Optimized out {total} bytes ({})", rules.join(", ")),
+                None,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::{Input, MemoryFiles};
+
+    fn optimize(source: &str) -> anyhow::Result<Compiler> {
+        let mut compiler = Compiler::builder()
+            .sources(MemoryFiles::new())
+            .input(Input::Raw(source.to_string()))
+            .build()?;
+        compiler.optimize();
+        compiler.compile()?;
+        Ok(compiler)
+    }
+
+    #[test]
+    fn rules() -> anyhow::Result<()> {
+        // lw %c, %a, %b twice
+        let compiler = optimize("mov %x, %a\nmov %y, %b\nlw %c\nmov %x, %a\nmov %y, %b\nlw %d")?;
+        assert_eq!(compiler.bin, [0x04, 0x00, 0x05, 0x01, 0x32, 0x33]);
+        // %a changes in between
+        let compiler = optimize("mov %x, %a\nlw %a\nmov %x, %a")?;
+        assert_eq!(compiler.bin.len(), 5);
+
+        let compiler = optimize("push %a\npop %a\npush %a\npop %b")?;
+        assert_eq!(compiler.bin, [0x50, 0x61]);
+
+        let compiler = optimize("jmp next\nnext:\n.sub:\n    jmp .sub\nother:\n    nop")?;
+        assert_eq!(compiler.bin, [0x28, 0x00, 0x00, 0x00, 0x00]);
+
+        let compiler = optimize("main:\n    call f\n    ret\nf:\n    ret")?;
+        assert_eq!(compiler.bin[..3], [0x28, 0x03, 0x00]);
+        assert_eq!(compiler.labels["f"], 3);
+        // Not when something jumps to the `ret`
+        let compiler = optimize("main:\n    call f\n.end:\n    ret\nf:\n    jmp main.end")?;
+        assert_eq!(compiler.labels["f"], 10);
+
+        assert!(compiler
+            .diagnostics
+            .iter()
+            .all(|d| !d.message.starts_with("Optimized out")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+
+use super::{Compiler, Namespace, PRELUDE};
+use crate::compiler::lex::{
+    Expansion, Expr, ExprOperation, Instruction, Node, Placement, PlacementKind, Span,
+    UnaryOperation, Value,
+};
+use crate::compiler::{Diagnostic, Level, BANK_LEN, BANK_START, FIRST_ROM_BANK};
+use crate::reg::Register;
+
+/// The end of the window the selected bank is mapped to.
+pub(super) const BANK_END: usize = BANK_START + BANK_LEN;
+
+impl Compiler {
+    /// Place the nodes that follow in the ROM bank `bank`, or back in the fixed ROM with
+    /// `None`.
+    pub(crate) fn enter_bank(&mut self, bank: Option<usize>, span: Span) {
+        let placement = Placement {
+            kind: PlacementKind::Bank(bank.map(Expr::Literal)),
+            fill: Expr::Literal(0),
+        };
+        self.tree.push(Node::Placement(placement, span));
+    }
+
+    /// The ROM bank `bank` refers to, `None` for the fixed ROM. Banks that hold RAM can't
+    /// hold code too.
+    pub(crate) fn check_rom_bank(&self, bank: Option<&Expr>) -> Result<Option<usize>> {
+        let Some(bank) = bank else {
+            return Ok(None);
+        };
+        let bank = bank.resolve(self)?;
+        if bank < FIRST_ROM_BANK {
+            bail!("Bank {bank} is RAM, code can only be placed in banks {FIRST_ROM_BANK} to 255");
+        }
+        if bank > 0xFF {
+            bail!("There is no bank {bank}, `bank` selects one of 256");
+        }
+        let region = self
+            .regions
+            .iter()
+            .find(|(_, (region, _))| region.bank == Some(bank));
+        if let Some((name, _)) = region {
+            bail!("Bank {bank} is RAM, #[dyn(region {name})] is in it");
+        }
+        Ok(Some(bank))
+    }
+
+    /// Make the `call`s to labels in another ROM bank go through a trampoline in the fixed
+    /// ROM, which selects the bank of the label, and the one from before again once it
+    /// returns. As `%k` can't be read, the bank selected is kept in `core::KREG`.
+    ///
+    /// The trampoline overwrites `%x` before calling, and `%x` and `%y` when returning, like
+    /// `ret` does. Flags are kept.
+    ///
+    /// Any other reference to the address of a label or `#[const]` in another bank is an
+    /// error, as it would be read from whatever bank is selected.
+    pub(crate) fn resolve_far_calls(&mut self) {
+        let mut tree = std::mem::take(&mut self.tree);
+        // #[bank]s are checked once their nodes are placed
+        let section = |kind: &Option<Expr>| kind.as_ref().and_then(|b| b.resolve(self).ok());
+
+        let mut banks = IndexMap::new();
+        let mut bank = None;
+        let mut last_label = String::new();
+        for node in &tree {
+            match node {
+                Node::Placement(
+                    Placement {
+                        kind: PlacementKind::Bank(kind),
+                        ..
+                    },
+                    _,
+                ) => bank = section(kind),
+                Node::Label(ln, _) => {
+                    let name = if ln.starts_with('.') {
+                        format!("{last_label}{ln}")
+                    } else {
+                        if !ln.contains('.') {
+                            last_label = ln.clone();
+                        }
+                        ln.clone()
+                    };
+                    if let Some(bank) = bank {
+                        banks.insert(name, bank);
+                    }
+                }
+                Node::Constant(name, ..) => {
+                    if let Some(bank) = bank {
+                        banks.insert(name.clone(), bank);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let call = self
+            .qualify("call", PRELUDE, Namespace::Macro)
+            .ok()
+            .flatten();
+        let mut far = IndexMap::new();
+        let mut errors = vec![];
+        bank = None;
+        last_label.clear();
+        for node in tree.iter_mut() {
+            let inst = match node {
+                Node::Placement(
+                    Placement {
+                        kind: PlacementKind::Bank(kind),
+                        ..
+                    },
+                    _,
+                ) => {
+                    bank = section(kind);
+                    continue;
+                }
+                Node::Label(ln, _) => {
+                    if !ln.contains('.') {
+                        last_label = ln.clone();
+                    }
+                    continue;
+                }
+                Node::Instruction(inst) => inst,
+                _ => continue,
+            };
+            // The jump of a `call`, with the return address already pushed
+            let from = inst.expanded_from.last().filter(|from| {
+                let qualified =
+                    self.qualify(&from.id, self.module_of(&from.span), Namespace::Macro);
+                inst.id == "jmp" && qualified.ok().flatten() == call
+            });
+            if let (Some(from), [Value::Expr(Expr::Variable(target))]) = (from, &mut inst.args[..])
+            {
+                if let Some(to) = banks.get(target).copied().filter(|to| Some(*to) != bank) {
+                    far.entry(target.clone())
+                        .or_insert_with(|| (to, from.span.clone()));
+                    *target = far_label(target);
+                    continue;
+                }
+            }
+
+            let mut other = None;
+            for arg in &inst.args {
+                let Value::Expr(expr) = arg else {
+                    continue;
+                };
+                addresses(expr, &mut |var| {
+                    let name = match var.starts_with('.') {
+                        true => format!("{last_label}{var}"),
+                        false => var.to_string(),
+                    };
+                    if let Some(to) = banks.get(&name).filter(|to| Some(**to) != bank) {
+                        other.get_or_insert((name, *to));
+                    }
+                });
+            }
+            if let Some((target, to)) = other {
+                let here = match bank {
+                    Some(bank) => format!("bank {bank}"),
+                    None => "the fixed ROM".to_string(),
+                };
+                errors.push(Diagnostic::at_instruction(
+                    Level::Error,
+                    format!("{target} is in bank {to}, which {here} can only reach with `call`"),
+                    inst,
+                ));
+            }
+        }
+        self.tree = tree;
+        self.diagnostics.extend(errors);
+
+        let kreg = match self.qualify_value("KREG", PRELUDE) {
+            Ok(kreg) => Expr::Variable(kreg),
+            Err(_) => unreachable!("core defines KREG"),
+        };
+        for (target, (bank, span)) in far {
+            self.trampoline(&target, bank, &kreg, span);
+        }
+    }
+
+    /// Add the trampoline that calls `target` in `bank` to the end of the fixed ROM.
+    fn trampoline(&mut self, target: &str, bank: usize, kreg: &Expr, span: Span) {
+        use Register as R;
+        use Value as V;
+
+        let label = far_label(target);
+        let back = Expr::Variable(format!("{label}.back"));
+        let byte = |op, n| {
+            V::Expr(Expr::Expr {
+                lhs: Box::new(back.clone()),
+                op,
+                rhs: Box::new(Expr::Literal(n)),
+            })
+        };
+        let inst = |id: &str, args: Vec<Value>| {
+            Node::Instruction(Instruction {
+                id: id.to_string(),
+                args,
+                span: span.clone(),
+                // Listed and reported along with the `call`
+                expanded_from: vec![Expansion {
+                    id: "call".to_string(),
+                    span: span.clone(),
+                }],
+            })
+        };
+
+        let nodes = vec![
+            Node::Label(label.clone(), span.clone()),
+            // Keep the bank selected now, and select the one of `target`
+            inst("lw", vec![V::Register(R::X), V::Expr(kreg.clone())]),
+            inst("push", vec![V::Register(R::X)]),
+            inst("mov", vec![V::Register(R::X), V::Literal(bank)]),
+            inst("sw", vec![V::Expr(kreg.clone()), V::Register(R::X)]),
+            inst("bank", vec![V::Register(R::X)]),
+            // call target
+            inst("push", vec![byte(ExprOperation::Rsh, 8)]),
+            inst("push", vec![byte(ExprOperation::And, 0xFF)]),
+            inst("jmp", vec![V::Expr(Expr::Variable(target.to_string()))]),
+            Node::Label(format!("{label}.back"), span.clone()),
+            // Select the bank from before again
+            inst("pop", vec![V::Register(R::X)]),
+            inst("sw", vec![V::Expr(kreg.clone()), V::Register(R::X)]),
+            inst("bank", vec![V::Register(R::X)]),
+            // ret
+            inst("pop", vec![V::Register(R::X)]),
+            inst("pop", vec![V::Register(R::Y)]),
+            inst("jmp", vec![]),
+        ];
+        self.enter_bank(None, span);
+        self.tree.extend(nodes);
+    }
+
+    /// Make `self.bin` the ROM bank `bank`, or the fixed ROM with `None`. Banks start with
+    /// [BANK_START] bytes that aren't theirs, so that `$` is the address in them too.
+    pub(crate) fn select_bank(&mut self, bank: Option<usize>) {
+        if bank == self.bank {
+            return;
+        }
+        // Swapped with the fixed ROM when it was selected
+        if let Some(old) = self.bank.take() {
+            std::mem::swap(&mut self.bin, &mut self.rom_banks[&old]);
+        }
+        if let Some(new) = bank {
+            let rom = self
+                .rom_banks
+                .entry(new)
+                .or_insert_with(|| vec![0; BANK_START]);
+            std::mem::swap(&mut self.bin, rom);
+        }
+        self.bank = bank;
+    }
+
+    /// The compiled bytes at `range` in the fixed ROM, or in the ROM bank `bank`.
+    pub(crate) fn rom_bytes(&self, bank: Option<usize>, range: Range<usize>) -> &[u8] {
+        match bank {
+            Some(bank) => &self.rom_banks[&bank][range.start - BANK_START..range.end - BANK_START],
+            None => &self.bin[range],
+        }
+    }
+}
+
+/// Call `f` with the variables whose address `expr` uses, which the ones in `bank()` aren't.
+fn addresses(expr: &Expr, f: &mut impl FnMut(&str)) {
+    match expr {
+        Expr::Variable(var) => f(var),
+        Expr::Literal(_) => {}
+        Expr::Unary {
+            op: UnaryOperation::Bank,
+            ..
+        } => {}
+        Expr::Unary { expr, .. } => addresses(expr, f),
+        Expr::Expr { lhs, rhs, .. } => {
+            addresses(lhs, f);
+            addresses(rhs, f);
+        }
+    }
+}
+
+/// The trampoline for far calls to `target`, which can't be written in source.
+fn far_label(target: &str) -> String {
+    format!("far@{target}")
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile_errors, compile_with};
+    use crate::compiler::{Compiler, Input, Level, MemoryFiles};
+
+    #[test]
+    fn banks() -> Result<()> {
+        let compiler = compile_with(
+            MemoryFiles::new().with("lib.asm", "#[pub]\nnear:\n    ret"),
+            r#"
+#[main]
+main:
+    call far
+#[bank(2)]
+#[use("lib")]
+far:
+    mov %a, bank(far)
+    call lib::near
+    call local
+    ret
+local:
+    ret
+"#,
+            Level::Error,
+        )?;
+        assert_eq!(compiler.labels["far"], 0x8000);
+        assert_eq!(compiler.rom_banks[&2][..2], [0x08, 2]);
+        // Files only take the #[bank] of their own
+        assert!(compiler.labels["lib::near"] < 0x8000);
+        // Only `call far` leaves the bank it is in
+        assert!(compiler.labels["far@far"] < 0x8000);
+        assert_eq!(compiler.bin[7..9], [0x28, compiler.labels["far@far"] as u8]);
+        assert_eq!(
+            compiler
+                .labels
+                .keys()
+                .filter(|l| l.starts_with("far@"))
+                .count(),
+            2
+        );
+
+        for (src, msg) in [
+            (
+                "#[bank(0)]\nnop",
+                "Bank 0 is RAM, code can only be placed in banks 2 to 255",
+            ),
+            (
+                "#[dyn(region r: bank 3, &0x8000, 1)]\n#[bank(3)]\nnop",
+                "Bank 3 is RAM, #[dyn(region r)] is in it",
+            ),
+            (
+                "#[bank(2)]\n#[org(0xBFFF)]\n#[const(A)] { 1, 2 }",
+                "Bank 2 reaches 0xC001, past the end of the 16K it is mapped to",
+            ),
+            (
+                "#[main]\nmain:\n    jmp two\n#[bank(2)]\ntwo:\n    ret",
+                "two is in bank 2, which the fixed ROM can only reach with `call`",
+            ),
+            (
+                "#[main]\nmain:\n    lw %a, TABLE\n#[bank(2)]\n#[const(TABLE)] { 1 }",
+                "TABLE is in bank 2, which the fixed ROM can only reach with `call`",
+            ),
+            (
+                "#[bank(2)]\ntwo:\n    jnz three.on, %a\n#[bank(3)]\nthree:\n.on:\n    ret",
+                "three.on is in bank 3, which bank 2 can only reach with `call`",
+            ),
+        ] {
+            let errors = compile_errors(src);
+            assert_eq!(errors[0].message, msg);
+        }
+
+        let err = Compiler::builder()
+            .input(Input::Raw("#[bank(2)]\nnop".to_string()))
+            .compile_object()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("#[bank] cannot be used in an object"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use indexmap::IndexMap;
+
+use super::Compiler;
+use crate::compiler::lex::{Data, Instruction, Node, PlacementKind, Value};
+use crate::compiler::Diagnostic;
+
+/// The nodes from a top-level label or `#[const]` up to the next one, which are either all
+/// compiled or all left out.
+#[derive(Debug, Default)]
+struct Block {
+    /// The label or `#[const]` it starts with, `None` for the nodes in front of the first.
+    name: Option<String>,
+    nodes: Vec<usize>,
+    /// Every variable its instructions and data refer to.
+    refs: Vec<String>,
+    /// Whether running past its end goes on into the next block.
+    falls_through: bool,
+    size: usize,
+}
+
+impl Compiler {
+    /// Leave out the labels and `#[const]`s that nothing reaches from `#[main]`, along with
+    /// the nodes up to the next one. Blocks are reached by being referred to, or by the
+    /// block before them running into them. `#[keep]` labels, and labels `#[static]`s or
+    /// `#[org]`s refer to, are always reached.
+    ///
+    /// Only done for whole programs with a `#[main]`, as anything could be the entry point
+    /// otherwise.
+    pub(crate) fn eliminate_dead_code(&mut self) {
+        if !self.preamble || self.is_object() {
+            return;
+        }
+        let tree = std::mem::take(&mut self.tree);
+        let mut blocks = vec![Block {
+            falls_through: true,
+            ..Default::default()
+        }];
+        let mut owners = IndexMap::new();
+        let mut roots = vec![0];
+        // What #[org]s and #[align]s refer to, which can be labels after them
+        let mut placed = vec![];
+        let mut last_label = String::new();
+        for (i, node) in tree.iter().enumerate() {
+            let starts = match node {
+                Node::Label(ln, _) if !ln.contains('.') => Some(ln),
+                Node::Constant(name, ..) => Some(name),
+                _ => None,
+            };
+            if let Some(name) = starts {
+                blocks.push(Block {
+                    name: Some(name.clone()),
+                    falls_through: true,
+                    ..Default::default()
+                });
+            }
+            let n = blocks.len() - 1;
+            let block = &mut blocks[n];
+            block.nodes.push(i);
+
+            // Named like resolve_labels does
+            let mut refer = |var: &str| match var.starts_with('.') {
+                true => block.refs.push(format!("{last_label}{var}")),
+                false => block.refs.push(var.to_string()),
+            };
+            match node {
+                Node::Label(ln, _) if ln.starts_with('.') => {
+                    owners.insert(format!("{last_label}{ln}"), n);
+                }
+                Node::Label(ln, _) => {
+                    if !ln.contains('.') {
+                        last_label = ln.clone();
+                    }
+                    owners.insert(ln.clone(), n);
+                }
+                Node::Constant(name, constant, _) => {
+                    for data in &constant.0 {
+                        if let Data::Byte(e) | Data::Word(e) = data {
+                            e.visit(&mut refer);
+                        }
+                    }
+                    owners.insert(name.clone(), n);
+                    block.size += constant.len();
+                    block.falls_through = false;
+                }
+                Node::Instruction(inst) => {
+                    for arg in &inst.args {
+                        if let Value::Expr(e) = arg {
+                            e.visit(&mut refer);
+                        }
+                    }
+                    block.size += inst.size().unwrap_or_default();
+                    block.falls_through = self.falls_through(inst);
+                }
+                Node::Placement(placement, _) => {
+                    let mut refer = |var: &str| placed.push(var.to_string());
+                    placement.fill.visit(&mut refer);
+                    match &placement.kind {
+                        PlacementKind::Org(e) | PlacementKind::Align(e) => e.visit(&mut refer),
+                        PlacementKind::Bank(e) => e.iter().for_each(|e| e.visit(&mut refer)),
+                    }
+                }
+                Node::Use(_) => {}
+            }
+        }
+
+        roots.extend(placed.iter().filter_map(|var| owners.get(var)));
+        roots.extend(self.keep.iter().filter_map(|name| owners.get(name)));
+        for (expr, span) in self.static_exprs.values() {
+            let Ok(expr) = self.qualify_expr(expr, self.module_of(span)) else {
+                continue;
+            };
+            expr.visit(&mut |var| roots.extend(owners.get(var)));
+        }
+
+        let mut reached = vec![false; blocks.len()];
+        while let Some(n) = roots.pop() {
+            if std::mem::replace(&mut reached[n], true) {
+                continue;
+            }
+            let block = &blocks[n];
+            roots.extend(block.refs.iter().filter_map(|var| owners.get(var)));
+            if block.falls_through && n + 1 < blocks.len() {
+                roots.push(n + 1);
+            }
+        }
+
+        let mut keep = vec![false; tree.len()];
+        let mut saved = 0;
+        for (block, reached) in blocks.iter().zip(reached) {
+            if let (false, Some(name)) = (reached, &block.name) {
+                self.eliminated.insert(name.clone(), block.size);
+                saved += block.size;
+                continue;
+            }
+            for i in &block.nodes {
+                keep[*i] = true;
+            }
+        }
+        self.tree = tree
+            .into_iter()
+            .zip(keep)
+            // Where the code after them goes doesn't depend on what is left out before it
+            .filter(|(node, keep)| *keep || matches!(node, Node::Placement(..)))
+            .map(|(node, _)| node)
+            .collect();
+
+        if !self.eliminated.is_empty() {
+            let names = self.eliminated.keys().cloned().collect::<Vec<_>>();
+            self.diagnostics.push(Diagnostic::note(
+                format!(
+                    "Left out {saved} bytes that nothing reaches from #[main]: {}",
+                    names.join(", ")
+                ),
+                None,
+            ));
+        }
+    }
+
+    /// Whether running `inst` can go on to the instruction after it.
+    fn falls_through(&self, inst: &Instruction) -> bool {
+        let from = &inst.expanded_from;
+        if from.iter().any(|e| self.is_prelude_macro(e, "halt")) {
+            return false;
+        }
+        // `call`s jump too, but come back
+        inst.id != "jmp"
+            || from
+                .last()
+                .is_some_and(|e| self.is_prelude_macro(e, "call"))
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use path_clean::clean;
+use png::Image;
+
+use super::{Compiler, Namespace};
+use crate::compiler::lex::{Constant, Data, Include, IncludeKind, Node, Span};
+
+impl Compiler {
+    /// Read the file of an `#[include_bytes]` or `#[include_image]` into a `#[const]`, and
+    /// define its `_LEN` statics (and `_W` and `_H`, in pixels, for images). The path is
+    /// relative to the file that includes it. They are all exported if the include is `pub`.
+    pub(crate) fn resolve_include(
+        &mut self,
+        include: Include,
+        span: Span,
+        public: bool,
+    ) -> Result<()> {
+        let dir = span.file.path.parent().unwrap_or(Path::new(""));
+        let path = clean(dir.join(&include.path));
+        let name = include.name();
+
+        let (bytes, size) = match include.kind {
+            IncludeKind::Bytes => (self.sources.read(&path)?, None),
+            IncludeKind::Image(format) => {
+                let bytes = self.sources.read(&path)?;
+                let img = Image::decode(&bytes, format)
+                    .with_context(|| format!("Failed to read image {path:?}"))?;
+                (img.bytes, Some((img.width as usize, img.height as usize)))
+            }
+        };
+
+        let mut statics = vec![(format!("{name}_LEN"), bytes.len())];
+        if let Some((w, h)) = size {
+            statics.push((format!("{name}_W"), w));
+            statics.push((format!("{name}_H"), h));
+        }
+        for (k, v) in statics {
+            let full = self.declare(&span, &k, public, Namespace::Value)?;
+            if self.is_static(&full) {
+                bail!("Attempted to define {k} twice");
+            }
+            self.statics.insert(full, v);
+        }
+
+        let name = self.declare(&span, &name, public, Namespace::Value)?;
+        self.tree.push(Node::Constant(
+            name,
+            Constant(vec![Data::Bytes(bytes)]),
+            span,
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile_errors, compile_with};
+    use crate::compiler::{Level, MemoryFiles};
+
+    #[test]
+    fn include_bytes() -> Result<()> {
+        let compiler = compile_with(
+            MemoryFiles::new().with("data.bin", [1, 2, 3]),
+            "#[include_bytes(\"data.bin\")]\n#[const(LEN)] { DATA_LEN }",
+            Level::Error,
+        )?;
+        assert_eq!(compiler.bin, [1, 2, 3, 3]);
+
+        let errors = compile_errors(r#"#[include_bytes(DATA, "missing.bin")]"#);
+        assert!(errors[0]
+            .message
+            .starts_with("Failed to read \"missing.bin\""));
+
+        Ok(())
+    }
+}
@@ -1,41 +1,108 @@
-use crate::compiler::lex::{Instruction, Node};
+use crate::compiler::lex::{Instruction, Node, Placement, PlacementKind};
+use crate::compiler::{Diagnostic, Level, BANK_LEN, BANK_START, ROM_LEN};
 use crate::op::Operation;
 
 use anyhow::{bail, Result};
+use indexmap::IndexMap;
+
+use super::bank::BANK_END;
 
 use super::Compiler;
 
 impl Compiler {
-    pub(crate) fn resolve_labels(&mut self) -> Result<()> {
-        for node in self.tree.iter() {
+    pub(crate) fn resolve_labels(&mut self) {
+        // Where each ROM the tree isn't in right now is up to
+        let mut pcs = IndexMap::new();
+        let tree = std::mem::take(&mut self.tree);
+        for node in tree.iter() {
+            let (start, section) = (self.pc, self.bank);
             match node {
-                Node::Label(ln) => {
+                Node::Label(ln, _) => {
                     if ln.starts_with('.') {
-                        self.labels
-                            .insert(format!("{}{ln}", self.last_label), self.pc);
+                        self.place_label(format!("{}{ln}", self.last_label));
+                    } else if ln.contains('.') {
+                        // Already qualified, like labels defined in macro bodies
+                        self.place_label(ln.to_string());
                     } else {
                         self.last_label = ln.to_string();
-                        self.labels.insert(ln.to_string(), self.pc);
+                        self.place_label(ln.to_string());
                     }
                 }
 
-                Node::Instruction(inst) => {
-                    let size = match inst.size() {
-                        Ok(sz) => sz,
-                        Err(e) => bail!("Argument error for {inst:#?}: {e:#?}. At {node:#?}"),
-                    };
-                    self.pc += size as usize;
-                }
-                Node::Constant(name, val) => {
-                    let len = val.0.len();
-                    self.labels.insert(name.to_string(), self.pc);
+                Node::Instruction(inst) => match inst.size() {
+                    Ok(size) => self.pc += size,
+                    Err(e) => self.diagnostics.push(Diagnostic::at_instruction(
+                        Level::Error,
+                        format!("Argument error: {e:#}"),
+                        inst,
+                    )),
+                },
+                Node::Constant(name, val, _) => {
+                    let len = val.len();
+                    self.place_label(name.to_string());
                     self.pc += len;
                 }
-                oth => bail!("Unexpected {oth:#?}. At {node:#?}"),
+                Node::Placement(
+                    Placement {
+                        kind: PlacementKind::Bank(bank),
+                        ..
+                    },
+                    span,
+                ) => match self.check_rom_bank(bank.as_ref()) {
+                    Ok(bank) => {
+                        pcs.insert(self.bank, self.pc);
+                        self.pc = match (pcs.get(&bank), bank) {
+                            (Some(pc), _) => *pc,
+                            (None, Some(_)) => BANK_START,
+                            (None, None) => 0,
+                        };
+                        self.bank = bank;
+                    }
+                    Err(e) => self
+                        .diagnostics
+                        .push(Diagnostic::error(format!("{e:#}"), Some(span.clone()))),
+                },
+                Node::Placement(placement, span) => match placement.target(self.pc, self) {
+                    Ok(target) => self.pc = target,
+                    Err(e) => self
+                        .diagnostics
+                        .push(Diagnostic::error(format!("{e:#}"), Some(span.clone()))),
+                },
+                oth => self.diagnostics.push(Diagnostic::error(
+                    format!("Unexpected {oth:#?}"),
+                    oth.span().cloned(),
+                )),
             }
+
+            if self.bank != section {
+                continue;
+            }
+            let message = match section {
+                None if start <= ROM_LEN && self.pc > ROM_LEN => format!(
+                    "The program reaches {:#06X}, past the end of the {}K ROM",
+                    self.pc,
+                    ROM_LEN / 1024
+                ),
+                Some(bank) if start <= BANK_END && self.pc > BANK_END => format!(
+                    "Bank {bank} reaches {:#06X}, past the end of the {}K it is mapped to",
+                    self.pc,
+                    BANK_LEN / 1024
+                ),
+                _ => continue,
+            };
+            self.diagnostics
+                .push(Diagnostic::error(message, node.span().cloned()));
         }
+        self.tree = tree;
+        self.bank = None;
+    }
 
-        Ok(())
+    /// Put the label `name` at the current address.
+    fn place_label(&mut self, name: String) {
+        if let Some(bank) = self.bank {
+            self.label_banks.insert(name.clone(), bank);
+        }
+        self.labels.insert(name, self.pc);
     }
 }
 
@@ -1,29 +1,34 @@
-use anyhow::{bail, Result};
 use indexmap::IndexMap;
 
-use crate::compiler::lex::{Expr, ExprOperation, Instruction, MacroCaptureArgType, Node, Value};
+use crate::compiler::lex::{
+    Expansion, Expr, ExprOperation, Instruction, MacroCaptureArgType, Node, Value,
+};
+use crate::compiler::{Diagnostic, Level};
 use crate::op::Operation;
 
 use super::Compiler;
 
 impl Compiler {
-    pub(crate) fn resolve_macros(&mut self) -> Result<()> {
+    pub(crate) fn resolve_macros(&mut self) {
         let mut new_tree = vec![];
 
         let mut tree = vec![];
         tree.append(&mut self.tree);
 
+        let mut expansions = 0;
         for node in tree {
-            let mut stripped = self.fill_macro(node)?;
-            new_tree.append(&mut stripped);
+            match self.fill_macro(node, &mut expansions) {
+                Ok(mut stripped) => new_tree.append(&mut stripped),
+                Err(e) => self.diagnostics.push(e),
+            }
         }
 
         self.tree = new_tree;
-
-        Ok(())
     }
 
-    fn fill_macro(&self, node: Node) -> Result<Vec<Node>> {
+    /// Replace macro calls in `node` with the body of the first matching capture.
+    /// `expansions` counts the bodies filled in so far, to give their labels unique names.
+    fn fill_macro(&self, node: Node, expansions: &mut usize) -> Result<Vec<Node>, Diagnostic> {
         use MacroCaptureArgType as MA;
         use Value as V;
 
@@ -31,12 +36,18 @@ impl Compiler {
 
         match node {
             Node::Instruction(inst) => {
-                let mac = match self.macros.get(&inst.id) {
+                let found = self.find_macro(&inst.id, &inst.span).map_err(|e| {
+                    Diagnostic::at_instruction(Level::Error, format!("{e:#}"), &inst)
+                })?;
+                let mac = match found {
                     Some(m) => m,
                     None => {
-                        match Operation::try_from(inst.id.as_str()) {
-                            Ok(_) => {}
-                            Err(_) => bail!("Macro {:#?} not defined", inst.id),
+                        if Operation::try_from(inst.id.as_str()).is_err() {
+                            return Err(Diagnostic::at_instruction(
+                                Level::Error,
+                                format!("Macro {:#?} not defined", inst.id),
+                                &inst,
+                            ));
                         };
 
                         return Ok(vec![Node::Instruction(inst)]);
@@ -127,42 +138,111 @@ impl Compiler {
                         continue;
                     }
 
-                    for instruction in capturer.content.iter() {
+                    let mut expanded_from = inst.expanded_from.clone();
+                    expanded_from.push(Expansion {
+                        id: inst.id.clone(),
+                        span: inst.span.clone(),
+                    });
+
+                    *expansions += 1;
+                    let n = *expansions;
+                    let locals = capturer
+                        .content
+                        .iter()
+                        .filter_map(|node| match node {
+                            Node::Label(ln, _) => Some((
+                                ln.trim_start_matches('.').to_string(),
+                                local_label(&inst.id, n, ln),
+                            )),
+                            _ => None,
+                        })
+                        .collect::<IndexMap<_, _>>();
+
+                    for node in capturer.content.iter() {
+                        let instruction = match node {
+                            Node::Label(ln, span) => {
+                                tree.push(Node::Label(local_label(&inst.id, n, ln), span.clone()));
+                                continue;
+                            }
+                            Node::Instruction(instruction) => instruction,
+                            _ => continue,
+                        };
+
+                        let mut expanded = Instruction {
+                            expanded_from: expanded_from.clone(),
+                            ..instruction.clone()
+                        };
+
+                        // Names in the body are looked up from the module of the macro
+                        let scope = self.module_of(&instruction.span);
                         let mut new_args: Vec<Value> = vec![];
 
-                        for arg in instruction.args.iter() {
+                        for arg in expanded.args.iter() {
                             match arg {
                                 V::MacroVariable(ma) => {
                                     let Some(val) = captured_args.get(ma) else {
-                                        panic!(
-                                            "Attempted to use undefined macro arg {:#?} at {:#?}",
-                                            ma, inst.id
-                                        );
+                                        return Err(Diagnostic::at_instruction(
+                                            Level::Error,
+                                            format!("Attempted to use undefined macro arg {ma:#?}"),
+                                            &expanded,
+                                        ));
                                     };
                                     new_args.push(val.to_owned());
                                 }
+                                V::Expr(e) => {
+                                    let e = e.try_replace(&|var| {
+                                        if let Some(label) = locals.get(var.trim_start_matches('.'))
+                                        {
+                                            return Ok(Some(Expr::Variable(label.clone())));
+                                        }
+                                        match captured_args.get(var) {
+                                            Some(V::Expr(e)) => Ok(Some(e.clone())),
+                                            Some(V::Literal(lit)) => Ok(Some(Expr::Literal(*lit))),
+                                            _ => self
+                                                .qualify_value(var, scope)
+                                                .map(|var| Some(Expr::Variable(var))),
+                                        }
+                                    });
+                                    match e {
+                                        Ok(e) => new_args.push(V::Expr(e)),
+                                        Err(e) => {
+                                            return Err(Diagnostic::at_instruction(
+                                                Level::Error,
+                                                format!("{e:#}"),
+                                                &expanded,
+                                            ))
+                                        }
+                                    }
+                                }
                                 oth => new_args.push(oth.clone()),
                             }
                         }
 
-                        let mut nodes = self.fill_macro(Node::Instruction(Instruction {
-                            id: instruction.id.clone(),
-                            args: new_args,
-                        }))?;
+                        expanded.args = new_args;
+
+                        let mut nodes = self.fill_macro(Node::Instruction(expanded), expansions)?;
 
                         tree.append(&mut nodes);
                     }
                     return Ok(tree);
                 }
 
-                match Operation::try_from(inst.id.as_str()) {
-                    Ok(_) => {}
-                    Err(_) => bail!(
-                        "Could not find matching macro or instruction for {:#?}. \nExpected one of {:#?}. \nGot {:#?}",
-                        inst.id,
-                        mac.captures,
-                        inst.args
-                    ),
+                if Operation::try_from(inst.id.as_str()).is_err() {
+                    let mut diagnostic = Diagnostic::at_instruction(
+                        Level::Error,
+                        format!(
+                            "Could not find matching macro or instruction for {:#?}",
+                            inst.id
+                        ),
+                        &inst,
+                    );
+                    for capture in mac.captures.iter() {
+                        diagnostic = diagnostic.with(Diagnostic::note(
+                            format!("expected {} {capture}", mac.id),
+                            None,
+                        ));
+                    }
+                    return Err(diagnostic);
                 };
 
                 return Ok(vec![Node::Instruction(inst)]);
@@ -173,3 +253,15 @@ impl Compiler {
         Ok(tree)
     }
 }
+
+/// The name of label `ln` in the `n`th expansion of macro `mac`. Both `loop` and `.loop` become
+/// `mac@n.loop`, which can't be written in source, and (like `label.sub`) doesn't start a new
+/// scope for the `.sub` labels that follow.
+fn local_label(mac: &str, n: usize, ln: &str) -> String {
+    format!("{mac}@{n}.{}", ln.trim_start_matches('.'))
+}
+
+/// Whether `ln` was defined in a macro body, see [local_label].
+pub(crate) fn is_local_label(ln: &str) -> bool {
+    ln.contains('@')
+}
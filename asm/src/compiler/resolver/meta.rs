@@ -1,59 +1,340 @@
 use anyhow::{bail, Result};
 
-use super::Compiler;
+use super::{Compiler, Namespace};
 use crate::compiler::config::Input;
-use crate::compiler::lex::{Expr, Instruction, Item, ItemInner, Meta, Node, Value};
+use crate::compiler::lex::{
+    Condition, Constant, ConstantItem, Data, Expr, Instruction, Item, ItemInner, Meta, Node,
+    Placement, PlacementKind, Repeat, Span, Value,
+};
+use crate::compiler::{Diagnostic, ROM_LEN};
+
+/// More iterations than the `#[repeat]`s of a program, nested ones included, could possibly
+/// fit into ROM.
+const MAX_REPEAT: usize = ROM_LEN;
 
 impl Compiler {
-    pub(crate) fn resolve_meta(&mut self, nodes: Vec<Item>) -> Result<()> {
+    pub(crate) fn resolve_meta(&mut self, nodes: Vec<Item>) {
         for node in nodes {
-            match node.item {
+            let span = node.span;
+            let (item, public) = match node.item {
+                ItemInner::Meta(Meta::Pub(meta)) => (ItemInner::Meta(*meta), true),
+                item @ ItemInner::Meta(Meta::PubLabel(_)) => (item, true),
+                item => (item, false),
+            };
+
+            macro_rules! error {
+                ($($arg:tt)*) => {{
+                    self.diagnostics
+                        .push(Diagnostic::error(format!($($arg)*), Some(span)));
+                    continue;
+                }};
+            }
+
+            macro_rules! declare {
+                ($name:expr, $ns:ident) => {
+                    match self.declare(&span, &$name, public, Namespace::$ns) {
+                        Ok(name) => name,
+                        Err(e) => error!("{e:#}"),
+                    }
+                };
+            }
+
+            match item {
                 ItemInner::Meta(Meta::Use(f)) => {
-                    self.push(Input::File(f.to_string()), node.file)?;
+                    let module = self.include(
+                        Input::File(f.to_string()),
+                        span.file.path.clone(),
+                        Some(&span),
+                    );
+                    if let Some(module) = module {
+                        self.add_use(&span, module, public);
+                    }
+                }
+                ItemInner::Meta(Meta::Include(include)) => {
+                    if let Err(e) = self.resolve_include(include, span.clone(), public) {
+                        error!("{e:#}");
+                    }
+                }
+                ItemInner::Meta(Meta::PubLabel(label)) => {
+                    declare!(label, Value);
+                }
+                ItemInner::Meta(Meta::Keep(label)) => {
+                    let name = declare!(label, Value);
+                    self.keep.insert(name);
+                }
+                ItemInner::Meta(Meta::Placement(Placement {
+                    kind: PlacementKind::Bank(Some(bank)),
+                    ..
+                })) => {
+                    if self.is_object() {
+                        error!("#[bank] cannot be used in an object, only in a whole program");
+                    }
+                    match self.resolve_in(&bank, &span) {
+                        Ok(bank) => {
+                            self.code_bank = Some((bank, span.clone()));
+                            self.enter_bank(Some(bank), span);
+                        }
+                        Err(e) => error!("{e:#}"),
+                    }
+                }
+                ItemInner::Meta(Meta::Placement(placement)) => {
+                    self.tree.push(Node::Placement(placement, span));
                 }
                 ItemInner::Meta(Meta::Main(to)) => {
                     if self.preamble {
-                        bail!("Cannot set #[main] twice");
+                        error!("Cannot set #[main] twice");
                     }
+                    self.preamble = true;
 
+                    let own = !self.is_foreign(&span);
+                    if let Some(object) = self.object.as_mut().filter(|_| own) {
+                        object.entry = true;
+                    }
                     self.tree.insert(
                         0,
                         Node::Instruction(Instruction {
                             id: "jmp".to_string(),
                             args: vec![Value::Expr(Expr::Variable(to))],
+                            span,
+                            expanded_from: vec![],
                         }),
                     );
                 }
                 ItemInner::Meta(Meta::Static(k, v)) => {
-                    if self.statics.contains_key(&k) {
-                        bail!("Error: attempted to define {k} twice");
+                    let name = declare!(k, Value);
+                    if self.is_static(&name) {
+                        error!("Attempted to define {k} twice");
+                    }
+                    match v {
+                        Expr::Literal(v) => {
+                            self.statics.insert(name, v);
+                        }
+                        v => {
+                            self.static_exprs.insert(name, (v, span));
+                        }
                     }
-                    self.statics.insert(k, v);
                 }
-                ItemInner::Meta(Meta::Dyn(k, v)) => {
-                    if self.ram_locations.contains_key(&k) {
-                        bail!("Error: attempted to set #[dyn] {k:#?} twice");
+                ItemInner::Meta(Meta::Dyn(k, v, init)) => {
+                    let name = declare!(k, Value);
+                    let placed = self
+                        .object
+                        .as_ref()
+                        .is_some_and(|o| o.placed.contains_key(&name));
+                    if self.ram_locations.contains_key(&name) || placed {
+                        error!("Attempted to set #[dyn] {k:#?} twice");
+                    }
+                    if self.is_foreign(&span) {
+                        self.add_extern(name);
+                        continue;
+                    }
+                    if let Some(items) = init {
+                        let mut data = vec![];
+                        if let Err(e) = self.expand_constant(&items, &mut data, &span) {
+                            error!("{e:#}");
+                        }
+                        let init = Constant(data);
+                        if init.len() > v {
+                            error!(
+                                "#[dyn({k}: {v})] is initialized with {} bytes, more than it holds",
+                                init.len()
+                            );
+                        }
+                        self.ram_init.insert(name.clone(), (init, span.clone()));
+                    }
+                    if self.place(&name, v) {
+                        continue;
                     }
-                    self.ram_locations
-                        .insert(k, self.ram_length + self.ram_origin);
-                    self.ram_length += v;
+                    match self.allocate(&k, v) {
+                        Ok(var) => {
+                            self.ram_locations.insert(name, var);
+                        }
+                        Err(e) => error!("{e:#}"),
+                    }
+                }
+                ItemInner::Meta(Meta::DynZero) => {
+                    self.ram_zero = Some(span);
                 }
                 ItemInner::Meta(Meta::DynOrigin(v)) => {
-                    self.ram_origin = v;
+                    // Objects leave the default region to the linker script
+                    if self.object.is_some() {
+                        if !self.is_foreign(&span) {
+                            error!("#[dyn(&{v:#06X})] cannot be used in an object, place the ram section with a linker script instead");
+                        }
+                        continue;
+                    }
+                    if let Err(e) = self.set_dyn_origin(v) {
+                        error!("{e:#}");
+                    }
+                }
+                ItemInner::Meta(Meta::DynRegion(name, region)) => {
+                    let result = match region {
+                        Some(region) => self.define_region(name, region),
+                        None => self.select_region(name),
+                    };
+                    if let Err(e) = result {
+                        error!("{e:#}");
+                    }
                 }
                 ItemInner::Meta(Meta::Macro(m)) => {
-                    if self.macros.contains_key(&m.id) {
-                        bail!("Error: attempted to set macro {:#?} twice", m.id);
+                    let name = declare!(m.id, Macro);
+                    if self.macros.contains_key(&name) {
+                        error!("Attempted to set macro {:#?} twice", m.id);
                     }
 
-                    self.macros.insert(m.id.to_string(), m);
+                    self.macros.insert(name, m);
+                }
+                ItemInner::Meta(Meta::Constant(id, items)) => {
+                    let name = declare!(id, Value);
+                    let mut data = vec![];
+                    if let Err(e) = self.expand_constant(&items, &mut data, &span) {
+                        error!("{e:#}");
+                    }
+                    self.tree.push(Node::Constant(name, Constant(data), span));
+                }
+                ItemInner::Meta(Meta::Repeat(repeat)) => {
+                    if let Some((label, at)) = find_label(&repeat.body) {
+                        self.diagnostics.push(Diagnostic::error(
+                            format!("Label {label:#?} can't be in a #[repeat], as every iteration would define it"),
+                            Some(at.clone()),
+                        ));
+                        continue;
+                    }
+                    let count = match self.repeat_count(&repeat, &span) {
+                        Ok(count) => count,
+                        Err(e) => error!("{e:#}"),
+                    };
+                    for i in 0..count {
+                        self.resolve_meta(repeat.iteration(i));
+                        // A #[repeat] in the body went over, and would again
+                        if self.repeated > MAX_REPEAT {
+                            break;
+                        }
+                    }
                 }
-                ItemInner::Meta(Meta::Constant(id, bytes)) => {
-                    self.tree.push(Node::Constant(id, bytes));
+                ItemInner::Meta(Meta::If {
+                    cond,
+                    span,
+                    then,
+                    otherwise,
+                }) => {
+                    let taken = match cond {
+                        Condition::Cfg(name) => self
+                            .qualify_value(&name, self.module_of(&span))
+                            .is_ok_and(|name| self.is_static(&name)),
+                        Condition::Expr(expr) => match self.resolve_in(&expr, &span) {
+                            Ok(value) => value != 0,
+                            Err(e) => {
+                                self.diagnostics
+                                    .push(Diagnostic::error(format!("{e:#}"), Some(span)));
+                                continue;
+                            }
+                        },
+                    };
+                    self.resolve_meta(if taken { then } else { otherwise });
+                }
+                ItemInner::Node(Node::Label(ln, ln_span)) if !ln.starts_with('.') => {
+                    let name = declare!(ln, Value);
+                    self.tree.push(Node::Label(name, ln_span));
                 }
                 ItemInner::Node(n) => self.tree.push(n),
+                ItemInner::Meta(Meta::Pub(_)) => unreachable!("#[pub] is taken off above"),
             }
         }
+    }
+
+    /// Resolve every `#[static]` defined with an expression, now that labels are known.
+    pub(crate) fn resolve_statics(&mut self) {
+        let mut resolved = vec![];
+        let mut linked = vec![];
+        for (name, (_, span)) in self.static_exprs.iter() {
+            match self.resolve_static(name) {
+                Ok(Some(value)) => resolved.push((name.clone(), value)),
+                // Addresses in an object, resolved where they are used
+                Ok(None) => linked.push(name.clone()),
+                Err(e) => self
+                    .diagnostics
+                    .push(Diagnostic::error(format!("{e:#}"), Some(span.clone()))),
+            }
+        }
+
+        self.static_exprs.retain(|name, _| linked.contains(name));
+        self.statics.extend(resolved);
+    }
+
+    /// Expand the `#[repeat]`s of a `#[const]` written at `span`.
+    fn expand_constant(
+        &mut self,
+        items: &[ConstantItem],
+        data: &mut Vec<Data>,
+        span: &Span,
+    ) -> Result<()> {
+        for item in items {
+            match item {
+                ConstantItem::Data(d) => data.push(d.clone()),
+                ConstantItem::Repeat(repeat) => {
+                    for i in 0..self.repeat_count(repeat, span)? {
+                        self.expand_constant(&repeat.iteration(i), data, span)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// How many times to repeat `repeat`, taken from what is left of [MAX_REPEAT].
+    fn repeat_count<T>(&mut self, repeat: &Repeat<T>, span: &Span) -> Result<usize> {
+        let count = self.resolve_in(&repeat.count, span)?;
+        self.repeated = self.repeated.saturating_add(count);
+        if self.repeated > MAX_REPEAT {
+            bail!("Cannot #[repeat] {count} times, #[repeat]s would add up to more than {MAX_REPEAT} iterations");
+        }
+        Ok(count)
+    }
+}
+
+/// The first label in `items`, looking inside `#[if]`s and `#[repeat]`s too.
+fn find_label(items: &[Item]) -> Option<(&str, &Span)> {
+    items.iter().find_map(|item| match &item.item {
+        ItemInner::Node(Node::Label(ln, span)) => Some((ln.as_str(), span)),
+        ItemInner::Meta(Meta::If {
+            then, otherwise, ..
+        }) => find_label(then).or_else(|| find_label(otherwise)),
+        ItemInner::Meta(Meta::Repeat(repeat)) => find_label(&repeat.body),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile, compile_errors};
+    use crate::compiler::Level;
+
+    #[test]
+    fn static_exprs() -> Result<()> {
+        let compiler = compile(
+            r#"
+#[static(VRAM_END: BRAM + SIZE - 1)]
+#[static(SIZE: 0x4000)]
+#[static(TABLE_END: table + 2)]
+#[if(VRAM_END == 0xBFFF)] {
+    mov %a, hi(TABLE_END)
+}
+table:
+"#,
+            Level::Error,
+        )?;
+        assert_eq!(compiler.statics()["VRAM_END"], 0xBFFF);
+        assert_eq!(compiler.statics()["TABLE_END"], 4);
+        assert_eq!(compiler.bin, [0x08, 0]);
+
+        let errors = compile_errors("#[static(A: B + 1)]\n#[static(B: C)]\n#[static(C: A * 2)]");
+        assert_eq!(
+            errors[0].message,
+            "#[static] A is defined in terms of itself: A -> B -> C -> A"
+        );
+        assert_eq!(errors[0].span.as_ref().unwrap().line, 1);
 
         Ok(())
     }
@@ -1,5 +1,16 @@
 use super::Compiler;
 
+mod bank;
+mod dead;
+mod include;
 mod labels;
 mod macros;
 mod meta;
+mod module;
+mod region;
+mod startup;
+
+pub(crate) use macros::is_local_label;
+use module::PRELUDE;
+pub(crate) use module::{Module, Namespace};
+pub(crate) use region::check_reserved;
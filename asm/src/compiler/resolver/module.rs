@@ -0,0 +1,369 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
+
+use super::Compiler;
+use crate::builtin::BUILTIN;
+use crate::compiler::lex::{Data, Expansion, Expr, Macro, Node, PlacementKind, Span, Value};
+use crate::compiler::Diagnostic;
+
+/// The module whose exports every other one can use without `#[use]`ing it.
+pub(super) const PRELUDE: &str = "core";
+
+/// A file, and the names it defines. They are private to it unless they are `pub`.
+#[derive(Debug, Default)]
+pub(crate) struct Module {
+    /// Labels, `#[static]`s, `#[dyn]`s and `#[const]`s, and whether they are `pub`.
+    values: IndexMap<String, bool>,
+    /// Macros, and whether they are `pub`.
+    macros: IndexMap<String, bool>,
+    /// The modules it `#[use]`s, and whether it passes on their exports with `#[pub use]`.
+    uses: IndexMap<String, bool>,
+}
+
+/// Macros are looked up apart from everything else, as they can only be called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Namespace {
+    Value,
+    Macro,
+}
+
+impl Module {
+    fn names(&self, ns: Namespace) -> &IndexMap<String, bool> {
+        match ns {
+            Namespace::Value => &self.values,
+            Namespace::Macro => &self.macros,
+        }
+    }
+}
+
+impl Compiler {
+    /// Load `file` as a module, named after its path if it is builtin (`std::gfx::frame`) or
+    /// after the file otherwise (`draw` for `draw.asm` or `draw/mod.asm`). Files given to
+    /// [Compiler::push] are the program itself, which has no name.
+    pub(crate) fn add_module(&mut self, file: &Path, program: bool) -> Result<String> {
+        let path = file.to_string_lossy();
+        let name = if BUILTIN.contains_key(path.as_ref()) {
+            path.to_string()
+        } else if program {
+            String::new()
+        } else {
+            let stem = match file.file_stem().and_then(|s| s.to_str()) {
+                Some("mod" | "main") | None => file.parent().and_then(|p| p.file_name()),
+                Some(_) => file.file_stem(),
+            };
+            stem.unwrap_or_default()
+                .to_string_lossy()
+                .chars()
+                .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                .collect()
+        };
+
+        if let Some((other, _)) = self
+            .file_modules
+            .iter()
+            .find(|(other, module)| !name.is_empty() && **module == name && *other != file)
+        {
+            bail!("{file:?} and {other:?} would both be module {name}");
+        }
+        self.file_modules.insert(file.to_path_buf(), name.clone());
+        self.modules.entry(name.clone()).or_default();
+        Ok(name)
+    }
+
+    /// The module the file `span` is in.
+    pub(crate) fn module_of(&self, span: &Span) -> &str {
+        self.file_modules
+            .get(span.file.path.as_ref())
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Add `name` to the module `span` is in, and give its full name.
+    pub(crate) fn declare(
+        &mut self,
+        span: &Span,
+        name: &str,
+        public: bool,
+        ns: Namespace,
+    ) -> Result<String> {
+        if public && name.starts_with('_') {
+            bail!("{name} starts with `_`, so it is private to its module and cannot be #[pub]");
+        }
+        let scope = self.module_of(span).to_string();
+        let module = self.modules.entry(scope.clone()).or_default();
+        let names = match ns {
+            Namespace::Value => &mut module.values,
+            Namespace::Macro => &mut module.macros,
+        };
+        // `#[pub]` comes before the label it exports
+        let public = public || names.get(name).copied().unwrap_or_default();
+        names.insert(name.to_string(), public);
+        Ok(full_name(&scope, name))
+    }
+
+    /// `#[use]` `module` in the module `span` is in.
+    pub(crate) fn add_use(&mut self, span: &Span, module: String, public: bool) {
+        let scope = self.module_of(span).to_string();
+        let uses = &mut self.modules.entry(scope).or_default().uses;
+        let public = public || uses.get(&module).copied().unwrap_or_default();
+        uses.insert(module, public);
+    }
+
+    /// The full name of `name` as seen from the module `scope`: its own names first, then
+    /// what the modules it `#[use]`s export, then the prelude. `None` if it is none of those.
+    pub(crate) fn qualify(&self, name: &str, scope: &str, ns: Namespace) -> Result<Option<String>> {
+        if let Some((path, name)) = name.rsplit_once("::") {
+            let Some(module) = self.find_module(path, scope) else {
+                bail!("No module {path:#?}");
+            };
+            if let Some(public) = self.modules[&module].names(ns).get(name) {
+                if !public && module != scope {
+                    bail!("{name} is private to module {module}");
+                }
+                return Ok(Some(full_name(&module, name)));
+            }
+            return match unique(name, self.exported(&module, name, ns, &mut vec![]))? {
+                Some(found) => Ok(Some(found)),
+                None => bail!("Module {module} has no {name:#?}"),
+            };
+        }
+
+        let module = self.modules.get(scope);
+        if module.is_some_and(|m| m.names(ns).contains_key(name)) {
+            return Ok(Some(full_name(scope, name)));
+        }
+
+        let mut found = vec![];
+        for used in module.iter().flat_map(|m| m.uses.keys()) {
+            for candidate in self.exported(used, name, ns, &mut vec![]) {
+                if !found.contains(&candidate) {
+                    found.push(candidate);
+                }
+            }
+        }
+        if found.is_empty() {
+            found = self.exported(PRELUDE, name, ns, &mut vec![]);
+        }
+        if found.is_empty() && ns == Namespace::Value && self.defines.iter().any(|d| d == name) {
+            return Ok(Some(name.to_string()));
+        }
+        unique(name, found)
+    }
+
+    /// The macro `id` refers to when it is called from the module `span` is in.
+    pub(crate) fn find_macro(&self, id: &str, span: &Span) -> Result<Option<&Macro>> {
+        let id = self.qualify(id, self.module_of(span), Namespace::Macro)?;
+        Ok(id.and_then(|id| self.macros.get(&id)))
+    }
+
+    /// Whether `expansion` is a call of the macro `name` of the prelude, rather than one of
+    /// the same name defined elsewhere.
+    pub(crate) fn is_prelude_macro(&self, expansion: &Expansion, name: &str) -> bool {
+        let id = self.qualify(
+            &expansion.id,
+            self.module_of(&expansion.span),
+            Namespace::Macro,
+        );
+        let prelude = self.qualify(name, PRELUDE, Namespace::Macro);
+        matches!((id, prelude), (Ok(Some(id)), Ok(Some(prelude))) if id == prelude)
+    }
+
+    /// [Compiler::qualify] a label, `#[static]`, `#[dyn]` or `#[const]`. `.sub` labels, `$`
+    /// and macro variables are left as they are. Names that aren't found are taken to be in
+    /// `scope`, so they are reported as unknown once they are resolved.
+    pub(crate) fn qualify_value(&self, name: &str, scope: &str) -> Result<String> {
+        if name == "$" || name.starts_with(['.', '$']) {
+            return Ok(name.to_string());
+        }
+        let (name, sub) = name.split_at(name.find('.').unwrap_or(name.len()));
+        let name = match self.qualify(name, scope, Namespace::Value)? {
+            Some(name) => name,
+            None => full_name(scope, name),
+        };
+        Ok(name + sub)
+    }
+
+    /// [Compiler::qualify_value] every variable of `expr`.
+    pub(crate) fn qualify_expr(&self, expr: &Expr, scope: &str) -> Result<Expr> {
+        expr.try_replace(&|var| self.qualify_value(var, scope).map(Expr::Variable).map(Some))
+    }
+
+    /// Qualify every name used in the tree, now that everything has been defined.
+    pub(crate) fn qualify_tree(&mut self) {
+        let mut tree = std::mem::take(&mut self.tree);
+        for node in tree.iter_mut() {
+            let Some(span) = node.span().cloned() else {
+                continue;
+            };
+            let scope = self.module_of(&span);
+            let qualify = |e: &mut Expr| -> Result<()> {
+                *e = self.qualify_expr(e, scope)?;
+                Ok(())
+            };
+            let qualified = match node {
+                Node::Instruction(inst) => inst.args.iter_mut().try_for_each(|arg| match arg {
+                    Value::Expr(e) => qualify(e),
+                    _ => Ok(()),
+                }),
+                Node::Constant(_, constant, _) => {
+                    constant.0.iter_mut().try_for_each(|data| match data {
+                        Data::Byte(e) | Data::Word(e) => qualify(e),
+                        Data::Bytes(_) => Ok(()),
+                    })
+                }
+                Node::Placement(placement, _) => match &mut placement.kind {
+                    PlacementKind::Org(e) | PlacementKind::Align(e) => {
+                        qualify(e).and_then(|_| qualify(&mut placement.fill))
+                    }
+                    // Resolved when it is read
+                    PlacementKind::Bank(_) => Ok(()),
+                },
+                _ => Ok(()),
+            };
+            if let Err(e) = qualified {
+                self.diagnostics
+                    .push(Diagnostic::error(format!("{e:#}"), Some(span)));
+            }
+        }
+        self.tree = tree;
+    }
+
+    /// Resolve `expr` as written in the module `span` is in.
+    pub(crate) fn resolve_in(&self, expr: &Expr, span: &Span) -> Result<usize> {
+        self.qualify_expr(expr, self.module_of(span))?.resolve(self)
+    }
+
+    /// The full names of what `module` exports as `name`: its own if it is `pub`, or those of
+    /// the modules it `#[pub use]`s.
+    fn exported(
+        &self,
+        module: &str,
+        name: &str,
+        ns: Namespace,
+        seen: &mut Vec<String>,
+    ) -> Vec<String> {
+        if seen.iter().any(|m| m == module) {
+            return vec![];
+        }
+        seen.push(module.to_string());
+        let Some(m) = self.modules.get(module) else {
+            return vec![];
+        };
+        if m.names(ns).get(name) == Some(&true) {
+            return vec![full_name(module, name)];
+        }
+
+        let mut found = vec![];
+        for (used, _) in m.uses.iter().filter(|(_, public)| **public) {
+            for candidate in self.exported(used, name, ns, seen) {
+                if !found.contains(&candidate) {
+                    found.push(candidate);
+                }
+            }
+        }
+        found
+    }
+
+    /// The module `path` refers to from `scope`: either the whole path, or one starting with
+    /// the last part of a module `scope` uses (`frame::clrvram` after
+    /// `#[use(std::gfx::frame)]`).
+    fn find_module(&self, path: &str, scope: &str) -> Option<String> {
+        if self.modules.contains_key(path) {
+            return Some(path.to_string());
+        }
+        let (first, rest) = match path.split_once("::") {
+            Some((first, rest)) => (first, format!("::{rest}")),
+            None => (path, String::new()),
+        };
+        self.modules
+            .get(scope)?
+            .uses
+            .keys()
+            .filter(|used| used.rsplit("::").next() == Some(first))
+            .map(|used| format!("{used}{rest}"))
+            .find(|module| self.modules.contains_key(module))
+    }
+}
+
+/// `name` in `module`, or just `name` in the program itself.
+pub(crate) fn full_name(module: &str, name: &str) -> String {
+    match module {
+        "" => name.to_string(),
+        module => format!("{module}::{name}"),
+    }
+}
+
+/// The only one of `found`, if there is one.
+fn unique(name: &str, found: Vec<String>) -> Result<Option<String>> {
+    match &found[..] {
+        [] => Ok(None),
+        [one] => Ok(Some(one.clone())),
+        _ => bail!(
+            "{name} could be any of {}, use the full path of one",
+            found.join(", ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile_errors_with, compile_with};
+    use crate::compiler::{Level, MemoryFiles};
+
+    #[test]
+    fn modules() -> Result<()> {
+        let files = ["a", "b"]
+            .iter()
+            .zip([2, 3])
+            .fold(MemoryFiles::new(), |files, (a, width)| {
+                files.with(
+                    format!("lib/{a}.asm"),
+                    format!(
+                        "#[pub static(WIDTH: {width})]\n#[static(_SECRET: 1)]\n\
+                     #[pub]\nwait:\nloop:\n    jmp loop\n_helper:\n    ret"
+                    ),
+                )
+            });
+        let using = |src: &str| format!("#[use(\"lib/a\")]\n#[use(\"lib/b\")]\n{src}");
+
+        // Both files and the program have their own `loop` and `WIDTH`
+        let compiler = compile_with(
+            files.clone(),
+            &using("loop:\n    mov %a, a::WIDTH + b::WIDTH\n    jmp a::wait\n    jmp b::wait\n    jmp loop"),
+            Level::Error,
+        )?;
+        assert_eq!(compiler.labels["a::loop"], compiler.labels["a::wait"]);
+        assert!(compiler.labels.contains_key("b::loop"));
+        assert!(compiler.labels.contains_key("loop"));
+        assert_eq!(
+            (compiler.statics["a::WIDTH"], compiler.statics["b::WIDTH"]),
+            (2, 3)
+        );
+        // `mov` comes from the prelude
+        let main = compiler.labels["loop"];
+        assert_eq!(compiler.bin[main + 1], 5);
+
+        for (src, msg) in [
+            (
+                "jmp wait",
+                "wait could be any of a::wait, b::wait, use the full path of one",
+            ),
+            ("jmp a::_helper", "_helper is private to module a"),
+            ("mov %a, b::_SECRET", "_SECRET is private to module b"),
+            ("jmp c::wait", "No module \"c\""),
+            (
+                "#[pub static(_X: 1)]",
+                "_X starts with `_`, so it is private to its module and cannot be #[pub]",
+            ),
+        ] {
+            let errors = compile_errors_with(files.clone(), &using(src));
+            assert_eq!(errors[0].message, msg);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt::Display;
+use std::ops::Range;
+
+use anyhow::{bail, Result};
+
+use super::Compiler;
+use crate::compiler::lex::Region;
+use crate::compiler::{Variable, ROM_LEN};
+
+/// Where the stack starts, up to the pseudo registers.
+const STACK: usize = 0xFC00;
+/// Where the pseudo registers start, up to the end of RAM.
+const PSR: usize = 0xFF00;
+/// Where the selected bank shows up.
+const BANKED: Range<usize> = 0x8000..0xC000;
+/// General purpose RAM, after the banks.
+const GPRAM: usize = 0xC000;
+
+/// The region `#[dyn]`s go in until another is selected. It starts at `GPRAM`, or wherever
+/// `#[dyn(&ADDR)]` says, and ends at the stack.
+const DEFAULT_REGION: &str = "ram";
+
+impl Compiler {
+    /// `#[dyn(region NAME: ...)]`: define a region and allocate in it from now on.
+    pub(crate) fn define_region(&mut self, name: String, region: Region) -> Result<()> {
+        if self.regions.contains_key(&name) {
+            bail!("Attempted to define region {name} twice");
+        }
+        let what = format!(
+            "Region {name} ({:#06X}..{:#06X})",
+            region.start,
+            region.end()
+        );
+        check_ram(&what, region.start..region.end())?;
+        if let Some(bank) = region.bank {
+            if region.start < BANKED.start || region.end() > BANKED.end {
+                bail!(
+                    "{what} is in bank {bank}, but banks are only at {:#06X}..{:#06X}",
+                    BANKED.start,
+                    BANKED.end
+                );
+            }
+        }
+        if let Some((other, used)) =
+            self.overlapping(&name, region.start..region.end(), region.bank)
+        {
+            bail!(
+                "{what} overlaps region {other} ({:#06X}..{:#06X})",
+                used.start,
+                used.end
+            );
+        }
+
+        self.regions.insert(name.clone(), (region, region.start));
+        self.region = name;
+        Ok(())
+    }
+
+    /// `#[dyn(region NAME)]`: allocate in a region defined before.
+    pub(crate) fn select_region(&mut self, name: String) -> Result<()> {
+        // In an object, the linker places the regions that aren't defined
+        if !self.regions.contains_key(&name) && name != DEFAULT_REGION && self.object.is_none() {
+            bail!("No region {name:#?}, define it with #[dyn(region {name}: &ADDR, LEN)]");
+        }
+        self.region = name;
+        Ok(())
+    }
+
+    /// `#[dyn(&ADDR)]`: allocate in the default region, starting over at `addr`.
+    pub(crate) fn set_dyn_origin(&mut self, addr: usize) -> Result<()> {
+        check_ram(format!("#[dyn(&{addr:#06X})]"), addr..addr + 1)?;
+        self.regions
+            .insert(DEFAULT_REGION.to_string(), default_region(addr));
+        self.region = DEFAULT_REGION.to_string();
+        Ok(())
+    }
+
+    /// Take `size` bytes of the current region for the `#[dyn]` `name`.
+    pub(crate) fn allocate(&mut self, name: &str, size: usize) -> Result<Variable> {
+        if self.region.is_empty() {
+            self.region = DEFAULT_REGION.to_string();
+        }
+        let (region, next) = self
+            .regions
+            .entry(self.region.clone())
+            .or_insert_with(|| default_region(GPRAM));
+
+        let (region, address) = (*region, *next);
+        let end = address + size;
+
+        if end > region.end() {
+            if self.region == DEFAULT_REGION {
+                bail!(
+                    "#[dyn({name}: {size})] at {address:#06X} runs into the stack at {STACK:#06X}"
+                );
+            }
+            bail!(
+                "#[dyn({name}: {size})] doesn't fit in region {}, which has {} of {} bytes left",
+                self.region,
+                region.end() - address,
+                region.len
+            );
+        }
+        if self.region == DEFAULT_REGION {
+            if let Some((other, _)) = self.overlapping(DEFAULT_REGION, address..end, None) {
+                bail!("#[dyn({name}: {size})] at {address:#06X} runs into region {other}");
+            }
+        }
+
+        if let Some((_, next)) = self.regions.get_mut(&self.region) {
+            *next = end;
+        }
+        Ok(Variable {
+            address,
+            size,
+            bank: region.bank,
+        })
+    }
+
+    /// In an object, take `size` bytes for the `#[dyn]` `name` in the section of the current
+    /// region, if the linker places it. `false` if the region has an address.
+    pub(crate) fn place(&mut self, name: &str, size: usize) -> bool {
+        let section = match self.region.as_str() {
+            "" => DEFAULT_REGION,
+            region => region,
+        };
+        if self.regions.contains_key(section) {
+            return false;
+        }
+        let Some(object) = &mut self.object else {
+            return false;
+        };
+        let next = object.sections.entry(section.to_string()).or_default();
+        object
+            .placed
+            .insert(name.to_string(), (section.to_string(), *next));
+        *next += size;
+        true
+    }
+
+    /// The first region other than `except` that shares some of `range` in `bank`, and the
+    /// addresses it takes. Only the allocated part of the default region counts, as it takes
+    /// up the rest of RAM otherwise.
+    fn overlapping(
+        &self,
+        except: &str,
+        range: Range<usize>,
+        bank: Option<usize>,
+    ) -> Option<(&str, Range<usize>)> {
+        self.regions
+            .iter()
+            .filter(|(name, (region, _))| {
+                *name != except && region.bank.unwrap_or(0) == bank.unwrap_or(0)
+            })
+            .map(|(name, (region, next))| match name.as_str() {
+                DEFAULT_REGION => (name.as_str(), region.start..*next),
+                _ => (name.as_str(), region.start..region.end()),
+            })
+            .find(|(_, used)| used.start < range.end && range.start < used.end)
+    }
+}
+
+/// The default region starting at `start`, and where the next `#[dyn]` goes in it.
+fn default_region(start: usize) -> (Region, usize) {
+    let region = Region {
+        bank: None,
+        start,
+        len: STACK - start,
+    };
+    (region, start)
+}
+
+/// Fail if `range` isn't RAM #[dyn]s can go in: if it is in ROM, or overlaps the stack or
+/// the pseudo registers.
+fn check_ram(what: impl Display, range: Range<usize>) -> Result<()> {
+    if range.start < ROM_LEN {
+        bail!("{what} is in ROM, which ends at {ROM_LEN:#06X}");
+    }
+    check_reserved(what, range)
+}
+
+/// Fail if `range` overlaps the stack or the pseudo registers.
+pub(crate) fn check_reserved(what: impl Display, range: Range<usize>) -> Result<()> {
+    if range.start < PSR && range.end > STACK {
+        bail!("{what} overlaps the stack at {STACK:#06X}");
+    }
+    if range.end > PSR {
+        bail!("{what} overlaps the pseudo registers at {PSR:#06X}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile, compile_errors};
+    use crate::compiler::Level;
+
+    #[test]
+    fn regions() -> Result<()> {
+        let compiler = compile(
+            "#[dyn(A: 2)]\n#[dyn(region vram: bank 1, &0x8000, 0x4000)]\n#[dyn(B: 0x4000)]\n\
+             #[dyn(region ram)]\n#[dyn(C: 1)]\nmov %a, bank(B)\nmov %b, bank(C)\nlwb %c, B + 1",
+            Level::Error,
+        )?;
+        let vars = &compiler.ram_locations;
+        assert_eq!((vars["A"].address, vars["A"].bank), (0xC000, None));
+        assert_eq!((vars["B"].address, vars["B"].bank), (0x8000, Some(1)));
+        assert_eq!((vars["C"].address, vars["C"].bank), (0xC002, None));
+        // The operands of the two `mov`s
+        assert_eq!((compiler.bin[1], compiler.bin[3]), (1, 0));
+
+        for (src, msg) in [
+            (
+                "#[dyn(region vram: bank 1, &0x8000, 2)]\n#[dyn(A: 3)]",
+                "#[dyn(A: 3)] doesn't fit in region vram, which has 2 of 2 bytes left",
+            ),
+            (
+                "#[dyn(&0xFB00)]\n#[dyn(A: 0x101)]",
+                "#[dyn(A: 257)] at 0xFB00 runs into the stack at 0xFC00",
+            ),
+            (
+                "#[dyn(region io: &0xFE00, 0x10)]",
+                "Region io (0xFE00..0xFE10) overlaps the stack at 0xFC00",
+            ),
+            (
+                "#[dyn(region io: &0xFFF0, 0x10)]",
+                "Region io (0xFFF0..0x10000) overlaps the pseudo registers at 0xFF00",
+            ),
+            (
+                "#[dyn(region r: &0x0010, 0x10)]\n#[dyn(V: 1 = { 7 })]",
+                "Region r (0x0010..0x0020) is in ROM, which ends at 0x8000",
+            ),
+            ("#[dyn(&0x7FFF)]", "#[dyn(&0x7FFF)] is in ROM, which ends at 0x8000"),
+            (
+                "#[dyn(region a: &0xD000, 0x10)]\n#[dyn(region b: &0xD008, 0x10)]",
+                "Region b (0xD008..0xD018) overlaps region a (0xD000..0xD010)",
+            ),
+            (
+                "#[dyn(region a: bank 2, &0xC000, 0x10)]",
+                "Region a (0xC000..0xC010) is in bank 2, but banks are only at 0x8000..0xC000",
+            ),
+            ("#[dyn(region nope)]", "No region \"nope\", define it with #[dyn(region nope: &ADDR, LEN)]"),
+            (
+                "#[dyn(A: 1)]\n#[dyn(region v: bank 1, &0x8000, 1)]\n#[dyn(B: 1)]\nmov %a, bank(A + B)",
+                "bank(A + B) refers to #[dyn]s in different banks",
+            ),
+        ] {
+            let errors = compile_errors(src);
+            assert_eq!(errors[0].message, msg);
+        }
+
+        Ok(())
+    }
+}
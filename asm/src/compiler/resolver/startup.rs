@@ -0,0 +1,164 @@
+use super::{Compiler, Namespace, PRELUDE};
+use crate::compiler::lex::{Constant, Data, Expr, Instruction, Node, Span, Value};
+
+/// Variables next to each other in RAM, set up by the same loop.
+struct Run {
+    bank: usize,
+    address: usize,
+    data: Vec<Data>,
+    len: usize,
+    name: String,
+    span: Span,
+}
+
+impl Compiler {
+    /// Put the routine that copies the initial bytes of `#[dyn]`s to RAM (and zeroes the
+    /// rest, with `#[dyn(zero)]`) in front of everything else, so it runs before `#[main]`.
+    /// The initial bytes are stored at the end of ROM.
+    pub(crate) fn resolve_startup(&mut self) {
+        let mut vars = self
+            .ram_locations
+            .iter()
+            .filter(|(_, var)| var.size > 0)
+            .collect::<Vec<_>>();
+        vars.sort_by_key(|(_, var)| (var.bank.unwrap_or_default(), var.address));
+
+        let mut copies = vec![];
+        let mut zeroes = vec![];
+        for (name, var) in vars {
+            let bank = var.bank.unwrap_or_default();
+            let init = self.ram_init.get(name);
+            let len = init.map(|(init, _)| init.len()).unwrap_or_default();
+            if let Some((init, span)) = init.filter(|_| len > 0) {
+                add(
+                    &mut copies,
+                    Run {
+                        bank,
+                        address: var.address,
+                        data: init.0.clone(),
+                        len,
+                        name: name.clone(),
+                        span: span.clone(),
+                    },
+                );
+            }
+            // Whatever isn't initialized
+            if let Some(span) = self.ram_zero.as_ref().filter(|_| var.size > len) {
+                add(
+                    &mut zeroes,
+                    Run {
+                        bank,
+                        address: var.address + len,
+                        data: vec![],
+                        len: var.size - len,
+                        name: name.clone(),
+                        span: span.clone(),
+                    },
+                );
+            }
+        }
+
+        // Named in full, so that macros of the program with the same names don't replace them
+        let [memcpy, memset, setbank] = ["memcpy", "memset", "setbank"].map(|id| {
+            self.qualify(id, PRELUDE, Namespace::Macro)
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| id.to_string())
+        });
+        let mut startup = vec![];
+        // The builtin RAM is selected when the machine starts
+        let mut selected = 0;
+        let mut last = None;
+        for (id, run) in copies
+            .into_iter()
+            .map(|run| (&memcpy, run))
+            .chain(zeroes.into_iter().map(|run| (&memset, run)))
+        {
+            if run.bank != selected {
+                startup.push(instruction(&setbank, [Expr::Literal(run.bank)], &run.span));
+                selected = run.bank;
+            }
+
+            let from = if id == &memcpy {
+                let name = format!("{}.init", run.name);
+                self.tree.push(Node::Constant(
+                    name.clone(),
+                    Constant(run.data),
+                    run.span.clone(),
+                ));
+                Expr::Variable(name)
+            } else {
+                Expr::Literal(0)
+            };
+            startup.push(instruction(
+                id,
+                [Expr::Literal(run.address), from, Expr::Literal(run.len)],
+                &run.span,
+            ));
+            last = Some(run.span);
+        }
+        if let Some(span) = last.filter(|_| selected != 0) {
+            startup.push(instruction(&setbank, [Expr::Literal(0)], &span));
+        }
+
+        self.tree.splice(0..0, startup);
+    }
+}
+
+/// Add `new` to the last run if it follows it, or start a new one.
+fn add(runs: &mut Vec<Run>, new: Run) {
+    match runs.last_mut() {
+        Some(run) if run.bank == new.bank && run.address + run.len == new.address => {
+            run.data.extend(new.data);
+            run.len += new.len;
+        }
+        _ => runs.push(new),
+    }
+}
+
+fn instruction<const N: usize>(id: &str, args: [Expr; N], span: &Span) -> Node {
+    Node::Instruction(Instruction {
+        id: id.to_string(),
+        args: args.into_iter().map(Value::Expr).collect(),
+        span: span.clone(),
+        expanded_from: vec![],
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compiler::test::{compile, compile_errors};
+    use crate::compiler::Level;
+
+    #[test]
+    fn dyn_init() -> Result<()> {
+        // A, B and the first byte of C are copied together, D on its own
+        let compiler = compile(
+            "#[dyn(A: 1 = { 5 })]\n#[dyn(B: 1 = { 6 })]\n#[dyn(C: 2 = { 7 })]\n#[dyn(D: 1 = { 8 })]",
+            Level::Error,
+        )?;
+        let len = compiler.bin.len();
+        assert_eq!(compiler.bin[len - 4..], [5, 6, 7, 8]);
+        assert_eq!(compiler.labels["A.init"], len - 4);
+        assert_eq!(compiler.labels["D.init"], len - 1);
+        assert!(!compiler.labels.contains_key("B.init"));
+
+        // Not with a macro of the program that has the same name
+        let shadowed = compile(
+            "#[macro] memcpy: {\n    ($a: expr, $b: expr, $c: expr) => {\n        mov %d, 66\n    }\n}\n\
+             #[dyn(A: 1 = { 5 })]\n#[dyn(B: 1 = { 6 })]\n#[dyn(C: 2 = { 7 })]\n#[dyn(D: 1 = { 8 })]",
+            Level::Error,
+        )?;
+        assert_eq!(shadowed.bin, compiler.bin);
+
+        let errors = compile_errors("#[dyn(A: 2 = { 1, 2, 3 })]");
+        assert_eq!(
+            errors[0].message,
+            "#[dyn(A: 2)] is initialized with 3 bytes, more than it holds"
+        );
+
+        Ok(())
+    }
+}
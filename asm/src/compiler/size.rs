@@ -0,0 +1,202 @@
+use std::fmt::Display;
+
+use indexmap::IndexMap;
+
+use super::lex::{Node, Region};
+use super::listing::origin;
+use super::{Compiler, Variable, BANK_LEN, ROM_LEN};
+
+/// How much ROM and RAM a compiled program takes, and what takes it (`asm --size`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Bytes of the fixed ROM.
+    pub rom: usize,
+    /// Bytes of each ROM bank code is placed in, by bank.
+    pub banks: IndexMap<usize, usize>,
+    /// Bytes by the file they were written in. Instructions macros expand to count for the
+    /// file of the call.
+    pub files: IndexMap<String, usize>,
+    /// Bytes from each label or `#[const]` up to the next, in the order they are in ROM.
+    /// What comes before the first is under `""`.
+    pub labels: IndexMap<String, usize>,
+    /// Bytes the calls of each macro expanded to, and how many calls there are.
+    pub macros: IndexMap<String, (usize, usize)>,
+    pub variables: IndexMap<String, Variable>,
+    /// The `#[dyn]` regions, and how many of their bytes are used.
+    pub regions: IndexMap<String, (Region, usize)>,
+    /// Bytes left out as nothing reaches them from `#[main]`.
+    pub eliminated: usize,
+}
+
+impl Compiler {
+    /// What takes up the ROM and RAM of the program, once it is [compiled](Compiler::compile).
+    pub fn size_report(&self) -> SizeReport {
+        let mut report = SizeReport {
+            rom: self.bin.len(),
+            banks: self
+                .rom_banks
+                .iter()
+                .map(|(bank, bin)| (*bank, bin.len()))
+                .collect(),
+            eliminated: self.eliminated.values().sum(),
+            ..Default::default()
+        };
+
+        let mut label = "";
+        let mut calls = IndexMap::new();
+        for emitted in &self.emitted {
+            match &emitted.node {
+                Node::Label(ln, _) if !ln.contains('.') => label = ln,
+                Node::Constant(name, ..) => label = name,
+                _ => {}
+            }
+            *report.labels.entry(label.to_string()).or_default() += emitted.size;
+            if emitted.size == 0 {
+                continue;
+            }
+            if let Some(span) = origin(&emitted.node) {
+                *report.files.entry(span.file.name()).or_default() += emitted.size;
+            }
+            // Counted for what was written, not the macros it calls in turn
+            if let Node::Instruction(inst) = &emitted.node {
+                if let Some(call) = inst.expanded_from.first() {
+                    let at = (&call.span.file.path, call.span.line, call.span.col);
+                    let (bytes, seen) = calls.entry(call.id.clone()).or_insert((0, vec![]));
+                    *bytes += emitted.size;
+                    if !seen.contains(&at) {
+                        seen.push(at);
+                    }
+                }
+            }
+        }
+        report.labels.retain(|_, size| *size > 0);
+        report.macros = calls
+            .into_iter()
+            .map(|(id, (bytes, seen))| (id, (bytes, seen.len())))
+            .collect();
+        report.files.sort_by(|_, a, _, b| b.cmp(a));
+        report.macros.sort_by(|_, (a, _), _, (b, _)| b.cmp(a));
+
+        let mut variables = self.ram_locations.iter().collect::<Vec<_>>();
+        variables.sort_by_key(|(_, var)| (var.bank, var.address));
+        report.variables = variables
+            .into_iter()
+            .map(|(name, var)| (name.clone(), *var))
+            .collect();
+        report.regions = self
+            .regions
+            .iter()
+            .map(|(name, (region, next))| (name.clone(), (*region, next - region.start)))
+            .collect();
+        report
+    }
+}
+
+impl Display for SizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let usage = |used: usize, len: usize| {
+            format!(
+                "{used:>6} of {len:>6} bytes ({:.1}%)",
+                used as f64 * 100.0 / len as f64
+            )
+        };
+        writeln!(f, "ROM      {}", usage(self.rom, ROM_LEN))?;
+        for (bank, used) in &self.banks {
+            writeln!(f, "Bank {bank:<3} {}", usage(*used, BANK_LEN))?;
+        }
+        for (name, (region, used)) in &self.regions {
+            let bank = region
+                .bank
+                .map(|b| format!(" in bank {b}"))
+                .unwrap_or_default();
+            writeln!(
+                f,
+                "RAM      {}  #[dyn(region {name})] at {:#06X}{bank}",
+                usage(*used, region.len),
+                region.start
+            )?;
+        }
+        if self.eliminated > 0 {
+            writeln!(
+                f,
+                "Left out {} bytes nothing reaches from #[main]",
+                self.eliminated
+            )?;
+        }
+
+        writeln!(f, "\nFiles")?;
+        for (file, size) in &self.files {
+            writeln!(f, "{size:>8}  {file}")?;
+        }
+        writeln!(f, "\nLabels")?;
+        for (label, size) in &self.labels {
+            let label = if label.is_empty() { "(start)" } else { label };
+            writeln!(f, "{size:>8}  {label}")?;
+        }
+        writeln!(f, "\nMacros")?;
+        for (id, (size, calls)) in &self.macros {
+            let s = if *calls == 1 { "" } else { "s" };
+            writeln!(f, "{size:>8}  {id} ({calls} call{s})")?;
+        }
+        writeln!(f, "\n#[dyn]s")?;
+        for (name, var) in &self.variables {
+            let bank = var
+                .bank
+                .map(|b| format!(" in bank {b}"))
+                .unwrap_or_default();
+            writeln!(f, "{:>8}  {name} at {:#06X}{bank}", var.size, var.address)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::{Input, MemoryFiles};
+
+    #[test]
+    fn size_report() -> anyhow::Result<()> {
+        let mut compiler = Compiler::builder()
+            .sources(MemoryFiles::new().with("lib.asm", "#[pub]\nf:\n    ret"))
+            .input(Input::Raw(
+                r#"
+#[use("lib")]
+#[dyn(VAR: 2)]
+#[dyn(FLAG: 1)]
+#[main]
+main:
+    call lib::f
+    call lib::f
+    lw %a, DATA
+.loop:
+    jmp .loop
+#[const(DATA)] { 1, 2, 3 }
+"#
+                .to_string(),
+            ))
+            .build()?;
+        compiler.compile()?;
+        let report = compiler.size_report();
+
+        assert_eq!(report.rom, 29);
+        assert_eq!(report.files["raw"], 26);
+        assert_eq!(report.files["lib.asm"], 3);
+        // The `jmp main`, then the files in the order they are placed
+        assert_eq!(
+            report.labels.keys().collect::<Vec<_>>(),
+            ["", "lib::f", "main", "DATA"]
+        );
+        assert_eq!(report.labels["main"], 20);
+        assert_eq!(report.macros["call"], (14, 2));
+        assert_eq!(report.macros["ret"], (3, 1));
+        assert_eq!(report.variables["FLAG"].address, 0xC002);
+        assert_eq!(report.regions["ram"].1, 3);
+
+        let text = report.to_string();
+        assert!(text.starts_with("ROM          29 of  32768 bytes (0.1%)\n"));
+        assert!(text.contains("      14  call (2 calls)\n"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use path_clean::clean;
+
+/// Where the compiler reads the files that are `#[use]`d and included. Builtin modules
+/// don't go through it, so they are always there.
+pub trait SourceProvider: Debug + Send + Sync {
+    /// Whether there is a file at `path` that can be read.
+    fn is_file(&self, path: &Path) -> bool;
+
+    /// The contents of the file at `path`.
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        String::from_utf8(self.read(path)?).with_context(|| format!("{path:?} is not UTF-8"))
+    }
+}
+
+impl Default for Box<dyn SourceProvider> {
+    fn default() -> Self {
+        Box::new(FileSystem)
+    }
+}
+
+/// The real filesystem, with relative paths starting at `$PWD`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystem;
+
+impl SourceProvider for FileSystem {
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        fs::read(path).with_context(|| format!("Failed to read {path:?}"))
+    }
+}
+
+/// Files kept in memory, for running without a filesystem (wasm, tests).
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFiles {
+    files: IndexMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the file `path`, or replace it.
+    pub fn insert(&mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) {
+        self.files.insert(clean(path.as_ref()), content.into());
+    }
+
+    /// [MemoryFiles::insert], for building one up in place.
+    pub fn with(mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) -> Self {
+        self.insert(path, content);
+        self
+    }
+}
+
+impl SourceProvider for MemoryFiles {
+    fn is_file(&self, path: &Path) -> bool {
+        self.files.contains_key(&clean(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        match self.files.get(&clean(path)) {
+            Some(content) => Ok(content.clone()),
+            None => bail!("Failed to read {path:?}"),
+        }
+    }
+}
+
+/// No files at all: only the builtin modules can be `#[use]`d.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuiltinOnly;
+
+impl SourceProvider for BuiltinOnly {
+    fn is_file(&self, _: &Path) -> bool {
+        false
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        bail!("Failed to read {path:?}, only builtin modules are available")
+    }
+}
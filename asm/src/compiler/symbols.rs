@@ -0,0 +1,213 @@
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::Result;
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize};
+
+use super::lex::{Node, Span};
+use super::Compiler;
+
+/// A `#[dyn]` variable: where it lives in RAM and how many bytes it occupies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variable {
+    pub address: usize,
+    pub size: usize,
+    /// The bank to select before using it, if it is in a banked region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank: Option<usize>,
+}
+
+/// A node of the tree and the bytes of [Compiler::bin] it compiled to.
+#[derive(Debug)]
+pub struct Emitted {
+    pub address: usize,
+    pub size: usize,
+    pub node: Node,
+    /// The ROM bank it is in, `None` for the fixed ROM.
+    pub bank: Option<usize>,
+}
+
+/// A position in a source file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub file: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl From<&Span> for Location {
+    fn from(span: &Span) -> Self {
+        Self {
+            file: span.file.name(),
+            line: span.line,
+            col: span.col,
+        }
+    }
+}
+
+/// A macro call that a [LineMapping] was expanded from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MacroLocation {
+    pub name: String,
+    pub location: Location,
+}
+
+/// The source line that produced the native instruction at `address`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineMapping {
+    pub address: usize,
+    pub size: usize,
+    pub location: Location,
+    /// Macro calls that lead to this instruction, outermost first.
+    pub expansion: Vec<MacroLocation>,
+}
+
+/// Everything the compiler knows about the layout of a binary. Written next to it (as JSON)
+/// with `--symbols` so that other tools can make sense of the bytes.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolMap {
+    pub files: Vec<String>,
+    pub labels: IndexMap<String, usize>,
+    pub variables: IndexMap<String, Variable>,
+    pub statics: IndexMap<String, usize>,
+    pub constants: IndexMap<String, Range<usize>>,
+    pub lines: Vec<LineMapping>,
+}
+
+impl SymbolMap {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The label defined at `address`, preferring top-level labels over sub-labels.
+    pub fn label_at(&self, address: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, addr)| **addr == address)
+            .map(|(name, _)| name.as_str())
+            .min_by_key(|name| name.contains('.'))
+    }
+
+    /// The source line of the instruction that covers `address`.
+    pub fn line_at(&self, address: usize) -> Option<&LineMapping> {
+        self.lines
+            .iter()
+            .find(|l| (l.address..l.address + l.size).contains(&address))
+    }
+}
+
+impl Compiler {
+    /// Collect the symbols of the compiled binary.
+    pub fn symbols(&self) -> SymbolMap {
+        let mut constants = IndexMap::new();
+        let mut lines = vec![];
+
+        for emitted in self.emitted.iter() {
+            match &emitted.node {
+                Node::Constant(name, ..) => {
+                    constants.insert(
+                        name.to_string(),
+                        emitted.address..emitted.address + emitted.size,
+                    );
+                }
+                Node::Instruction(inst) => lines.push(LineMapping {
+                    address: emitted.address,
+                    size: emitted.size,
+                    location: Location::from(&inst.span),
+                    expansion: inst
+                        .expanded_from
+                        .iter()
+                        .map(|e| MacroLocation {
+                            name: e.id.to_string(),
+                            location: Location::from(&e.span),
+                        })
+                        .collect(),
+                }),
+                _ => {}
+            }
+        }
+
+        SymbolMap {
+            files: self
+                .files
+                .iter()
+                .map(|f| path_clean::clean(f.as_path()).display().to_string())
+                .collect::<IndexSet<_>>()
+                .into_iter()
+                .collect(),
+            labels: self.labels.clone(),
+            variables: self.ram_locations.clone(),
+            statics: self.statics.clone(),
+            constants,
+            lines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::compiler::Input;
+
+    #[test]
+    fn collect_symbols() -> Result<()> {
+        let mut compiler = Compiler::new();
+        compiler.push(
+            Input::Raw(
+                r#"
+#[dyn(VAR: 2)]
+#[static(LEN: 3)]
+#[main]
+main:
+    call func
+    jmp main
+func:
+    ret
+#[keep]
+#[const(DATA)] { 1, 2, 3 }
+"#
+                .to_string(),
+            ),
+            Arc::new(PathBuf::from("test")),
+        )?;
+        compiler.compile()?;
+
+        let symbols = compiler.symbols();
+        assert_eq!(symbols.labels["main"], 3);
+        assert_eq!(symbols.labels["func"], 13);
+        assert_eq!(symbols.label_at(13), Some("func"));
+        assert_eq!(symbols.statics["LEN"], 3);
+        assert_eq!(
+            symbols.variables["VAR"],
+            Variable {
+                address: 0xC000,
+                size: 2,
+                bank: None,
+            }
+        );
+        assert_eq!(symbols.constants["DATA"], 16..19);
+
+        // The `jmp` that `call func` expanded to
+        let jmp = symbols.line_at(8).unwrap();
+        assert_eq!(jmp.address, 7);
+        assert_eq!(jmp.location.file, "core::macros::call");
+        assert_eq!(jmp.expansion.len(), 1);
+        assert_eq!(jmp.expansion[0].name, "call");
+        assert_eq!(jmp.expansion[0].location.line, 6);
+
+        let json = serde_json::to_string(&symbols)?;
+        assert_eq!(serde_json::from_str::<SymbolMap>(&json)?, symbols);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,460 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use indexmap::IndexMap;
+
+use crate::compiler::lex::{
+    Expr, ExprOperation, Instruction, Macro, MacroCaptureArgType, Node, Value,
+};
+use crate::compiler::Compiler;
+use crate::op::Operation;
+use crate::reg::Register;
+
+use super::Line;
+
+/// Macros nested deeper than this are assumed to be recursive.
+const MAX_DEPTH: usize = 32;
+
+/// An argument of a native instruction that a macro expands to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Reg(Register),
+    /// A register captured by the macro.
+    RegVar(String),
+    /// An immediate, possibly referring to captured values (`$addr`) or the address (`$`).
+    Imm(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Param {
+    Reg,
+    Imm,
+}
+
+#[derive(Debug, Clone)]
+struct Native {
+    op: Operation,
+    args: Vec<Arg>,
+}
+
+/// The native instructions that one capture of a macro expands to.
+#[derive(Debug, Clone)]
+struct Pattern {
+    id: String,
+    params: Vec<(String, Param)>,
+    body: Vec<Native>,
+}
+
+/// Recognizes the native instructions that macros expand to, so that the disassembler can
+/// print the macro call instead. The patterns are made by expanding every capture of every
+/// macro that the [Compiler] knows about, so user macros fold just like `core` ones.
+#[derive(Debug, Clone)]
+pub struct Folder {
+    patterns: Vec<Pattern>,
+    statics: IndexMap<String, usize>,
+}
+
+impl Folder {
+    pub fn new(compiler: &Compiler) -> Self {
+        let mut patterns = vec![];
+
+        for mac in compiler.macros().values() {
+            for capture in mac.captures.iter() {
+                for params in param_kinds(&capture.args) {
+                    let mut bindings = IndexMap::new();
+                    for ((name, param), arg) in params.iter().zip(capture.args.iter()) {
+                        let value = match param {
+                            Param::Reg => Arg::RegVar(name.to_string()),
+                            Param::Imm => Arg::Imm(Expr::Variable(name.to_string())),
+                        };
+                        bind(&mut bindings, name, arg.ty, value);
+                    }
+
+                    let Some(body) = expand_body(compiler, &capture.content, &bindings, 0) else {
+                        continue;
+                    };
+
+                    // A macro that only repeats its own instruction (`mov $a, $b, $c, $d`)
+                    // would fold every pair of them together.
+                    if body.iter().all(|n| n.op.to_string() == mac.id) {
+                        continue;
+                    }
+
+                    patterns.push(Pattern {
+                        id: mac.id.to_string(),
+                        params,
+                        body,
+                    });
+                }
+            }
+        }
+
+        // Try the longest first, so `ret` wins over `pop $l, $h`, then the most specific so
+        // `halt` wins over `send CTRL, SIGHALT`
+        patterns.sort_by_key(|p| (Reverse(p.body.len()), p.params.len()));
+
+        Self {
+            patterns,
+            statics: compiler.statics(),
+        }
+    }
+
+    /// Replace every sequence of native instructions that a macro expands to with a call
+    /// to it. Sequences that a label points into the middle of are left alone.
+    pub(super) fn fold(
+        &self,
+        lines: Vec<Line>,
+        labels: &BTreeMap<usize, Vec<String>>,
+    ) -> Vec<Line> {
+        let mut out = vec![];
+        let mut i = 0;
+
+        'lines: while i < lines.len() {
+            for pattern in self.patterns.iter() {
+                let Some(candidate) = lines.get(i..i + pattern.body.len()) else {
+                    continue;
+                };
+
+                let mut insts = vec![];
+                for (n, line) in candidate.iter().enumerate() {
+                    match line {
+                        Line::Instruction { address, inst, .. }
+                            if n == 0 || !labels.contains_key(address) =>
+                        {
+                            insts.push((*address, inst))
+                        }
+                        _ => break,
+                    }
+                }
+                if insts.len() != pattern.body.len() {
+                    continue;
+                }
+
+                if let Some(inst) = self.matches(pattern, &insts) {
+                    let bytes = candidate
+                        .iter()
+                        .flat_map(|line| match line {
+                            Line::Instruction { bytes, .. } => bytes.clone(),
+                            _ => vec![],
+                        })
+                        .collect();
+                    out.push(Line::Instruction {
+                        address: insts[0].0,
+                        bytes,
+                        inst,
+                    });
+                    i += pattern.body.len();
+                    continue 'lines;
+                }
+            }
+
+            out.push(lines[i].clone());
+            i += 1;
+        }
+
+        out
+    }
+
+    /// The macro call that `insts` were expanded from, if they match `pattern`.
+    fn matches(&self, pattern: &Pattern, insts: &[(usize, &Instruction)]) -> Option<Instruction> {
+        let mut regs: HashMap<&str, Register> = HashMap::new();
+        let mut imms: HashMap<String, (usize, usize)> = HashMap::new();
+
+        for (native, (address, inst)) in pattern.body.iter().zip(insts) {
+            if inst.id != native.op.to_string() || inst.args.len() != native.args.len() {
+                return None;
+            }
+
+            for (arg, value) in native.args.iter().zip(inst.args.iter()) {
+                let ok = match (arg, value) {
+                    (Arg::Reg(r), Value::Register(v)) => r == v,
+                    (Arg::RegVar(name), Value::Register(v)) => regs.entry(name).or_insert(*v) == v,
+                    (Arg::Imm(e), Value::Literal(v)) => {
+                        self.match_imm(e, *v, 0xFF, *address, &mut imms)
+                    }
+                    (Arg::Imm(e), Value::Expr(Expr::Literal(v))) => {
+                        self.match_imm(e, *v, 0xFFFF, *address, &mut imms)
+                    }
+                    _ => false,
+                };
+                if !ok {
+                    return None;
+                }
+            }
+        }
+
+        let mut args = vec![];
+        for (name, param) in pattern.params.iter() {
+            args.push(match param {
+                Param::Reg => Value::Register(*regs.get(name.as_str())?),
+                Param::Imm => match imms.get(name)? {
+                    (v, 0xFF) => Value::Literal(*v),
+                    (v, 0xFFFF) => Value::Expr(Expr::Literal(*v)),
+                    // Only half of an address was used
+                    _ => return None,
+                },
+            });
+        }
+
+        Some(Instruction {
+            id: pattern.id.to_string(),
+            args,
+            span: Default::default(),
+            expanded_from: vec![],
+        })
+    }
+
+    /// Check that the immediate `value` (of which the bits in `mask` were encoded) could have
+    /// come from `expr`, learning the captured values it refers to.
+    fn match_imm(
+        &self,
+        expr: &Expr,
+        value: usize,
+        mask: usize,
+        address: usize,
+        imms: &mut HashMap<String, (usize, usize)>,
+    ) -> bool {
+        let (name, value, mask) = match expr {
+            Expr::Variable(name) if is_capture(name) => (name, value, mask),
+            Expr::Expr { lhs, op, rhs } => match (lhs.as_ref(), op, rhs.as_ref()) {
+                (Expr::Variable(name), ExprOperation::And, Expr::Literal(0xFF))
+                    if is_capture(name) =>
+                {
+                    (name, value & 0xFF, mask & 0xFF)
+                }
+                (Expr::Variable(name), ExprOperation::Rsh, Expr::Literal(8))
+                    if is_capture(name) =>
+                {
+                    (name, (value << 8) & 0xFF00, (mask << 8) & 0xFF00)
+                }
+                _ => {
+                    return self
+                        .eval(expr, address)
+                        .is_some_and(|e| e & mask == value & mask)
+                }
+            },
+            _ => {
+                return self
+                    .eval(expr, address)
+                    .is_some_and(|e| e & mask == value & mask)
+            }
+        };
+
+        let (known, known_mask) = imms.entry(name.to_string()).or_insert((0, 0));
+        if (*known ^ value) & *known_mask & mask != 0 {
+            return false;
+        }
+        *known |= value & mask;
+        *known_mask |= mask;
+        true
+    }
+
+    /// Resolve an expression that doesn't refer to any captured values.
+    fn eval(&self, expr: &Expr, address: usize) -> Option<usize> {
+        match expr {
+            Expr::Literal(lit) => Some(*lit),
+            Expr::Variable(var) if var == "$" => Some(address),
+            Expr::Variable(var) => self.statics.get(var).copied(),
+            Expr::Unary { op, expr } => Some(op.apply(self.eval(expr, address)?)),
+            Expr::Expr { lhs, op, rhs } => op
+                .apply(self.eval(lhs, address)?, self.eval(rhs, address)?)
+                .ok(),
+        }
+    }
+}
+
+fn is_capture(name: &str) -> bool {
+    name.starts_with('$') && name != "$"
+}
+
+/// Every combination of registers and immediates that `args` could be called with.
+fn param_kinds(args: &[crate::compiler::lex::MacroCaptureArg]) -> Vec<Vec<(String, Param)>> {
+    let mut kinds = vec![vec![]];
+    for arg in args {
+        let options: &[Param] = match arg.ty {
+            MacroCaptureArgType::Register => &[Param::Reg],
+            MacroCaptureArgType::Literal | MacroCaptureArgType::Expr => &[Param::Imm],
+            MacroCaptureArgType::Any => &[Param::Reg, Param::Imm],
+        };
+        kinds = kinds
+            .into_iter()
+            .flat_map(|kind: Vec<(String, Param)>| {
+                options.iter().map(move |p| {
+                    let mut kind = kind.clone();
+                    kind.push((arg.id.to_string(), *p));
+                    kind
+                })
+            })
+            .collect();
+    }
+    kinds
+}
+
+/// Bind a capture the same way that the compiler does when filling a macro, `$addr: expr`
+/// also gets `$addr.l` and `$addr.h`.
+fn bind(bindings: &mut IndexMap<String, Arg>, name: &str, ty: MacroCaptureArgType, value: Arg) {
+    if let (MacroCaptureArgType::Expr, Arg::Imm(e)) = (ty, &value) {
+        let l = ExprOperation::And.to_expr(e.clone(), Expr::Literal(0xFF));
+        let h = ExprOperation::Rsh.to_expr(e.clone(), Expr::Literal(8));
+        bindings.insert(format!("{name}.l"), Arg::Imm(l));
+        bindings.insert(format!("{name}.h"), Arg::Imm(h));
+    }
+    bindings.insert(name.to_string(), value);
+}
+
+fn expand_body(
+    compiler: &Compiler,
+    content: &[Node],
+    bindings: &IndexMap<String, Arg>,
+    depth: usize,
+) -> Option<Vec<Native>> {
+    let mut body = vec![];
+    for node in content {
+        // Bodies with labels jump around inside themselves, they aren't a fixed sequence
+        let Node::Instruction(inst) = node else {
+            return None;
+        };
+        let args = inst
+            .args
+            .iter()
+            .map(|arg| match arg {
+                Value::Register(r) => Some(Arg::Reg(*r)),
+                Value::Literal(lit) => Some(Arg::Imm(Expr::Literal(*lit))),
+                Value::Expr(e) => compiler
+                    .qualify_expr(e, compiler.module_of(&inst.span))
+                    .ok()
+                    .map(Arg::Imm),
+                Value::MacroVariable(var) => bindings.get(var).cloned(),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mac = compiler.find_macro(&inst.id, &inst.span).ok()?;
+        body.append(&mut expand(compiler, mac, &inst.id, args, depth)?);
+    }
+    Some(body)
+}
+
+/// Expand `id` like [Compiler::compile] would: with the first capture of `mac` (the macro
+/// it refers to, if any) that accepts `args`, or as a native instruction if none do.
+fn expand(
+    compiler: &Compiler,
+    mac: Option<&Macro>,
+    id: &str,
+    args: Vec<Arg>,
+    depth: usize,
+) -> Option<Vec<Native>> {
+    use MacroCaptureArgType as MA;
+
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    if let Some(mac) = mac {
+        for capture in mac.captures.iter() {
+            if capture.args.len() != args.len() {
+                continue;
+            }
+
+            let mut bindings = IndexMap::new();
+            let valid = capture.args.iter().zip(args.iter()).all(|(param, arg)| {
+                let accepted = matches!(
+                    (param.ty, arg),
+                    (MA::Register, Arg::Reg(_) | Arg::RegVar(_))
+                        | (MA::Literal | MA::Expr, Arg::Imm(_))
+                        | (MA::Any, _)
+                );
+                if accepted {
+                    bind(&mut bindings, &param.id, param.ty, arg.clone());
+                }
+                accepted
+            });
+
+            if valid {
+                return expand_body(compiler, &capture.content, &bindings, depth + 1);
+            }
+        }
+    }
+
+    let op = Operation::try_from(id).ok()?;
+    let shape = args
+        .iter()
+        .map(|arg| match arg {
+            Arg::Imm(_) => Value::Expr(Expr::Literal(0)),
+            _ => Value::Register(Register::A),
+        })
+        .collect::<Vec<_>>();
+    op.check(&shape).ok()?;
+
+    Some(vec![Native { op, args }])
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::compiler::Input;
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn fold_macros() -> Result<()> {
+        let mut compiler = Compiler::new();
+        compiler.push(
+            Input::Raw(
+                r#"
+#[macro] double: {
+    ($r: reg) => {
+        add $r, $r
+    }
+}
+
+#[main]
+main:
+    call func
+    jeq main
+    halt
+func:
+    not %a
+    double %b
+    nop
+    mov %c, %c
+    ret
+"#
+                .to_string(),
+            ),
+            Arc::new(PathBuf::from("test")),
+        )?;
+        compiler.compile()?;
+
+        let folder = Folder::new(&compiler);
+        let symbols = compiler.symbols();
+
+        let disassembly = disassemble(&compiler.bin, Some(&symbols), Some(&folder)).to_string();
+        let code = disassembly
+            .lines()
+            .map(|l| l.split(';').next().unwrap().trim_end())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert_eq!(
+            code,
+            r#"    jmp main
+
+main:
+    call func
+    jeq main
+    halt
+
+func:
+    not %a
+    double %b
+    nop
+    mov %c, %c
+    ret"#
+        );
+
+        Ok(())
+    }
+}
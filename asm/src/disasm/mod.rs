@@ -0,0 +1,382 @@
+#![doc(alias = "disassembler")]
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+
+use crate::compiler::lex::{Expr, Instruction, Value};
+use crate::compiler::{is_local_label, SymbolMap};
+use crate::op::Operation;
+use crate::reg::Register;
+
+mod fold;
+
+pub use fold::*;
+
+/// A line of disassembled output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Label(String),
+    Instruction {
+        address: usize,
+        bytes: Vec<u8>,
+        inst: Instruction,
+    },
+    /// Bytes that are a `#[const]` according to the symbol map, or that don't decode to an
+    /// instruction.
+    Data {
+        address: usize,
+        bytes: Vec<u8>,
+        name: Option<String>,
+    },
+}
+
+/// A binary decoded back into assembly, see [disassemble].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Disassembly(pub Vec<Line>);
+
+/// Decode the native instruction at `address`. Returns the instruction (with every immediate
+/// as a literal) and its size in bytes, or [None] if the bytes there aren't an instruction.
+pub fn decode(bin: &[u8], address: usize) -> Option<(Instruction, usize)> {
+    // XXXXYZZZ: X = operation, Y = immediate, Z = first register
+    let header = *bin.get(address)?;
+    let op = Operation::try_from(header >> 4).ok()?;
+    let imm = header & 0b1000 == 0b1000;
+
+    let (shape, size) = shape(op, imm)?;
+    let bytes = bin.get(address + 1..address + size)?;
+    let mut bytes = bytes.iter().copied();
+    let mut reg_bits = Some(header & 0b111);
+
+    let mut args = vec![];
+    for arg in shape {
+        args.push(match arg {
+            Value::Register(_) => match reg_bits.take() {
+                Some(r) => Value::Register(Register::try_from(r).ok()?),
+                None => Value::Register(Register::try_from(bytes.next()?).ok()?),
+            },
+            _ if is_wide(op) => {
+                let lo = bytes.next()? as usize;
+                let hi = bytes.next()? as usize;
+                Value::Expr(Expr::Literal(hi << 8 | lo))
+            }
+            _ => Value::Literal(bytes.next()? as usize),
+        });
+    }
+
+    let inst = Instruction {
+        id: op.to_string(),
+        args,
+        span: Default::default(),
+        expanded_from: vec![],
+    };
+    Some((inst, size))
+}
+
+/// The kinds of arguments `op` takes, found by asking [Operation::check] which of the
+/// possible shapes it accepts with or without an immediate.
+fn shape(op: Operation, imm: bool) -> Option<(Vec<Value>, usize)> {
+    let r = || Value::Register(Register::A);
+    let i = || Value::Expr(Expr::Literal(0));
+
+    [
+        vec![],
+        vec![r()],
+        vec![i()],
+        vec![r(), r()],
+        vec![r(), i()],
+        vec![i(), r()],
+    ]
+    .into_iter()
+    .find_map(|args| match op.check(&args) {
+        Ok((size, is_imm)) if is_imm == imm => Some((args, size)),
+        _ => None,
+    })
+}
+
+/// Operations whose immediate is a 16 bit address rather than a single byte.
+fn is_wide(op: Operation) -> bool {
+    matches!(
+        op,
+        Operation::LW | Operation::SW | Operation::JNZ | Operation::JMP
+    )
+}
+
+/// Decode `bin` from the start. With `symbols`, labels and `#[const]` data come from the
+/// symbol map and addresses are replaced with the name of what they point to. Any jump target
+/// that isn't named gets a label of the form `L_0123`. With `folder`, native instructions are
+/// folded back into the macro calls they were expanded from.
+pub fn disassemble(
+    bin: &[u8],
+    symbols: Option<&SymbolMap>,
+    folder: Option<&Folder>,
+) -> Disassembly {
+    let mut lines = vec![];
+    let mut address = 0;
+
+    let constants = symbols
+        .iter()
+        .flat_map(|s| s.constants.iter())
+        .map(|(name, range)| (range.start, (name, range.end)))
+        .collect::<HashMap<_, _>>();
+
+    while address < bin.len() {
+        if let Some((name, end)) = constants.get(&address) {
+            let end = (*end).min(bin.len()).max(address + 1);
+            lines.push(Line::Data {
+                address,
+                bytes: bin[address..end].to_vec(),
+                name: Some(name.to_string()),
+            });
+            address = end;
+            continue;
+        }
+
+        match decode(bin, address) {
+            Some((inst, size)) => {
+                lines.push(Line::Instruction {
+                    address,
+                    bytes: bin[address..address + size].to_vec(),
+                    inst,
+                });
+                address += size;
+            }
+            None => {
+                match lines.last_mut() {
+                    Some(Line::Data {
+                        bytes, name: None, ..
+                    }) => bytes.push(bin[address]),
+                    _ => lines.push(Line::Data {
+                        address,
+                        bytes: vec![bin[address]],
+                        name: None,
+                    }),
+                }
+                address += 1;
+            }
+        }
+    }
+
+    // Every name that an address could be replaced with
+    let mut labels: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let mut names: HashMap<usize, String> = HashMap::new();
+    if let Some(symbols) = symbols {
+        // Constants are labels too, but get printed as `#[const]`
+        let labels_only = symbols
+            .labels
+            .iter()
+            .filter(|(name, _)| !symbols.constants.contains_key(*name));
+        for (name, address) in labels_only {
+            labels.entry(*address).or_default().push(name.to_string());
+        }
+        for address in labels.keys() {
+            if let Some(name) = symbols.label_at(*address) {
+                names.insert(*address, name.to_string());
+            }
+        }
+        for (name, var) in symbols.variables.iter() {
+            names.entry(var.address).or_insert_with(|| name.to_string());
+        }
+        for (name, range) in symbols.constants.iter() {
+            names.entry(range.start).or_insert_with(|| name.to_string());
+        }
+    }
+
+    for line in lines.iter() {
+        let Line::Instruction { inst, .. } = line else {
+            continue;
+        };
+        if inst.id != "jmp" && inst.id != "jnz" {
+            continue;
+        }
+        for arg in inst.args.iter() {
+            if let Value::Expr(Expr::Literal(target)) = arg {
+                names.entry(*target).or_insert_with(|| {
+                    let name = format!("L_{target:04X}");
+                    labels.entry(*target).or_default().push(name.clone());
+                    name
+                });
+            }
+        }
+    }
+
+    if let Some(folder) = folder {
+        lines = folder.fold(lines, &labels);
+    }
+
+    for line in lines.iter_mut() {
+        let Line::Instruction { inst, .. } = line else {
+            continue;
+        };
+        for arg in inst.args.iter_mut() {
+            if let Value::Expr(Expr::Literal(target)) = arg {
+                if let Some(name) = names.get(target) {
+                    *arg = Value::Expr(Expr::Variable(name.to_string()));
+                }
+            }
+        }
+    }
+
+    // Put labels before the line at their address
+    let mut out = vec![];
+    for line in lines {
+        let address = match &line {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address,
+            Line::Label(_) => unreachable!(),
+        };
+        if let Some(names) = labels.remove(&address) {
+            out.extend(names.into_iter().map(Line::Label));
+        }
+        out.push(line);
+    }
+
+    Disassembly(out)
+}
+
+impl Display for Disassembly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.0.iter() {
+            match line {
+                Line::Label(name) if is_local_label(name) => writeln!(f, "{name}:")?,
+                Line::Label(name) => match name.find('.') {
+                    Some(i) => writeln!(f, "{}:", &name[i..])?,
+                    None => writeln!(f, "\n{name}:")?,
+                },
+                Line::Instruction {
+                    address,
+                    bytes,
+                    inst,
+                } => writeln!(
+                    f,
+                    "    {:<28} ; {address:04X}: {}",
+                    inst.to_string(),
+                    hex(bytes)
+                )?,
+                Line::Data {
+                    address,
+                    bytes,
+                    name: Some(name),
+                } => {
+                    let bytes = bytes
+                        .iter()
+                        .map(|b| format!("{b:#04X}"))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    writeln!(f, "#[const({name})] {{ {bytes} }} ; {address:04X}")?
+                }
+                Line::Data { address, bytes, .. } => {
+                    writeln!(f, "    ; {address:04X}: {}", hex(bytes))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::compiler::{Compiler, Input};
+
+    fn compile() -> Result<Compiler> {
+        let mut compiler = Compiler::new();
+        compiler.push(
+            Input::Raw(
+                r#"
+#[dyn(VAR: 1)]
+#[main]
+main:
+    mov %a, 3
+.loop:
+    sw VAR, %a
+    dec %a
+    jnz .loop, %a
+    jmp main
+#[keep]
+#[const(DATA)] { 1, 2 }
+"#
+                .to_string(),
+            ),
+            Arc::new(PathBuf::from("test")),
+        )?;
+        compiler.compile()?;
+        Ok(compiler)
+    }
+
+    #[test]
+    fn decode_instructions() {
+        let (inst, size) = decode(&[0x28, 0x03, 0x00], 0).unwrap();
+        assert_eq!((inst.to_string().as_str(), size), ("jmp 3", 3));
+
+        let (inst, size) = decode(&[0x01, 0x02], 0).unwrap();
+        assert_eq!((inst.to_string().as_str(), size), ("mov %b, %c", 2));
+
+        let (inst, size) = decode(&[0x48, 0x00, 0xC0], 0).unwrap();
+        assert_eq!((inst.to_string().as_str(), size), ("sw 0xC000, %a", 3));
+
+        // pop has no immediate variant
+        assert_eq!(decode(&[0x68], 0), None);
+        // Truncated
+        assert_eq!(decode(&[0x28, 0x03], 0), None);
+    }
+
+    #[test]
+    fn disassemble_with_symbols() -> Result<()> {
+        let compiler = compile()?;
+        let symbols = compiler.symbols();
+
+        assert_eq!(
+            disassemble(&compiler.bin, Some(&symbols), None).to_string(),
+            r#"    jmp main                     ; 0000: 28 03 00
+
+main:
+    mov %a, 3                    ; 0003: 08 03
+.loop:
+    sw VAR, %a                   ; 0005: 48 00 C0
+    and %f, 7                    ; 0008: CF 07
+    sbb %a, 1                    ; 000A: A8 01
+    jnz main.loop, %a            ; 000C: 18 05 00
+    jmp main                     ; 000F: 28 03 00
+#[const(DATA)] { 0x01, 0x02 } ; 0012
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn disassemble_without_symbols() -> Result<()> {
+        let compiler = compile()?;
+
+        assert_eq!(
+            disassemble(&compiler.bin, None, None).to_string(),
+            r#"    jmp L_0003                   ; 0000: 28 03 00
+
+L_0003:
+    mov %a, 3                    ; 0003: 08 03
+
+L_0005:
+    sw 0xC000, %a                ; 0005: 48 00 C0
+    and %f, 7                    ; 0008: CF 07
+    sbb %a, 1                    ; 000A: A8 01
+    jnz L_0005, %a               ; 000C: 18 05 00
+    jmp L_0003                   ; 000F: 28 03 00
+    mov %b, %c                   ; 0012: 01 02
+"#
+        );
+
+        Ok(())
+    }
+}
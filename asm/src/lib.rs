@@ -6,3 +6,5 @@ pub mod reg;
 pub mod builtin;
 
 pub mod compiler;
+pub mod disasm;
+pub mod link;
@@ -0,0 +1,240 @@
+//! Separate compilation: the objects `asm -c` makes of single files, and the linker
+//! (`cr8-ld`) that puts them together into one binary.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+mod script;
+
+pub use script::*;
+
+/// A file compiled on its own, with the addresses it doesn't know left for the linker.
+/// Written as JSON, like [SymbolMap](crate::compiler::SymbolMap)s.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Object {
+    /// Code and `#[const]`s, as if they started at address 0. Addresses that are only known
+    /// once linked are 0, see [Object::relocations].
+    pub text: Vec<u8>,
+    /// What the address of `text` has to be a multiple of, from its `#[align]`s.
+    pub align: usize,
+    /// Whether it has the `#[main]`, whose jump has to be at the start of ROM.
+    pub entry: bool,
+    /// The bytes each `#[dyn]` section takes, by name. `ram` is the default region, others
+    /// are regions selected with `#[dyn(region NAME)]` but not defined.
+    pub sections: IndexMap<String, usize>,
+    /// Everything it defines, by full name.
+    pub symbols: IndexMap<String, Symbol>,
+    pub relocations: Vec<Relocation>,
+}
+
+/// Where something an [Object] defines is, once its sections are placed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Symbol {
+    pub section: Section,
+    /// The offset in `section`, or the address if it is [Section::Absolute].
+    pub value: usize,
+    /// The bank of an absolute `#[dyn]`, if it is in a banked region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Section {
+    /// Not moved by the linker, like `#[dyn]`s in regions defined with an address.
+    Absolute,
+    /// [Object::text].
+    Text,
+    /// A `#[dyn]` section.
+    Ram(String),
+}
+
+/// Bytes of [Object::text] to fill in with an address once it is known.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Relocation {
+    pub offset: usize,
+    pub kind: RelocationKind,
+    pub target: Target,
+    /// Added to the address of `target`, wrapping around for negative offsets.
+    pub addend: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RelocationKind {
+    /// Both bytes of the address, little endian.
+    Word,
+    /// `lo(...)`, the low byte.
+    Lo,
+    /// `hi(...)`, the high byte.
+    Hi,
+    /// `bank(...)`, the bank of the memory the `#[dyn]` is placed in.
+    Bank,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// The start of one of the object's own sections.
+    Section(Section),
+    /// Something defined by another object.
+    Symbol(String),
+}
+
+impl Object {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+        serde_json::from_str(&content).with_context(|| format!("{path:?} is not an object"))
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        if let Some(dir) = path.as_ref().parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// What [link] makes of the objects.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Linked {
+    /// The contents of `rom`, from its start to the end of the last object.
+    pub bin: Vec<u8>,
+    /// The address every symbol ended up at.
+    pub symbols: IndexMap<String, usize>,
+}
+
+/// Where an object's sections were placed.
+struct Placed {
+    text: usize,
+    sections: IndexMap<String, usize>,
+}
+
+/// Place the `objects` (named for errors) in the memory of `script`, one after the other
+/// with the one that has the `#[main]` first, and fill in the addresses they refer to.
+pub fn link(objects: &[(String, Object)], script: &Script) -> Result<Linked> {
+    let rom = script.memory[ROM];
+
+    let mut order = (0..objects.len()).collect::<Vec<_>>();
+    let entries = objects
+        .iter()
+        .filter(|(_, obj)| obj.entry)
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    if let [_, _, ..] = entries[..] {
+        bail!(
+            "#[main] is set in more than one object: {}",
+            entries.join(", ")
+        );
+    }
+    order.sort_by_key(|i| !objects[*i].1.entry);
+
+    let mut placed = IndexMap::new();
+    let mut pc = rom.start;
+    let mut next = script
+        .memory
+        .iter()
+        .map(|(name, region)| (name.as_str(), region.start))
+        .collect::<IndexMap<_, _>>();
+    for i in order {
+        let (name, obj) = &objects[i];
+        let text = pc.next_multiple_of(obj.align.max(1));
+        pc = text + obj.text.len();
+        if pc > rom.end() {
+            bail!(
+                "{name} reaches {pc:#06X}, past the end of {ROM} at {:#06X}",
+                rom.end()
+            );
+        }
+
+        let mut sections = IndexMap::new();
+        for (section, size) in &obj.sections {
+            let (Some(region), Some(at)) = (script.memory.get(section), next.get_mut(&**section))
+            else {
+                bail!("{name} has #[dyn] section {section}, but there is no memory for it");
+            };
+            if section == ROM {
+                bail!("{name} has #[dyn] section {section}, which is the ROM");
+            }
+            sections.insert(section.clone(), *at);
+            *at += size;
+            if *at > region.end() {
+                bail!(
+                    "{name} needs {size} bytes of {section}, which only has {} left",
+                    region.end() + size - *at
+                );
+            }
+        }
+        placed.insert(i, Placed { text, sections });
+    }
+
+    let mut symbols = IndexMap::new();
+    let mut defined: IndexMap<&str, (&str, Option<usize>)> = IndexMap::new();
+    for (i, (name, obj)) in objects.iter().enumerate() {
+        for (symbol, at) in &obj.symbols {
+            let (address, bank) = locate(&at.section, &placed[&i], script)?;
+            let (address, bank) = (address + at.value, bank.or(at.bank));
+            if let Some((other, _)) = defined.insert(symbol, (name, bank)) {
+                bail!("{symbol} is defined in both {other} and {name}");
+            }
+            symbols.insert(symbol.clone(), address);
+        }
+    }
+
+    let mut bin = vec![0; pc - rom.start];
+    for (i, (name, obj)) in objects.iter().enumerate() {
+        let at = placed[&i].text - rom.start;
+        let text = &mut bin[at..at + obj.text.len()];
+        text.copy_from_slice(&obj.text);
+
+        for reloc in &obj.relocations {
+            let (address, bank) = match &reloc.target {
+                Target::Section(section) => locate(section, &placed[&i], script)?,
+                Target::Symbol(symbol) => match (symbols.get(symbol), defined.get(&**symbol)) {
+                    (Some(address), Some((_, bank))) => (*address, *bank),
+                    _ => bail!("{name} uses {symbol}, which no object defines"),
+                },
+            };
+            let value = address.wrapping_add(reloc.addend);
+            let len = match reloc.kind {
+                RelocationKind::Word => 2,
+                _ => 1,
+            };
+            let Some(bytes) = text.get_mut(reloc.offset..reloc.offset + len) else {
+                bail!("{name} has a relocation past the end of its text");
+            };
+            match reloc.kind {
+                RelocationKind::Word => {
+                    let Ok(word) = u16::try_from(value) else {
+                        bail!("{name} refers to {value:#X}, which does not fit in 16 bits");
+                    };
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+                RelocationKind::Lo => bytes[0] = value as u8,
+                RelocationKind::Hi => bytes[0] = (value >> 8) as u8,
+                RelocationKind::Bank => bytes[0] = bank.unwrap_or_default() as u8,
+            }
+        }
+    }
+
+    Ok(Linked { bin, symbols })
+}
+
+/// The address `section` of an object starts at, and the bank of the memory it is in.
+fn locate(section: &Section, placed: &Placed, script: &Script) -> Result<(usize, Option<usize>)> {
+    Ok(match section {
+        Section::Absolute => (0, None),
+        Section::Text => (placed.text, script.memory[ROM].bank),
+        Section::Ram(name) => match placed.sections.get(name) {
+            Some(start) => (*start, script.memory[name].bank),
+            None => bail!("No #[dyn] section {name} to refer to"),
+        },
+    })
+}
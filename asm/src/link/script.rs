@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+
+use crate::compiler::lex::{expect, ignore_whitespace, ignore_whitespace_noline, Lexable, Region};
+use crate::compiler::{check_reserved, ROM_LEN};
+use crate::token;
+
+/// The memory `rom` is, and where text goes.
+pub const ROM: &str = "rom";
+
+/// Where the linker puts things: one named memory per line, written like a
+/// `#[dyn(region ...)]`.
+///
+/// ```text
+/// ; Code and #[const]s
+/// rom: &0x0000, 0x8000
+/// ; The default #[dyn] section
+/// ram: &0xC000, 0x3C00
+/// vram: bank 1, &0x8000, 0x4000
+/// ```
+///
+/// Text goes in `rom`, and every `#[dyn]` section in the memory it is named after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    pub memory: IndexMap<String, Region>,
+}
+
+impl Default for Script {
+    /// ROM at the start of the address space, and the general purpose RAM up to the stack.
+    fn default() -> Self {
+        Self::parse("rom: &0x0000, 0x8000\nram: &0xC000, 0x3C00").unwrap()
+    }
+}
+
+impl Script {
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut memory = IndexMap::new();
+        let mut buf = ignore_whitespace(content);
+        while !buf.is_empty() {
+            let line = content[..content.len() - buf.len()].lines().count() + 1;
+            let (name, region, rest) =
+                lex_memory(buf).with_context(|| format!("Invalid linker script at line {line}"))?;
+            if memory.insert(name.to_string(), region).is_some() {
+                bail!("Memory {name} is defined twice in the linker script");
+            }
+            buf = ignore_whitespace(rest);
+        }
+        if !memory.contains_key(ROM) {
+            bail!("The linker script has no {ROM} to put the code in");
+        }
+        for (name, region) in memory.iter().filter(|(_, region)| region.bank.is_none()) {
+            let what = format!(
+                "Memory {name} ({:#06X}..{:#06X})",
+                region.start,
+                region.end()
+            );
+            check_reserved(&what, region.start..region.end())?;
+            if name == ROM && region.end() > ROM_LEN {
+                bail!("{what} goes past the end of the {}K ROM", ROM_LEN / 1024);
+            }
+        }
+        Ok(Self { memory })
+    }
+}
+
+/// `NAME: bank N, &ADDR, LEN`
+fn lex_memory(buf: &str) -> Result<(&str, Region, &str)> {
+    let (name, buf) = token!(buf; '_')?;
+    let buf = expect(ignore_whitespace_noline(buf), ":")?;
+    let (region, buf) = Region::lex(ignore_whitespace_noline(buf))?;
+    let buf = ignore_whitespace_noline(buf);
+    if !(buf.is_empty() || buf.starts_with(['\n', '\r', ';'])) {
+        bail!(
+            "Expected the end of the line, found {:#?}",
+            buf.lines().next()
+        );
+    }
+    Ok((name, region, buf))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse() -> Result<()> {
+        let script = Script::parse(
+            "; Memory map\nrom: &0x0000, 0x8000\nram: &0xC000, 0x3C00 ; up to the stack\n\
+             vram: bank 1, &0x8000, 0x4000\n",
+        )?;
+        assert_eq!(script.memory.len(), 3);
+        assert_eq!(
+            script.memory["vram"],
+            Region {
+                bank: Some(1),
+                start: 0x8000,
+                len: 0x4000
+            }
+        );
+        assert_eq!(script.memory[ROM], Script::default().memory[ROM]);
+
+        let err = Script::parse("rom: &0, 0x8000\nram: &0xC000").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid linker script at line 2");
+        assert!(Script::parse("ram: &0xC000, 0x3C00").is_err());
+
+        for (script, msg) in [
+            (
+                "rom: &0, 0x8000\nram: &0xC000, 0x3D00",
+                "Memory ram (0xC000..0xFD00) overlaps the stack at 0xFC00",
+            ),
+            (
+                "rom: &0, 0x8000\npsr: &0xFF00, 0x100",
+                "Memory psr (0xFF00..0x10000) overlaps the pseudo registers at 0xFF00",
+            ),
+            (
+                "rom: &0, 0x9000",
+                "Memory rom (0x0000..0x9000) goes past the end of the 32K ROM",
+            ),
+        ] {
+            assert_eq!(Script::parse(script).unwrap_err().to_string(), msg);
+        }
+
+        Ok(())
+    }
+}
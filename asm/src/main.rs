@@ -2,7 +2,7 @@ use std::env;
 use std::sync::Arc;
 
 use anyhow::Result;
-use asm::compiler::{micro, Compiler, Config};
+use asm::compiler::{micro, Compiler, Config, Level};
 
 use env_logger::Env;
 
@@ -14,28 +14,71 @@ fn main() -> Result<()> {
         .parse_env(Env::new().default_filter_or("asm=info"))
         .init();
 
-    let config = Config::from_argv();
+    let config = Config::from_argv()?;
 
     if config.micro {
-        micro::compile_to_logisim(config.input, config.output)?;
+        let output = config.outputs.into_iter().next().unwrap_or_default();
+        micro::compile_to_logisim(config.input, output)?;
 
         return Ok(());
     }
 
     let mut compiler = Compiler::new();
+    compiler.set_search_path(config.search);
+    if config.warn_truncation {
+        compiler.set_truncation_level(Level::Warning);
+    }
+    if config.optimize {
+        compiler.optimize();
+    }
+    for (name, value) in config.defines {
+        compiler.define(name, value)?;
+    }
+    if config.object {
+        compiler.set_object_mode();
+    }
 
     compiler.push(config.input, Arc::new(env::current_dir().unwrap()))?;
 
-    compiler.compile().map_err(|e| {
-        compiler.debug();
-        e
-    })?;
+    if config.object {
+        let object = compiler.compile_object()?;
+        for diagnostic in compiler.diagnostics() {
+            eprintln!("{diagnostic}");
+        }
+        for output in &config.outputs {
+            object.write(output.path()?)?;
+        }
+        return Ok(());
+    }
+
+    compiler.compile().inspect_err(|_| compiler.debug())?;
+
+    for diagnostic in compiler.diagnostics() {
+        eprintln!("{diagnostic}");
+    }
 
     if config.debug {
         compiler.debug_bin();
     }
 
-    let _ = config.output.write(&compiler.bin);
+    if config.size {
+        print!("{}", compiler.size_report());
+    }
+
+    for output in &config.outputs {
+        output.write(&compiler.bin)?;
+        for (bank, bin) in &compiler.rom_banks {
+            output.write_bank(*bank, bin)?;
+        }
+
+        if config.symbols {
+            output.write_symbols(&compiler.symbols())?;
+        }
+
+        if config.listing {
+            output.write_listing(&compiler.listing())?;
+        }
+    }
 
     Ok(())
 }
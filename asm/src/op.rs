@@ -103,29 +103,61 @@ impl TryFrom<u8> for Operation {
     }
 }
 
+/// An immediate that doesn't fit in the bits it is encoded into, and got truncated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow {
+    /// Index of the argument.
+    pub operand: usize,
+    pub value: usize,
+    pub bits: u32,
+}
+
+impl Overflow {
+    /// Values fit if they are unsigned or (two's complement) negative numbers of `bits`.
+    pub(crate) fn check(operand: usize, value: usize, bits: u32) -> Option<Self> {
+        let fits = value < 1 << bits || (-(1 << (bits - 1))..0).contains(&(value as isize));
+        (!fits).then_some(Self {
+            operand,
+            value,
+            bits,
+        })
+    }
+}
+
 impl Operation {
-    pub fn compile(&self, args: Vec<Value>, ctx: &Compiler) -> Result<Vec<u8>> {
-        let (_, is_imm) = self.check(&args)?;
+    /// Encode the instruction. Immediates that are too large are truncated and reported as
+    /// [Overflow]s.
+    pub fn compile(&self, args: &[Value], ctx: &Compiler) -> Result<(Vec<u8>, Vec<Overflow>)> {
+        let (_, is_imm) = self.check(args)?;
 
         let mut reg_amt = 0;
         let mut reg_bits = 0b000;
         let mut bytes = vec![0];
+        let mut overflows = vec![];
 
-        for arg in args {
+        for (i, arg) in args.iter().enumerate() {
             match arg {
                 Value::Expr(e) => {
-                    let val = e.resolve(ctx)?;
+                    let bits = match self {
+                        Self::LW | Self::SW | Self::JNZ | Self::JMP => 16,
+                        _ => 8,
+                    };
+                    let val = ctx.immediate(e, ctx.bin.len() + bytes.len(), bits)?;
                     bytes.push(val as u8);
-                    if matches!(self, Self::LW | Self::SW | Self::JNZ | Self::JMP) {
+                    if bits == 16 {
                         bytes.push((val >> 8) as u8);
                     }
+                    overflows.extend(Overflow::check(i, val, bits));
+                }
+                Value::Literal(imm) => {
+                    bytes.push(*imm as u8);
+                    overflows.extend(Overflow::check(i, *imm, 8));
                 }
-                Value::Literal(imm) => bytes.push(imm as u8),
                 Value::Register(r) => {
                     if reg_amt > 0 {
-                        bytes.push(r as u8);
+                        bytes.push(*r as u8);
                     } else {
-                        reg_bits = (r as u8) & 0b111;
+                        reg_bits = (*r as u8) & 0b111;
                         reg_amt += 1;
                     }
                 }
@@ -138,7 +170,7 @@ impl Operation {
         if is_imm {
             bytes[0] |= 0b1000;
         }
-        Ok(bytes)
+        Ok((bytes, overflows))
     }
 
     pub fn check(&self, args: &[Value]) -> Result<(usize, bool)> {
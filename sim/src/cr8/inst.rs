@@ -299,7 +299,7 @@ impl CR8 {
     /// MOV: (see README.md)
     fn bank(&mut self, mem: &RwLock<Mem>, is_imm: bool, bytes: [u8; 4]) -> Result<u8> {
         let (val, sz) = match is_imm {
-            false => (bytes[0] & 0b111, 1),
+            false => (self.reg[(bytes[0] & 0b111) as usize], 1),
             true => (bytes[1], 2),
         };
         trace!("{:04x}: BANK {val:02x} | {val:?}", self.pc);
@@ -1,12 +1,13 @@
 mod bank;
 
 use anyhow::{bail, Result};
+use asm::compiler::ROM_LEN;
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
-use bank::{mask, BankCollection, BankId};
+use bank::{mask, Bank, BankCollection, BankId};
 
 const ROM_START: usize = 0x0000;
-const ROM_LEN: usize = 0x8000;
 
 const RAM_LEN: usize = 0x8000;
 pub const BANK_LEN: usize = 0x4000;
@@ -26,6 +27,10 @@ pub struct Mem {
     rom: [u8; ROM_LEN],
     builtin_ram: [u8; RAM_LEN],
     pub banks: BankCollection,
+    /// Read-only banks of code placed with `#[bank]`, by id.
+    rom_banks: BTreeMap<u8, Box<Bank>>,
+    /// Set instead of `selected` while one of the `rom_banks` is selected.
+    selected_rom: Option<u8>,
 }
 
 impl Default for Mem {
@@ -35,6 +40,8 @@ impl Default for Mem {
             builtin_ram: [0; RAM_LEN],
             banks: BankCollection::default(),
             selected: BankId::Builtin,
+            rom_banks: BTreeMap::new(),
+            selected_rom: None,
         }
     }
 }
@@ -50,8 +57,30 @@ impl Mem {
         }
     }
 
-    pub fn select(&mut self, id: impl TryInto<BankId> + Debug + Clone) -> Result<()> {
+    /// Back the bank `id` with a read-only `image`, like a cartridge would.
+    pub fn load_rom_bank(&mut self, id: u8, image: &[u8]) -> Result<()> {
+        if BankId::try_from(id).is_ok() {
+            bail!("Memory bank: {id:#04x} is RAM, it can't be loaded with a ROM image");
+        }
+        if image.len() > BANK_LEN {
+            bail!(
+                "ROM bank {id:#04x} is {} bytes, more than the {BANK_LEN} a bank holds",
+                image.len()
+            );
+        }
+        let mut bank = Box::<Bank>::default();
+        bank.0[..image.len()].copy_from_slice(image);
+        self.rom_banks.insert(id, bank);
+        Ok(())
+    }
+
+    pub fn select(&mut self, id: u8) -> Result<()> {
+        if self.rom_banks.contains_key(&id) {
+            self.selected_rom = Some(id);
+            return Ok(());
+        }
         self.selected = BankId::check(id)?;
+        self.selected_rom = None;
         Ok(())
     }
 
@@ -63,6 +92,9 @@ impl Mem {
             if addr > BANK_END {
                 return Ok(self.builtin_ram[mask(addr)]);
             }
+            if let Some(id) = self.selected_rom {
+                return Ok(self.rom_banks[&id].get(addr).unwrap());
+            }
 
             use BankId as B;
             match self.selected {
@@ -89,6 +121,9 @@ impl Mem {
             if addr > BANK_END {
                 return Ok(&mut self.builtin_ram[mask(addr)]);
             }
+            if let Some(id) = self.selected_rom {
+                bail!("Cannot mutate ROM bank {id:#04x} at {addr:#06x?}");
+            }
 
             use BankId as B;
             match self.selected {
@@ -1,32 +1,22 @@
-use std::env;
-use std::sync::Arc;
-
 use anyhow::Result;
 
-use asm::compiler;
+use asm::compiler::{Build, Compiler, Input};
 
 use super::Runner;
 
 impl Runner {
     pub fn from_argv() -> Result<Self> {
         let (file, tickrate, debug) = Self::read_argv()?;
-        let bin = Self::jit(String::from_utf8(file)?)?;
+        let build = Self::jit(String::from_utf8(file)?)?;
 
-        Ok(Self::new(&bin, tickrate, debug))
+        let mut runner = Self::new(&build.bin, tickrate, debug);
+        for (id, image) in &build.banks {
+            runner.load_rom_bank(*id as u8, image)?;
+        }
+        Ok(runner)
     }
 
-    pub fn jit(file: String) -> Result<Vec<u8>> {
-        let config = compiler::Config {
-            input: compiler::Input::Raw(file),
-            output: compiler::Output::default(),
-            micro: false,
-            debug: false,
-        };
-        let mut compiler = compiler::Compiler::new();
-
-        compiler.push(config.input, Arc::new(env::current_dir().unwrap()))?;
-
-        compiler.compile()?;
-        Ok(compiler.bin)
+    pub fn jit(file: String) -> Result<Build> {
+        Compiler::builder().input(Input::Raw(file)).compile()
     }
 }
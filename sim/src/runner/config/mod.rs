@@ -1,5 +1,6 @@
 use anyhow::{bail, Result};
 use std::fs;
+use std::path::Path;
 use std::{env, time::Duration};
 
 use super::Runner;
@@ -12,7 +13,38 @@ impl Runner {
     pub fn from_argv() -> Result<Self> {
         let (bytes, tickrate, debug) = Self::read_argv()?;
 
-        Ok(Self::new(&bytes, tickrate, debug))
+        let mut runner = Self::new(&bytes, tickrate, debug);
+        for (id, image) in Self::read_rom_banks()? {
+            runner.load_rom_bank(id, &image)?;
+        }
+        Ok(runner)
+    }
+
+    /// The ROM banks `asm` wrote next to the binary given with `-f` (`out.bin` ->
+    /// `out.bank2.bin`).
+    pub fn read_rom_banks() -> Result<Vec<(u8, Vec<u8>)>> {
+        let args: Vec<_> = env::args().collect();
+        let Some(path) = args
+            .iter()
+            .position(|arg| arg == "-f")
+            .and_then(|i| args.get(i + 1))
+        else {
+            return Ok(vec![]);
+        };
+
+        let path = Path::new(path);
+        let mut banks = vec![];
+        for id in 0..=u8::MAX {
+            let extension = match path.extension() {
+                Some(ext) => format!("bank{id}.{}", ext.to_string_lossy()),
+                None => format!("bank{id}"),
+            };
+            let bank = path.with_extension(extension);
+            if bank.is_file() {
+                banks.push((id, fs::read(bank)?));
+            }
+        }
+        Ok(banks)
     }
 
     pub fn read_argv() -> Result<(Vec<u8>, Duration, bool)> {
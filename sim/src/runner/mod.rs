@@ -37,6 +37,14 @@ impl Runner {
         }
     }
 
+    /// Back the bank `id` with the read-only `image` of a `#[bank]`, see [Mem::load_rom_bank].
+    pub fn load_rom_bank(&mut self, id: u8, image: &[u8]) -> Result<()> {
+        self.mem
+            .write()
+            .map_err(|_| anyhow!("Poisoned"))?
+            .load_rom_bank(id, image)
+    }
+
     pub fn debug(&self) -> Result<()> {
         let mem = self.mem.read().map_err(|_| anyhow!("Poisoned"))?;
         let dev = self.devices.read().map_err(|_| anyhow!("Poisoned"))?;
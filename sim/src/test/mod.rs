@@ -1,5 +1,6 @@
 use anyhow::Result;
 
+use asm::compiler::Build;
 use asm::reg::Register;
 
 #[macro_use]
@@ -145,3 +146,295 @@ fn dec() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn dyn_init() -> Result<()> {
+    let runner = util::run(
+        r#"
+#[dyn(zero)]
+#[dyn(A: 2 = { 5, 6 })]
+#[dyn(B: 1)]
+#[dyn(C: 3 = { 'c' })]
+#[dyn(D: 2 = { word(main) })]
+
+#[main]
+main:
+    lw %a, C
+    halt
+"#
+        .to_string(),
+    )?;
+
+    let mem = runner.mem.read().unwrap();
+    let ram = (0xC000..0xC008_usize)
+        .map(|addr| mem.get(addr))
+        .collect::<Result<Vec<_>>>()?;
+    let main = ram[6] as usize | (ram[7] as usize) << 8;
+    assert_eq!(ram[..6], [5, 6, 0, b'c', 0, 0]);
+    assert_ne!(main, 0);
+    assert_eq!(runner.cr8.read().unwrap().reg[Register::A as usize], b'c');
+
+    Ok(())
+}
+
+// VRAM is bank 1, so this needs it connected
+#[test]
+#[cfg(feature = "gfx")]
+fn banked_dyn() -> Result<()> {
+    let runner = util::run(
+        r#"
+#[dyn(region vram: bank 1, &0x8000, 0x100)]
+#[dyn(V: 2 = { 9 })]
+#[dyn(region ram)]
+#[dyn(R: 1 = { 4 })]
+
+#[main]
+main:
+    lwb %a, V
+    mov %b, 3
+    swb V + 1, %b
+    lw %c, R
+    lwb %d, V
+    halt
+"#
+        .to_string(),
+    )?;
+
+    let state = runner.cr8.read().unwrap();
+    // R is read with bank 1 selected, but isn't banked
+    assert_eq!(state.reg[Register::A as usize], 9);
+    assert_eq!(state.reg[Register::C as usize], 4);
+    assert_eq!(state.reg[Register::D as usize], 9);
+
+    let mut mem = runner.mem.write().unwrap();
+    assert_eq!(mem.get(0x8001_usize)?, 3);
+    mem.select(0)?;
+    assert_eq!(mem.get(0x8001_usize)?, 0);
+
+    Ok(())
+}
+
+#[test]
+fn linked() -> Result<()> {
+    use asm::compiler::{BuiltinOnly, Compiler, Input};
+    use asm::link::{link, Script};
+
+    let src = r#"
+#[use(std::math::shift::lsh)]
+#[dyn(X: 1)]
+
+#[main]
+main:
+    mov %a, 0b0011
+    mov %b, 2
+    call lsh
+    sw X, %z
+    lw %c, X
+    halt
+"#;
+    let object = |input| {
+        Compiler::builder()
+            .sources(BuiltinOnly)
+            .input(input)
+            .compile_object()
+    };
+    let main = object(Input::Raw(src.to_string()))?;
+    let lsh = object(Input::File("std::math::shift::lsh".to_string()))?;
+    let linked = link(
+        &[("main.o".into(), main), ("lsh.o".into(), lsh)],
+        &Script::default(),
+    )?;
+
+    let runner = util::run_bin(&linked.bin)?;
+    let state = runner.cr8.read().unwrap();
+    assert_eq!(state.reg[Register::C as usize], 0b1100);
+    // Same as compiling it all at once, apart from the return address
+    // `ret` leaves in %x
+    let whole = util::run(src.to_string())?;
+    let whole = whole.cr8.read().unwrap();
+    for reg in [Register::A, Register::B, Register::C, Register::Z] {
+        assert_eq!(state.reg[reg as usize], whole.reg[reg as usize]);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn far_calls() -> Result<()> {
+    let runner = util::run(
+        r#"
+#[main]
+main:
+    mov %a, 1
+    call two
+    ; The builtin RAM is selected again
+    sw BRAM, %a
+    lw %d, BRAM
+    halt
+
+#[bank(2)]
+two:
+    lw %b, TABLE
+    call three
+    lw %c, TABLE + 1
+    ret
+#[const(TABLE)] { 20, 30 }
+
+#[bank(3)]
+three:
+    add %a, 1
+    ret
+"#
+        .to_string(),
+    )?;
+
+    let state = runner.cr8.read().unwrap();
+    assert_eq!(state.reg[Register::A as usize], 2);
+    assert_eq!(state.reg[Register::B as usize], 20);
+    assert_eq!(state.reg[Register::C as usize], 30);
+    assert_eq!(state.reg[Register::D as usize], 2);
+
+    // Bank 2 is read-only
+    let mut mem = runner.mem.write().unwrap();
+    assert_eq!(mem.get(0x8000_usize)?, 2);
+    mem.select(2)?;
+    assert!(mem.set(0x8000_usize, 0).is_err());
+
+    Ok(())
+}
+
+// The RAM bank selected before a far call is selected again once it returns
+#[test]
+#[cfg(feature = "gfx")]
+fn far_call_from_ram_bank() -> Result<()> {
+    let runner = util::run(
+        r#"
+#[dyn(region vram: bank 1, &0x8000, 0x100)]
+#[dyn(V: 1 = { 9 })]
+
+#[main]
+main:
+    mov %z, 0
+    lwb %a, V
+    call two
+    lw %b, V
+    ; Bank 0 at the address of V
+    setbank 0
+    mov %c, 7
+    sw V, %c
+    mov %c, 1
+    setbank %c
+    call two
+    lw %c, V
+    setbank 0
+    call two
+    lw %d, V
+    halt
+
+#[bank(2)]
+two:
+    add %z, 1
+    ret
+"#
+        .to_string(),
+    )?;
+
+    let state = runner.cr8.read().unwrap();
+    assert_eq!(state.reg[Register::Z as usize], 3);
+    assert_eq!(state.reg[Register::B as usize], 9);
+    assert_eq!(state.reg[Register::C as usize], 9);
+    assert_eq!(state.reg[Register::D as usize], 7);
+
+    Ok(())
+}
+
+/// Programs end up in the same state with `-O`.
+#[test]
+fn peephole() -> Result<()> {
+    let programs = [
+        // Loading twice from the same address, keeping the flags of `cmp`
+        r#"
+#[main]
+main:
+    mov %a, lo(TABLE)
+    mov %b, hi(TABLE)
+    mov %x, %a
+    mov %y, %b
+    lw %c
+    cmp %c, 3
+    mov %x, %a
+    mov %y, %b
+    lw %d
+    adc %d, %c
+    sw BRAM, %f
+    halt
+; Where the code ends doesn't move it
+#[org(0x100)]
+#[const(TABLE)] { 3, 4 }
+"#,
+        r#"
+#[main]
+main:
+    mov %a, 5
+    call twice
+    push %a
+    pop %a
+    jmp .done
+.done:
+    sw BRAM, %a
+    halt
+twice:
+    call double
+    ret
+double:
+    add %a, %a
+    ret
+"#,
+        r#"
+#[use(std)]
+#[main]
+main:
+    mov %a, 7
+    mov %b, 6
+    call mul
+    sw BRAM, %z
+    memcpy BRAM + 1, DATA, 3
+    jmp .end
+.end:
+    halt
+#[org(0x200)]
+#[const(DATA)] { 1, 2, 3 }
+"#,
+    ];
+
+    use Register as R;
+
+    for program in programs {
+        let (plain, optimized) = (
+            util::build(program.to_string(), false)?,
+            util::build(program.to_string(), true)?,
+        );
+        let noted = |build: &Build| {
+            let mut notes = build.diagnostics.iter().map(|d| &d.message);
+            notes.any(|note| note.starts_with("Optimized out"))
+        };
+        assert!(!noted(&plain) && noted(&optimized), "{program}");
+
+        let (plain, optimized) = (util::run_build(&plain)?, util::run_build(&optimized)?);
+        let (a, b) = (plain.cr8.read().unwrap(), optimized.cr8.read().unwrap());
+        // %x and %y are left with return addresses, which move
+        for reg in [R::A, R::B, R::C, R::D, R::Z, R::F] {
+            assert_eq!(
+                a.reg[reg as usize], b.reg[reg as usize],
+                "%{reg} of {program}"
+            );
+        }
+        assert_eq!(a.sp, b.sp);
+        let (a, b) = (plain.mem.read().unwrap(), optimized.mem.read().unwrap());
+        for addr in 0xC000_usize..0xC010 {
+            assert_eq!(a.get(addr)?, b.get(addr)?, "{addr:#06X} of {program}");
+        }
+    }
+
+    Ok(())
+}
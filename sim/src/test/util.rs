@@ -1,9 +1,7 @@
 use anyhow::Result;
-use std::path::PathBuf;
-use std::sync::Arc;
 use std::time::Duration;
 
-use asm::compiler::{Compiler, Input};
+use asm::compiler::{Build, BuiltinOnly, Compiler, Input};
 
 use crate::cr8::CR8;
 use crate::runner::Runner;
@@ -43,15 +41,43 @@ pub fn run_asm(asm: String) -> Result<CR8> {
         halt"#
     );
 
-    let mut compiler = Compiler::new();
-    compiler.push(Input::Raw(asm), Arc::new(PathBuf::from("test")))?;
-    compiler.compile()?;
-    let mut runner = Runner::new(&compiler.bin, Duration::ZERO, false);
+    Ok(run(asm)?.cr8.into_inner().unwrap())
+}
+
+/// Compile and run a whole program until it halts.
+pub fn run(asm: String) -> Result<Runner> {
+    run_build(&build(asm, false)?)
+}
+
+/// Compile a whole program, with the peephole optimizer if `optimize` is set.
+pub fn build(asm: String, optimize: bool) -> Result<Build> {
+    let builder = Compiler::builder().sources(BuiltinOnly);
+    let builder = match optimize {
+        true => builder.optimize(),
+        false => builder,
+    };
+    builder.input(Input::Raw(asm)).compile()
+}
+
+/// Run a compiled program until it halts.
+pub fn run_build(build: &Build) -> Result<Runner> {
+    let mut runner = Runner::new(&build.bin, Duration::ZERO, false);
+    for (id, image) in &build.banks {
+        runner.load_rom_bank(*id as u8, image)?;
+    }
+    run_to_halt(runner)
+}
+
+/// Run a binary until it halts.
+pub fn run_bin(bin: &[u8]) -> Result<Runner> {
+    run_to_halt(Runner::new(bin, Duration::ZERO, false))
+}
 
+fn run_to_halt(mut runner: Runner) -> Result<Runner> {
     loop {
         let (_, should_continue) = runner.cycle()?;
         if !should_continue {
-            break Ok(runner.cr8.into_inner().unwrap());
+            break Ok(runner);
         }
     }
 }
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+pub use image::ImageError;
+use image::RgbImage;
+
+/// How pixels are packed into bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// 1 bit per pixel, 8 pixels per byte with the leftmost in the highest bit. A pixel is
+    /// set if it is brighter than half. Rows are padded to a whole byte.
+    Mono,
+    /// 1 byte per pixel, `0b00RRGGBB`, as the VRAM expects.
+    Rgb222,
+}
+
+/// An image converted to a [Format].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    /// In pixels.
+    pub width: u32,
+    /// In pixels.
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Image {
+    pub fn open(path: impl AsRef<Path>, format: Format) -> Result<Self, ImageError> {
+        let img = image::open(path)?;
+        Ok(Self::convert(&img.to_rgb8(), format))
+    }
+
+    /// [Image::open] an image that has already been read.
+    pub fn decode(bytes: &[u8], format: Format) -> Result<Self, ImageError> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(Self::convert(&img.to_rgb8(), format))
+    }
+
+    pub fn convert(img: &RgbImage, format: Format) -> Self {
+        let bytes = match format {
+            Format::Mono => img
+                .rows()
+                .flat_map(|row| {
+                    let row = row.collect::<Vec<_>>();
+                    row.chunks(8)
+                        .map(|px| {
+                            px.iter().enumerate().fold(0, |byte, (j, p)| {
+                                let [r, g, b] = p.0.map(u16::from);
+                                if (r + g + b) / 3 > 128 {
+                                    byte | (1 << (7 - j))
+                                } else {
+                                    byte
+                                }
+                            })
+                        })
+                        .collect::<Vec<u8>>()
+                })
+                .collect(),
+            Format::Rgb222 => img
+                .pixels()
+                .map(|p| {
+                    let [r, g, b] = p.0.map(|c| c >> 6);
+                    (r << 4) | (g << 2) | b
+                })
+                .collect(),
+        };
+
+        Self {
+            width: img.width(),
+            height: img.height(),
+            bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn convert() {
+        // Alternating white and black, 10 pixels wide
+        let img = RgbImage::from_fn(10, 2, |x, _| {
+            if x % 2 == 0 {
+                Rgb([0xFF, 0xFF, 0xFF])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+
+        let mono = Image::convert(&img, Format::Mono);
+        assert_eq!((mono.width, mono.height), (10, 2));
+        assert_eq!(
+            mono.bytes,
+            [0b1010_1010, 0b1000_0000, 0b1010_1010, 0b1000_0000]
+        );
+
+        let img = RgbImage::from_pixel(2, 1, Rgb([0xFF, 0x80, 0x3F]));
+        let rgb = Image::convert(&img, Format::Rgb222);
+        assert_eq!(rgb.bytes, [0b11_10_00, 0b11_10_00]);
+    }
+}
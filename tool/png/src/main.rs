@@ -1,6 +1,8 @@
 use std::env::args;
 use std::fs;
 
+use png::{Format, Image};
+
 fn main() {
     let args = args().collect::<Vec<_>>();
     let input = args.get(1).expect("Expected input file");
@@ -9,28 +11,16 @@ fn main() {
 
     let name = &input[slindx..dindx].to_ascii_uppercase();
 
-    let img = image::open(input).expect("Failed to open input image");
-    let w = img.width() * 8;
-    let h = img.height() * 8;
-
-    let img = img.to_rgb8();
+    let img = Image::open(input, Format::Mono).expect("Failed to open input image");
+    let w = img.width * 8;
+    let h = img.height * 8;
 
     let mut bytes = String::new();
-    let mut len = 0;
+    let len = img.bytes.len();
 
-    for x in img.chunks_exact(24) {
-        len += 1;
-        let mut byte = 0;
-        for j in 0..8 {
-            let r = x[j * 3] as u16;
-            let g = x[(j * 3) + 1] as u16;
-            let b = x[(j * 3) + 2] as u16;
-            if (r + g + b) / 3 > 128 {
-                byte |= 1 << (7 - j);
-            };
-        }
+    for (i, byte) in img.bytes.iter().enumerate() {
         bytes.push_str(&format!("{:#04X},", byte));
-        if len % 32 == 0 {
+        if (i + 1) % 32 == 0 {
             bytes.push_str("\n    ");
         }
     }